@@ -0,0 +1,49 @@
+struct FullscreenOutput {
+  @builtin(position) position: vec4f,
+  @location(0) uv: vec2f,
+}
+
+@group(0) @binding(0) var source: texture_2d<f32>;
+@group(0) @binding(1) var source_sampler: sampler;
+
+// A single triangle covering the screen
+@vertex
+fn vs_fullscreen(@builtin(vertex_index) index: u32) -> FullscreenOutput {
+    var out: FullscreenOutput;
+    let uv = vec2f(f32((index << 1) & 2), f32(index & 2));
+    out.position = vec4(uv * vec2f(2, -2) + vec2f(-1, 1), 0, 1);
+    out.uv = uv;
+    return out;
+}
+
+fn tap(uv: vec2f, offset: vec2f) -> vec3f {
+    let texel = 1 / vec2f(textureDimensions(source));
+    return textureSample(source, source_sampler, uv + offset * texel).rgb;
+}
+
+// Halves the resolution with the 13 tap filter from Jimenez's "Next
+// Generation Post Processing in Call of Duty", which doesn't shimmer as
+// bright bodies move between texels
+@fragment
+fn fs_downsample(in: FullscreenOutput) -> @location(0) vec4f {
+    let centre = tap(in.uv, vec2f(0, 0));
+    let inner = tap(in.uv, vec2f(-1, -1)) + tap(in.uv, vec2f(1, -1))
+      + tap(in.uv, vec2f(-1, 1)) + tap(in.uv, vec2f(1, 1));
+    let edges = tap(in.uv, vec2f(0, -2)) + tap(in.uv, vec2f(-2, 0))
+      + tap(in.uv, vec2f(2, 0)) + tap(in.uv, vec2f(0, 2));
+    let corners = tap(in.uv, vec2f(-2, -2)) + tap(in.uv, vec2f(2, -2))
+      + tap(in.uv, vec2f(-2, 2)) + tap(in.uv, vec2f(2, 2));
+    return vec4(centre * 0.125 + inner * 0.125 + edges * 0.0625 + corners * 0.03125, 1);
+}
+
+// Doubles the resolution with a 3x3 tent filter, blended additively onto the
+// level above
+@fragment
+fn fs_upsample(in: FullscreenOutput) -> @location(0) vec4f {
+    let centre = tap(in.uv, vec2f(0, 0));
+    let edges = tap(in.uv, vec2f(0, -1)) + tap(in.uv, vec2f(-1, 0))
+      + tap(in.uv, vec2f(1, 0)) + tap(in.uv, vec2f(0, 1));
+    let corners = tap(in.uv, vec2f(-1, -1)) + tap(in.uv, vec2f(1, -1))
+      + tap(in.uv, vec2f(-1, 1)) + tap(in.uv, vec2f(1, 1));
+    return vec4((centre * 4 + edges * 2 + corners) / 16, 1);
+}
@@ -0,0 +1,210 @@
+@group(0) @binding(0) var<storage, read_write> positions: array<vec4f>;
+@group(0) @binding(1) var<storage, read_write> velocities: array<vec4f>;
+@group(0) @binding(2) var<storage, read_write> masses: array<f32>;
+@group(0) @binding(3) var<storage, read_write> species: array<u32>;
+
+struct Simulation {
+  gravitation_const: f32,
+  time_step: f32,
+  body_count: u32,
+  potential_count: u32,
+  box_size: f32,
+  periodic: u32,
+  solver: u32,
+  split_scale: f32,
+  force_scale: f32,
+  velocity_damping: f32,
+  force_param: f32,
+  speed_of_light: f32,
+  post_newtonian_mass_threshold: f32,
+  post_newtonian_enabled: u32,
+  post_newtonian_pair_count: u32,
+  frame_rotation: f32,
+  compensated: u32,
+}
+
+struct Coloring {
+  quantity: u32,
+  log_scale: u32,
+  density_radius: f32,
+  p0_: u32,
+}
+
+@group(1) @binding(0) var<uniform> sim: Simulation;
+
+@group(2) @binding(0) var<uniform> coloring: Coloring;
+// The raw quantity, then normalised to [0, 1] in place
+@group(2) @binding(1) var<storage, read_write> values: array<f32>;
+// Bits of the smallest and largest value. The quantities are never negative,
+// so their bits order the same way as the floats
+@group(2) @binding(2) var<storage, read_write> range: array<atomic<u32>, 2>;
+@group(2) @binding(3) var<storage, read> tags: array<u32>;
+// Spatial hash of the bodies for the local density, laid out like the one in
+// sph.wgsl but with cells the size of the density radius
+@group(2) @binding(4) var<storage, read_write> cell_counts: array<atomic<u32>>;
+@group(2) @binding(5) var<storage, read_write> cell_bodies: array<u32>;
+
+// Must match physics::sph
+const HASH_TABLE_SIZE: u32 = 32768;
+const CELL_CAPACITY: u32 = 32;
+
+// See graphics::coloring::ColorQuantity
+const QUANTITY_SPEED: u32 = 0;
+const QUANTITY_MASS: u32 = 1;
+const QUANTITY_KINETIC_ENERGY: u32 = 2;
+const QUANTITY_LOCAL_DENSITY: u32 = 3;
+const QUANTITY_TAG: u32 = 4;
+const QUANTITY_INDEX: u32 = 5;
+
+fn removed(i: u32) -> bool {
+    return positions[i].w == 0;
+}
+
+// With a periodic boundary the cells are stretched slightly so a whole number
+// of them fit the box
+fn cells_per_box() -> i32 {
+    return max(i32(floor(sim.box_size / coloring.density_radius)), 1);
+}
+
+fn cell_of(p: vec3f) -> vec3i {
+    if sim.periodic != 0 {
+      return vec3i(floor((p / sim.box_size + 0.5) * f32(cells_per_box())));
+    }
+    return vec3i(floor(p / coloring.density_radius));
+}
+
+fn cell_hash(c: vec3i) -> u32 {
+    var cell = c;
+    if sim.periodic != 0 {
+      let n = cells_per_box();
+      cell = (c % n + n) % n;
+    }
+    let h = bitcast<vec3u>(cell) * vec3u(73856093, 19349663, 83492791);
+    return (h.x ^ h.y ^ h.z) % HASH_TABLE_SIZE;
+}
+
+// Each cell must only be summed once, even if the periodic wrap or a hash
+// collision puts it in the search twice
+fn already_visited(hashes: ptr<function, array<u32, 27>>, c: i32) -> bool {
+    for (var p = 0; p < c; p++) {
+      if (*hashes)[p] == (*hashes)[c] {
+        return true;
+      }
+    }
+    return false;
+}
+
+@compute @workgroup_size(64)
+fn insert_bodies(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.x >= sim.body_count || removed(id.x) {
+      return;
+    }
+    let hash = cell_hash(cell_of(positions[id.x].xyz));
+    let slot = atomicAdd(&cell_counts[hash], 1u);
+    if slot < CELL_CAPACITY {
+      cell_bodies[hash * CELL_CAPACITY + slot] = id.x;
+    }
+}
+
+// Mass inside the density radius over the volume it encloses. The radius is a
+// cell wide, so only the 27 cells around the body are searched
+fn local_density(x: u32) -> f32 {
+    let h = coloring.density_radius;
+    let cell = cell_of(positions[x].xyz);
+    var mass = 0.;
+    var visited_hashes: array<u32, 27>;
+    for (var c = 0; c < 27; c++) {
+      let hash = cell_hash(cell + vec3i(c % 3 - 1, (c / 3) % 3 - 1, c / 9 - 1));
+      visited_hashes[c] = hash;
+      if already_visited(&visited_hashes, c) {
+        continue;
+      }
+      let count = atomicLoad(&cell_counts[hash]);
+      let stored = min(count, CELL_CAPACITY);
+      var cell_mass = 0.;
+      for (var i = 0u; i < stored; i++) {
+        let y = cell_bodies[hash * CELL_CAPACITY + i];
+        var r = positions[y].xyz - positions[x].xyz;
+        if sim.periodic != 0 {
+          r -= sim.box_size * round(r / sim.box_size);
+        }
+        if dot(r, r) < h * h {
+          cell_mass += masses[y];
+        }
+      }
+      // A full cell only holds a sample of its bodies, which stands in for
+      // the rest
+      mass += cell_mass * f32(count) / f32(max(stored, 1u));
+    }
+    return mass / (4. / 3. * 3.14159265 * h * h * h);
+}
+
+// The categorical quantities aren't auto ranged
+fn auto_ranged() -> bool {
+    return coloring.quantity != QUANTITY_TAG && coloring.quantity != QUANTITY_INDEX;
+}
+
+@compute @workgroup_size(64)
+fn compute_values(@builtin(global_invocation_id) id: vec3<u32>) {
+    let x = id.x;
+    if x >= sim.body_count {
+      return;
+    }
+    var value = 0.;
+    switch coloring.quantity {
+      case QUANTITY_SPEED: {
+        value = length(velocities[x].xyz);
+      }
+      case QUANTITY_MASS: {
+        value = masses[x];
+      }
+      case QUANTITY_KINETIC_ENERGY: {
+        let v = velocities[x].xyz;
+        value = 0.5 * masses[x] * dot(v, v);
+      }
+      case QUANTITY_LOCAL_DENSITY: {
+        value = local_density(x);
+      }
+      default: {}
+    }
+    value = max(value, 0.);
+    values[x] = value;
+    if removed(x) || !auto_ranged() {
+      return;
+    }
+    // Zero has no logarithm, so it only counts towards the maximum
+    if coloring.log_scale == 0 || value > 0 {
+      atomicMin(&range[0], bitcast<u32>(value));
+    }
+    atomicMax(&range[1], bitcast<u32>(value));
+}
+
+@compute @workgroup_size(64)
+fn normalize_values(@builtin(global_invocation_id) id: vec3<u32>) {
+    let x = id.x;
+    if x >= sim.body_count {
+      return;
+    }
+    if coloring.quantity == QUANTITY_TAG {
+      // Golden ratio steps spread consecutive tags across the colormap
+      values[x] = fract(f32(tags[x]) * 0.618034);
+      return;
+    }
+    if coloring.quantity == QUANTITY_INDEX {
+      values[x] = f32(x) / f32(max(sim.body_count, 2u) - 1);
+      return;
+    }
+    var low = bitcast<f32>(atomicLoad(&range[0]));
+    var high = bitcast<f32>(atomicLoad(&range[1]));
+    var value = values[x];
+    if coloring.log_scale != 0 {
+      low = log(max(low, 1e-30));
+      high = log(max(high, 1e-30));
+      value = log(max(value, 1e-30));
+    }
+    if !(high > low) {
+      values[x] = 0.5;
+      return;
+    }
+    values[x] = clamp((value - low) / (high - low), 0., 1.);
+}
@@ -0,0 +1,59 @@
+// Appended to the shaders that colour by a value, see
+// graphics::coloring::Colormap
+
+const COLORMAP_VIRIDIS: u32 = 1;
+const COLORMAP_INFERNO: u32 = 2;
+const COLORMAP_DIVERGING: u32 = 3;
+
+// Polynomial fits to matplotlib's viridis and inferno, t in [0, 1]
+fn viridis(t: f32) -> vec3f {
+    let c0 = vec3f(0.2777273272234177, 0.005407344544966578, 0.3340998053353061);
+    let c1 = vec3f(0.1050930431085774, 1.404613529898575, 1.384590162594685);
+    let c2 = vec3f(-0.3308618287255563, 0.214847559468213, 0.09509516302823659);
+    let c3 = vec3f(-4.634230498983486, -5.799100973351585, -19.33244095627987);
+    let c4 = vec3f(6.228269936347081, 14.17993336680509, 56.69055260068105);
+    let c5 = vec3f(4.776384997670288, -13.74514537774601, -65.35303263337234);
+    let c6 = vec3f(-5.435455855934631, 4.645852612178535, 26.3124352495832);
+    return c0 + t * (c1 + t * (c2 + t * (c3 + t * (c4 + t * (c5 + t * c6)))));
+}
+
+fn inferno(t: f32) -> vec3f {
+    let c0 = vec3f(0.0002189403691192265, 0.001651004631001012, -0.01948089843709184);
+    let c1 = vec3f(0.1065134194856116, 0.5639564367884091, 3.932712388889277);
+    let c2 = vec3f(11.60249308247187, -3.972853965665698, -15.9423941062914);
+    let c3 = vec3f(-41.70399613139459, 17.43639888205313, 44.35414519872813);
+    let c4 = vec3f(77.162935699427, -33.40235894210092, -81.80730925738993);
+    let c5 = vec3f(-71.31942824499214, 32.62606426397723, 73.20951985803202);
+    let c6 = vec3f(25.13112622477341, -12.24266895238567, -23.07032500287172);
+    return c0 + t * (c1 + t * (c2 + t * (c3 + t * (c4 + t * (c5 + t * c6)))));
+}
+
+// Moreland's cool to warm map, blue through grey to red
+fn diverging(t: f32) -> vec3f {
+    let cool = vec3f(0.230, 0.299, 0.754);
+    let middle = vec3f(0.865, 0.865, 0.865);
+    let warm = vec3f(0.706, 0.016, 0.150);
+    if t < 0.5 {
+      return mix(cool, middle, t * 2);
+    }
+    return mix(middle, warm, t * 2 - 1);
+}
+
+// `map` is one of the constants above, anything else is plain white
+fn colormap(map: u32, t: f32) -> vec3f {
+    let s = clamp(t, 0., 1.);
+    switch map {
+      case COLORMAP_VIRIDIS: {
+        return viridis(s);
+      }
+      case COLORMAP_INFERNO: {
+        return inferno(s);
+      }
+      case COLORMAP_DIVERGING: {
+        return diverging(s);
+      }
+      default: {
+        return vec3f(1);
+      }
+    }
+}
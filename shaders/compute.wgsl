@@ -1,49 +1,322 @@
 @group(0) @binding(0) var<storage, read_write> positions: array<vec4f>;
 @group(0) @binding(1) var<storage, read_write> velocities: array<vec4f>;
 @group(0) @binding(2) var<storage, read_write> masses: array<f32>;
+// Low halves of the double-single positions and the Kahan compensation of the
+// velocities, only touched while sim.compensated is set
+@group(0) @binding(4) var<storage, read_write> position_lo: array<vec4f>;
+@group(0) @binding(5) var<storage, read_write> velocity_compensation: array<vec4f>;
 
-const G: f32 = 6e-3;
-const D: f32 = 0.005;
+struct Simulation {
+  gravitation_const: f32,
+  time_step: f32,
+  body_count: u32,
+  potential_count: u32,
+  box_size: f32,
+  periodic: u32,
+  solver: u32,
+  split_scale: f32,
+  force_scale: f32,
+  velocity_damping: f32,
+  // Softening, screening length or acceleration scale, depending on the
+  // force model
+  force_param: f32,
+  // Zero disables the post newtonian corrections
+  speed_of_light: f32,
+  post_newtonian_mass_threshold: f32,
+  // Non zero when the mass threshold picks out pairs
+  post_newtonian_enabled: u32,
+  post_newtonian_pair_count: u32,
+  // Angular velocity about z, zero unless running in a rotating frame
+  frame_rotation: f32,
+  // Non zero for Kahan summed kicks and double-single positions
+  compensated: u32,
+}
+
+struct Potential {
+  centre: vec4f,
+  axis: vec4f,
+  params: vec4f,
+  kind: u32,
+}
+
+@group(1) @binding(0) var<uniform> sim: Simulation;
+@group(1) @binding(1) var<storage, read> potentials: array<Potential>;
+@group(1) @binding(2) var<storage, read> ewald_table: array<vec4f>;
+// Only filled for the coulomb force model
+@group(1) @binding(3) var<storage, read> charges: array<f32>;
+// Pairs that get the post newtonian correction regardless of their masses
+@group(1) @binding(4) var<storage, read> post_newtonian_pairs: array<vec2u>;
+
+// Velocities from before this step's kicks, which the post newtonian pass reads
+// so no body sees another's velocity half updated
+@group(2) @binding(0) var<storage, read> step_velocities: array<vec4f>;
+
+const EWALD_RESOLUTION: u32 = 24;
+
+const SOLVER_P3M: u32 = 2;
+// Pairs further apart than this many split scales are left to the mesh
+const P3M_CUTOFF: f32 = 4.5;
+
+const POTENTIAL_POINT_MASS: u32 = 0;
+const POTENTIAL_NFW: u32 = 1;
+const POTENTIAL_HERNQUIST: u32 = 2;
+const POTENTIAL_LOGARITHMIC: u32 = 3;
+const POTENTIAL_MIYAMOTO_NAGAI: u32 = 4;
+
+// The gravitational constant for the pairwise forces, scaled for comoving
+// coordinates in an expanding run
+fn gravitation() -> f32 {
+    return sim.gravitation_const * sim.force_scale;
+}
+
+// Acceleration from a single external potential, see
+// physics::potentials::ExternalPotential::acceleration for the cpu version
+fn external_acceleration(p: Potential, position: vec3f) -> vec3f {
+    let G = sim.gravitation_const;
+    let d = p.centre.xyz - position;
+    switch p.kind {
+      case POTENTIAL_POINT_MASS: {
+        let l = inverseSqrt(dot(d, d) + p.params.y * p.params.y);
+        return d * G * p.params.x * l * l * l;
+      }
+      case POTENTIAL_NFW: {
+        let r = length(d);
+        if r == 0 { return vec3f(0); }
+        let enclosed = log(1 + r / p.params.y) - r / (r + p.params.y);
+        return d * G * p.params.x * enclosed / (r * r * r);
+      }
+      case POTENTIAL_HERNQUIST: {
+        let r = length(d);
+        if r == 0 { return vec3f(0); }
+        return d * G * p.params.x / (r * (r + p.params.y) * (r + p.params.y));
+      }
+      case POTENTIAL_LOGARITHMIC: {
+        let z = p.axis.xyz * dot(-d, p.axis.xyz);
+        let R = -d - z;
+        let q2 = p.params.z * p.params.z;
+        let denom = p.params.y * p.params.y + dot(R, R) + dot(z, z) / q2;
+        return -(R + z / q2) * p.params.x * p.params.x / denom;
+      }
+      case POTENTIAL_MIYAMOTO_NAGAI: {
+        let z = p.axis.xyz * dot(-d, p.axis.xyz);
+        let R = -d - z;
+        let zb = sqrt(dot(z, z) + p.params.z * p.params.z);
+        let s = p.params.y + zb;
+        let l = inverseSqrt(dot(R, R) + s * s);
+        return -(R + z * s / zb) * G * p.params.x * l * l * l;
+      }
+      default: {
+        return vec3f(0);
+      }
+    }
+}
+
+// Bodies absorbed by a sink or otherwise taken out of the simulation keep
+// their slot, but have a zero w coordinate
+fn removed(i: u32) -> bool {
+    return positions[i].w == 0;
+}
+
+// Wraps a displacement to the nearest periodic image
+fn minimum_image(r: vec3f) -> vec3f {
+    return r - sim.box_size * round(r / sim.box_size);
+}
+
+// Displacement from x to y. The high halves of nearby bodies cancel exactly,
+// so adding the difference of the low halves keeps their separation precise
+// far from the origin
+fn displacement(x: u32, y: u32) -> vec3f {
+    var r = positions[y].xyz - positions[x].xyz;
+    if sim.compensated != 0 {
+      r += position_lo[y].xyz - position_lo[x].xyz;
+    }
+    if sim.periodic != 0 {
+      r = minimum_image(r);
+    }
+    return r;
+}
+
+// A running sum, with the low order bits lost from it in c
+struct KahanSum {
+  sum: vec3f,
+  c: vec3f,
+}
+
+fn accumulate(k: ptr<function, KahanSum>, value: vec3f) {
+    if sim.compensated == 0 {
+      (*k).sum += value;
+      return;
+    }
+    let y = value - (*k).c;
+    let t = (*k).sum + y;
+    (*k).c = (t - (*k).sum) - y;
+    (*k).sum = t;
+}
+
+fn ewald_index(i: vec3u) -> u32 {
+    let n = EWALD_RESOLUTION + 1;
+    return i.x + n * (i.y + n * i.z);
+}
+
+// Trilinearly interpolates the Ewald correction table, d is the displacement
+// (target - source) in units of the box size, in [-1/2, 1/2]
+fn ewald_correction(d: vec3f) -> vec3f {
+    let u = min(abs(d) * 2 * f32(EWALD_RESOLUTION), vec3f(f32(EWALD_RESOLUTION) - 1e-3));
+    let i = vec3u(floor(u));
+    let t = fract(u);
 
-@compute @workgroup_size(1) fn cs_entry(
+    var c = vec3f(0);
+    for (var corner = 0u; corner < 8; corner++) {
+      let o = vec3u(corner & 1, (corner >> 1) & 1, (corner >> 2) & 1);
+      let w = select(1 - t, t, vec3<bool>(o));
+      c += ewald_table[ewald_index(i + o)].xyz * w.x * w.y * w.z;
+    }
+    // The table only covers the positive octant, the correction is odd in
+    // each coordinate
+    return c * sign(d);
+}
+
+// Complementary error function, see physics::periodic::erfc
+fn erfc(x: f32) -> f32 {
+    let z = abs(x);
+    let t = 1 / (1 + 0.5 * z);
+    let poly = -z * z - 1.26551223 + t * (1.00002368 + t * (0.37409196 + t * (0.09678418
+      + t * (-0.18628806 + t * (0.27886807 + t * (-1.13520398 + t * (1.48851587
+      + t * (-0.82215223 + t * 0.17087277))))))));
+    let ans = t * exp(poly);
+    return select(2 - ans, ans, x >= 0);
+}
+
+// Fraction of the newtonian force left for the direct sum once the mesh has
+// taken the long range part
+fn short_range_factor(r: f32) -> f32 {
+    let rs = sim.split_scale;
+    return erfc(r / (2 * rs)) + r / (rs * sqrt(3.14159265)) * exp(-r * r / (4 * rs * rs));
+}
+
+// Whether the pair is corrected because of the mass threshold
+fn post_newtonian_by_mass(x: u32, y: u32) -> bool {
+    return sim.post_newtonian_enabled != 0
+      && max(masses[x], masses[y]) >= sim.post_newtonian_mass_threshold;
+}
+
+// First post newtonian (EIH) correction to the acceleration of x due to y,
+// keeping only the two body terms, from the velocities the step started with
+fn post_newtonian_acceleration(r: vec3f, x: u32, y: u32) -> vec3f {
+    let d = length(r);
+    // Unit vector from y to x
+    let n = -r / d;
+    let v1 = step_velocities[x].xyz;
+    let v2 = step_velocities[y].xyz;
+    let G = gravitation();
+    let c2 = sim.speed_of_light * sim.speed_of_light;
+    let nv2 = dot(n, v2);
+
+    let radial = -dot(v1, v1) - 2 * dot(v2, v2) + 4 * dot(v1, v2) + 1.5 * nv2 * nv2
+      + 5 * G * masses[x] / d + 4 * G * masses[y] / d;
+    let tangential = 4 * dot(n, v1) - 3 * nv2;
+    return G * masses[y] / (c2 * d * d) * (n * radial + (v1 - v2) * tangential);
+}
+
+// One invocation per body, applies the external potentials and the
+// fictitious forces of a rotating frame
+@compute @workgroup_size(64) fn cs_external(
     @builtin(global_invocation_id) id: vec3<u32>
 ) {
-    var x = id.x;
-    var y = id.y;
+    let x = id.x;
+    if x >= sim.body_count || removed(x) {
+      return;
+    }
+    var a = vec3f(0);
+    for (var i = 0u; i < sim.potential_count; i++) {
+      a += external_acceleration(potentials[i], positions[x].xyz);
+    }
+    var v = velocities[x].xyz;
+    if sim.frame_rotation != 0 {
+      let omega = sim.frame_rotation;
+      // Centrifugal
+      a += omega * omega * vec3(positions[x].xy, 0);
+      // The coriolis force only turns the velocity, so it's applied as an
+      // exact rotation by -2Ω dt rather than a kick that would add energy
+      let angle = -2 * omega * sim.time_step;
+      let c = cos(angle);
+      let s = sin(angle);
+      v = vec3(c * v.x - s * v.y, s * v.x + c * v.y, v.z);
+      if sim.compensated != 0 {
+        let e = velocity_compensation[x].xyz;
+        velocity_compensation[x] = vec4(c * e.x - s * e.y, s * e.x + c * e.y, e.z, 0);
+      }
+    }
+    velocities[x] = vec4(v, 0);
+    kick(x, a * sim.time_step);
+}
+
+// One invocation per body, sums the pairwise acceleration from every other
+// body. pair_acceleration and finish_acceleration come from the force model
+// appended to this file, see physics::force_models
+@compute @workgroup_size(64) fn cs_entry(
+    @builtin(global_invocation_id) id: vec3<u32>
+) {
+    let x = id.x;
+    if x >= sim.body_count || removed(x) {
+      return;
+    }
 
-    if x == y {
-//      positions[x] += velocities[x] * D;
-//      positions[x].w = 1;
+    var a = KahanSum(vec3f(0), vec3f(0));
+    for (var y = 0u; y < sim.body_count; y++) {
+      if y == x || removed(y) {
+        continue;
+      }
+      let r = displacement(x, y);
+      if sim.solver == SOLVER_P3M {
+        let d = length(r);
+        if d > P3M_CUTOFF * sim.split_scale {
+          continue;
+        }
+        // The mesh handles the long range and periodic images
+        accumulate(&a, pair_acceleration(r, x, y) * short_range_factor(d));
+        continue;
+      }
+      accumulate(&a, pair_acceleration(r, x, y));
+      if sim.periodic != 0 {
+        // The table is for a unit box with G = 1, so rescale to the box size
+        let correction = ewald_correction(-r / sim.box_size);
+        accumulate(&a, correction * masses[y] * gravitation() / (sim.box_size * sim.box_size));
+      }
+    }
+
+    kick(x, finish_acceleration(a.sum) * sim.time_step);
+}
 
+// One invocation per body, kicks it by the post newtonian corrections. Only
+// dispatched while they're enabled, after the other kicks of the step
+@compute @workgroup_size(64) fn cs_post_newtonian(
+    @builtin(global_invocation_id) id: vec3<u32>
+) {
+    let x = id.x;
+    if x >= sim.body_count || removed(x) {
       return;
     }
 
-//    if y == 0 {
-//      // This thread is wasted anyways (can't divide by zero), and incrimenting
-//      // Position here garentees that it will be done once per body!
-//      positions[x] += velocities[x] * D;
-//      positions[x].w = 1;
-//      return;
-//    }
-
-//    if y <= x {
-//      // Between 1 and y=x, y is set back one to get every single value in [0,x]
-//      // Then, once y>x, y = y so the y = x edgecase (an object's gravitation 
-//      // on itself)
-//      y -= 1;
-//    }
-
-    // Calc new velocity
-    let m1 = masses[x];
-    let m2 = masses[y];
-    let r = positions[y] - positions[x];
-    // operation is unoptomized...
-    let l = inverseSqrt(r.x * r.x + r.y * r.y + r.z * r.z);
-    let f = m1 * m2 * G * l * l * l  * r;
-    let a = f * D / m1;
-
-    velocities[x] += a;
-    velocities[x].w = 0;
-//    velocities[x].z = 0;
-    
+    var a = vec3f(0);
+    if sim.post_newtonian_enabled != 0 {
+      for (var y = 0u; y < sim.body_count; y++) {
+        if y != x && !removed(y) && post_newtonian_by_mass(x, y) {
+          a += post_newtonian_acceleration(displacement(x, y), x, y);
+        }
+      }
+    }
+    for (var i = 0u; i < sim.post_newtonian_pair_count; i++) {
+      let pair = post_newtonian_pairs[i];
+      if pair.x != x && pair.y != x {
+        continue;
+      }
+      let y = select(pair.x, pair.y, pair.x == x);
+      // Already corrected by the mass threshold
+      if y == x || removed(y) || post_newtonian_by_mass(x, y) {
+        continue;
+      }
+      a += post_newtonian_acceleration(displacement(x, y), x, y);
+    }
+    kick(x, a * sim.time_step);
 }
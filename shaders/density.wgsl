@@ -0,0 +1,165 @@
+struct Uniform {
+  world_mat: mat4x4<f32>,
+  width: u32,
+  height: u32,
+  sprite_size: f32,
+  min_sprite_pixels: f32,
+  projection_scale: vec2f,
+  colormap: u32,
+  depth_mode: u32,
+  log_depth_far: f32,
+  p0_: u32,
+  p1_: u32,
+  p2_: u32,
+}
+
+struct Density {
+  // Maps clip space back to the simulation, for the volume's rays
+  inverse_world_mat: mat4x4<f32>,
+  // World radius of each body's kernel
+  kernel_size: f32,
+  min_kernel_pixels: f32,
+  // Orders of magnitude below the peak that are shown
+  decades: f32,
+  colormap: u32,
+  // Half width of the cube the volume covers, centred on the origin
+  extent: f32,
+  resolution: u32,
+  // Samples along each ray through the volume
+  steps: u32,
+  // Absorption of the densest cell, per cell crossed
+  opacity: f32,
+  // Only used by the grid deposit
+  mass_scale: f32,
+}
+
+struct Peaks {
+  mass: u32,
+  splat: u32,
+  cell: u32,
+}
+
+@group(0) @binding(0) var<uniform> inputs: Uniform;
+
+@group(1) @binding(0) var<uniform> density: Density;
+@group(1) @binding(1) var<storage, read> peaks: Peaks;
+
+@group(2) @binding(0) var splat: texture_2d<f32>;
+@group(2) @binding(1) var<storage, read> grid: array<u32>;
+
+struct SplatOutput {
+  @builtin(position) position: vec4f,
+  @location(0) corner: vec2f,
+  // Mass over the kernel's area in pixels, so wide kernels spread it thinner
+  @location(1) weight: f32,
+}
+
+// A gaussian kernel per body, added into the splat texture
+@vertex
+fn vs_splat(
+    @builtin(vertex_index) index: u32,
+    @location(0) body: vec4f,
+    @location(1) mass: f32,
+) -> SplatOutput {
+    var out: SplatOutput;
+    let corner = vec2f(f32(index & 1), f32(index >> 1)) * 2 - 1;
+    out.corner = corner;
+    let mapped = inputs.world_mat * vec4(body.xyz, 1);
+    let heaviest = bitcast<f32>(peaks.mass);
+    if body.w == 0 || mapped.w <= 0 || heaviest <= 0 {
+      out.position = vec4f(0, 0, 2, 1);
+      return out;
+    }
+    let size = vec2f(f32(inputs.width), f32(inputs.height));
+    let pixels = max(density.kernel_size * inputs.projection_scale.y / mapped.w * size.y / 2, density.min_kernel_pixels);
+    out.weight = max(mass, 0.) / heaviest / (pixels * pixels);
+    let offset = corner * pixels * 2 / size;
+    out.position = vec4(mapped.xy / mapped.w + offset, 0.5, 1);
+    return out;
+}
+
+@fragment
+fn fs_splat(in: SplatOutput) -> @location(0) vec4f {
+    let r2 = dot(in.corner, in.corner);
+    if r2 > 1 {
+      discard;
+    }
+    return vec4f(in.weight * exp(-4 * r2), 0, 0, 0);
+}
+
+@vertex
+fn vs_fullscreen(@builtin(vertex_index) index: u32) -> @builtin(position) vec4f {
+    let uv = vec2f(f32((index << 1) & 2), f32(index & 2));
+    return vec4(uv * vec2f(2, -2) + vec2f(-1, 1), 0, 1);
+}
+
+// Where a value lies on the colormap, the peak at one and `decades` below it
+// at zero
+fn log_scale(value: f32, peak: f32) -> f32 {
+    if value <= 0 || peak <= 0 {
+      return 0.;
+    }
+    return 1 + log(value / peak) / (log(10.) * density.decades);
+}
+
+@fragment
+fn fs_density(@builtin(position) position: vec4f) -> @location(0) vec4f {
+    let value = textureLoad(splat, vec2i(position.xy), 0).r;
+    let t = log_scale(value, bitcast<f32>(peaks.splat));
+    if t <= 0 {
+      return vec4f(0, 0, 0, 1);
+    }
+    return vec4(colormap(density.colormap, t), 1);
+}
+
+fn cell_value(p: vec3f) -> f32 {
+    let n = density.resolution;
+    let u = (p / density.extent * 0.5 + 0.5) * f32(n);
+    let i = vec3u(clamp(vec3i(floor(u)), vec3i(0), vec3i(i32(n) - 1)));
+    return f32(grid[i.x + n * (i.y + n * i.z)]);
+}
+
+// Marches a ray from the camera through the grid, front to back, each cell
+// glowing with its colour and absorbing what's behind it
+@fragment
+fn fs_volume(@builtin(position) position: vec4f) -> @location(0) vec4f {
+    let ndc = vec2f(
+      position.x / f32(inputs.width) * 2 - 1,
+      1 - position.y / f32(inputs.height) * 2,
+    );
+    // The projection is reversed and infinite, one is the near plane and a
+    // half twice as far
+    let near = density.inverse_world_mat * vec4(ndc, 1, 1);
+    let far = density.inverse_world_mat * vec4(ndc, 0.5, 1);
+    let origin = near.xyz / near.w;
+    let direction = normalize(far.xyz / far.w - origin);
+    // Slab test against the cube
+    let inverse = 1 / direction;
+    let a = (vec3f(-density.extent) - origin) * inverse;
+    let b = (vec3f(density.extent) - origin) * inverse;
+    let entry = max(max(max(min(a.x, b.x), min(a.y, b.y)), min(a.z, b.z)), 0.);
+    let exit = min(min(max(a.x, b.x), max(a.y, b.y)), max(a.z, b.z));
+    if exit <= entry {
+      return vec4f(0, 0, 0, 1);
+    }
+
+    let peak = f32(peaks.cell);
+    let step = (exit - entry) / f32(density.steps);
+    let cell = 2 * density.extent / f32(density.resolution);
+    var color = vec3f(0);
+    var transmittance = 1.;
+    for (var i = 0u; i < density.steps; i++) {
+      let p = origin + direction * (entry + (f32(i) + 0.5) * step);
+      let t = log_scale(cell_value(p), peak);
+      if t <= 0 {
+        continue;
+      }
+      let alpha = 1 - exp(-density.opacity * t * step / cell);
+      color += transmittance * alpha * colormap(density.colormap, t);
+      transmittance *= 1 - alpha;
+      if transmittance < 0.01 {
+        break;
+      }
+    }
+    return vec4(color, 1);
+}
@@ -0,0 +1,116 @@
+@group(0) @binding(0) var<storage, read_write> positions: array<vec4f>;
+@group(0) @binding(2) var<storage, read_write> masses: array<f32>;
+
+struct Simulation {
+  gravitation_const: f32,
+  time_step: f32,
+  body_count: u32,
+  potential_count: u32,
+  box_size: f32,
+  periodic: u32,
+  solver: u32,
+  split_scale: f32,
+  force_scale: f32,
+  velocity_damping: f32,
+  force_param: f32,
+  speed_of_light: f32,
+  post_newtonian_mass_threshold: f32,
+  post_newtonian_enabled: u32,
+  post_newtonian_pair_count: u32,
+  frame_rotation: f32,
+  compensated: u32,
+}
+
+
+// Mirrors graphics::density::DensityUniform
+struct Density {
+  inverse_world_mat: mat4x4<f32>,
+  kernel_size: f32,
+  min_kernel_pixels: f32,
+  decades: f32,
+  colormap: u32,
+  extent: f32,
+  resolution: u32,
+  steps: u32,
+  opacity: f32,
+  // Fixed point units of the grid per unit of mass, set from the total mass
+  // so a cell holding all of it still fits
+  mass_scale: f32,
+}
+
+// Bits of the largest value of each, all are positive so their bits order
+// the same way as the floats
+struct Peaks {
+  mass: atomic<u32>,
+  splat: atomic<u32>,
+  // The grid is already fixed point, so this is a plain integer
+  cell: atomic<u32>,
+}
+
+@group(1) @binding(0) var<uniform> sim: Simulation;
+
+@group(2) @binding(0) var<uniform> density: Density;
+// Mass in each cell of the volume in fixed point, as there are no float
+// atomics
+@group(2) @binding(1) var<storage, read_write> grid: array<atomic<u32>>;
+@group(2) @binding(2) var<storage, read_write> peaks: Peaks;
+@group(2) @binding(3) var splat: texture_2d<f32>;
+
+fn removed(i: u32) -> bool {
+    return positions[i].w == 0;
+}
+
+// The splat is scaled by the heaviest body, keeping it in range whatever the
+// mass unit
+@compute @workgroup_size(64)
+fn find_heaviest(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.x >= sim.body_count || removed(id.x) {
+      return;
+    }
+    atomicMax(&peaks.mass, bitcast<u32>(max(masses[id.x], 0.)));
+}
+
+fn cell_index(i: vec3u) -> u32 {
+    return i.x + density.resolution * (i.y + density.resolution * i.z);
+}
+
+@compute @workgroup_size(64)
+fn deposit_mass(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.x >= sim.body_count || removed(id.x) {
+      return;
+    }
+    let n = f32(density.resolution);
+    // Grid units, where cell centres lie on integers
+    let u = (positions[id.x].xyz / density.extent * 0.5 + 0.5) * n - 0.5;
+    let base = vec3i(floor(u));
+    let t = fract(u);
+    for (var corner = 0u; corner < 8; corner++) {
+      let o = vec3u(corner & 1, (corner >> 1) & 1, (corner >> 2) & 1);
+      let cell = base + vec3i(o);
+      // Bodies outside the volume are left out rather than wrapped
+      if any(cell < vec3i(0)) || any(cell >= vec3i(density.resolution)) {
+        continue;
+      }
+      let w = select(1 - t, t, vec3<bool>(o));
+      let m = masses[id.x] * w.x * w.y * w.z * density.mass_scale;
+      atomicAdd(&grid[cell_index(vec3u(cell))], u32(round(m)));
+    }
+}
+
+@compute @workgroup_size(64)
+fn find_cell_peak(@builtin(global_invocation_id) id: vec3<u32>) {
+    let cells = density.resolution * density.resolution * density.resolution;
+    if id.x >= cells {
+      return;
+    }
+    atomicMax(&peaks.cell, atomicLoad(&grid[id.x]));
+}
+
+@compute @workgroup_size(8, 8)
+fn find_splat_peak(@builtin(global_invocation_id) id: vec3<u32>) {
+    if any(id.xy >= textureDimensions(splat)) {
+      return;
+    }
+    let value = max(textureLoad(splat, id.xy, 0).r, 0.);
+    atomicMax(&peaks.splat, bitcast<u32>(value));
+}
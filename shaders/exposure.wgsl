@@ -0,0 +1,60 @@
+struct Post {
+  exposure: f32,
+  bloom_strength: f32,
+  tone_mapping: u32,
+  encode_srgb: u32,
+  // Average luminance of the lit pixels is exposed to this
+  exposure_key: f32,
+  // Fraction of the way the exposure moves to its target each frame
+  adaptation: f32,
+  auto_exposure: u32,
+  // Size of the scene relative to the surface
+  render_scale: f32,
+}
+
+struct Luminance {
+  // Sum of the log luminances in fixed point, see LOG_SCALE
+  log_sum: atomic<i32>,
+  count: atomic<u32>,
+}
+
+@group(0) @binding(0) var hdr: texture_2d<f32>;
+@group(0) @binding(1) var<storage, read_write> luminance: Luminance;
+@group(0) @binding(2) var<storage, read_write> exposure: array<f32>;
+@group(0) @binding(3) var<uniform> post: Post;
+
+// The frame is sampled on a grid this many pixels across
+const SAMPLE_GRID: u32 = 64;
+const LOG_SCALE: f32 = 256;
+
+@compute @workgroup_size(8, 8)
+fn accumulate_luminance(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.x >= SAMPLE_GRID || id.y >= SAMPLE_GRID {
+      return;
+    }
+    let size = textureDimensions(hdr);
+    let pixel = (id.xy * 2 + 1) * size / (2 * SAMPLE_GRID);
+    let color = textureLoad(hdr, pixel, 0).rgb;
+    let l = dot(color, vec3f(0.2126, 0.7152, 0.0722));
+    // The empty background would otherwise drag the exposure up until the
+    // bodies blow out
+    if l < 1e-4 {
+      return;
+    }
+    atomicAdd(&luminance.log_sum, i32(clamp(log(l), -20., 20.) * LOG_SCALE));
+    atomicAdd(&luminance.count, 1u);
+}
+
+// Moves the exposure towards the one mapping the average lit luminance to
+// the key, then resets the sums for the next frame
+@compute @workgroup_size(1)
+fn adapt_exposure() {
+    let count = atomicLoad(&luminance.count);
+    if count > 0 {
+      let average = exp(f32(atomicLoad(&luminance.log_sum)) / LOG_SCALE / f32(count));
+      let wanted = post.exposure_key / average;
+      exposure[0] = mix(exposure[0], wanted, post.adaptation);
+    }
+    atomicStore(&luminance.log_sum, 0);
+    atomicStore(&luminance.count, 0u);
+}
@@ -0,0 +1,15 @@
+// Signed inverse square law between per body charges, like charges repel.
+// The coupling constant is the gravitational constant
+
+fn pair_acceleration(r: vec3f, x: u32, y: u32) -> vec3f {
+    // Only removed bodies are massless, set_force_model refuses the rest
+    if masses[x] == 0 {
+      return vec3f(0);
+    }
+    let l = inverseSqrt(dot(r, r));
+    return -charges[x] * charges[y] * gravitation() * l * l * l * r / masses[x];
+}
+
+fn finish_acceleration(a: vec3f) -> vec3f {
+    return a;
+}
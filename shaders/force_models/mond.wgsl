@@ -0,0 +1,16 @@
+// MOND with the simple interpolating function μ(x) = x / (1 + x), the
+// acceleration scale a0 is sim.force_param. The newtonian field is summed
+// first, then inverted through μ(g / a0) g = g_N as a whole
+
+fn pair_acceleration(r: vec3f, x: u32, y: u32) -> vec3f {
+    let l = inverseSqrt(dot(r, r));
+    return masses[y] * gravitation() * l * l * l * r;
+}
+
+fn finish_acceleration(a: vec3f) -> vec3f {
+    let g = length(a);
+    if g == 0 {
+      return a;
+    }
+    return a * 0.5 * (1 + sqrt(1 + 4 * sim.force_param / g));
+}
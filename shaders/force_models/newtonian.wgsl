@@ -0,0 +1,10 @@
+// Plain inverse square gravity
+
+fn pair_acceleration(r: vec3f, x: u32, y: u32) -> vec3f {
+    let l = inverseSqrt(dot(r, r));
+    return masses[y] * gravitation() * l * l * l * r;
+}
+
+fn finish_acceleration(a: vec3f) -> vec3f {
+    return a;
+}
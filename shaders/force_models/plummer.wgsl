@@ -0,0 +1,12 @@
+// Gravity softened by a plummer sphere of radius sim.force_param, so close
+// encounters stay finite
+
+fn pair_acceleration(r: vec3f, x: u32, y: u32) -> vec3f {
+    let eps = sim.force_param;
+    let l = inverseSqrt(dot(r, r) + eps * eps);
+    return masses[y] * gravitation() * l * l * l * r;
+}
+
+fn finish_acceleration(a: vec3f) -> vec3f {
+    return a;
+}
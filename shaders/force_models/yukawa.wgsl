@@ -0,0 +1,11 @@
+// Screened gravity, Φ = -Gm exp(-r/λ) / r with λ = sim.force_param
+
+fn pair_acceleration(r: vec3f, x: u32, y: u32) -> vec3f {
+    let d = length(r);
+    let screening = exp(-d / sim.force_param) * (1 + d / sim.force_param);
+    return masses[y] * gravitation() * screening * r / (d * d * d);
+}
+
+fn finish_acceleration(a: vec3f) -> vec3f {
+    return a;
+}
@@ -1,11 +1,68 @@
 @group (0) @binding(0) var<storage, read_write> positions : array<vec4f>;
 @group (0) @binding(1) var<storage, read_write> velocities : array<vec4f>;
-@group (0) @binding(2) var<storage, read_write> masses : array<vec4f>;
+@group (0) @binding(2) var<storage, read_write> masses : array<f32>;
+@group (0) @binding(4) var<storage, read_write> position_lo : array<vec4f>;
+@group (0) @binding(5) var<storage, read_write> velocity_compensation : array<vec4f>;
 
-const D: f32 = 0.005;
+struct Simulation {
+  gravitation_const: f32,
+  time_step: f32,
+  body_count: u32,
+  potential_count: u32,
+  box_size: f32,
+  periodic: u32,
+  solver: u32,
+  split_scale: f32,
+  force_scale: f32,
+  velocity_damping: f32,
+  force_param: f32,
+  // Zero disables the post newtonian corrections
+  speed_of_light: f32,
+  post_newtonian_mass_threshold: f32,
+  post_newtonian_enabled: u32,
+  post_newtonian_pair_count: u32,
+  frame_rotation: f32,
+  compensated: u32,
+}
+
+@group(1) @binding(0) var<uniform> sim: Simulation;
+
+// Adds b to the double-single position, hi in positions and lo in
+// position_lo. The rounding error of hi + b is recovered with Knuth's two-sum
+// and kept in the low half
+fn add_double_single(i: u32, b: vec3f) {
+    let hi = positions[i].xyz;
+    let s = hi + b;
+    let v = s - hi;
+    let e = (hi - (s - v)) + (b - v);
+    let lo = position_lo[i].xyz + e;
+    // Renormalise so the low half stays below an ulp of the high half
+    let h = s + lo;
+    position_lo[i] = vec4(lo - (h - s), 0);
+    positions[i] = vec4(h, positions[i].w);
+}
 
 @compute @workgroup_size(1)
 fn cs_entry(@builtin(global_invocation_id) id : vec3<u32>) {
-    positions[id.x] += velocities[id.x] * D;
+    // Removed bodies stay where they were taken out
+    if positions[id.x].w == 0 {
+      return;
+    }
+    velocities[id.x] *= sim.velocity_damping;
+    if sim.compensated != 0 {
+      velocity_compensation[id.x] *= sim.velocity_damping;
+      add_double_single(id.x, velocities[id.x].xyz * sim.time_step);
+      if sim.periodic != 0 {
+        let p = positions[id.x].xyz;
+        add_double_single(id.x, -sim.box_size * floor(p / sim.box_size + 0.5));
+      }
+      return;
+    }
+    positions[id.x] += velocities[id.x] * sim.time_step;
+    if sim.periodic != 0 {
+      // Wrap into [-L/2, L/2)
+      let p = positions[id.x].xyz;
+      positions[id.x] = vec4(p - sim.box_size * floor(p / sim.box_size + 0.5), positions[id.x].w);
+    }
 }
 
@@ -0,0 +1,25 @@
+// Appended to every shader that changes the body velocities, which must declare
+// sim, velocities, position_lo and velocity_compensation
+
+// Adds dv to the velocity of x. When compensated the rounding error is carried
+// over to the next kick rather than lost
+fn kick(x: u32, dv: vec3f) {
+    let v = velocities[x].xyz;
+    if sim.compensated == 0 {
+      velocities[x] = vec4(v + dv, velocities[x].w);
+      return;
+    }
+    let y = dv - velocity_compensation[x].xyz;
+    let t = v + y;
+    velocity_compensation[x] = vec4((t - v) - y, 0);
+    velocities[x] = vec4(t, velocities[x].w);
+}
+
+// Drops the low order parts of x, for when its position or velocity is
+// replaced outright rather than added to
+fn reset_compensation(x: u32) {
+    if sim.compensated != 0 {
+      position_lo[x] = vec4f(0);
+      velocity_compensation[x] = vec4f(0);
+    }
+}
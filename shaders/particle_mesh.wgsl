@@ -0,0 +1,217 @@
+@group(0) @binding(0) var<storage, read_write> positions: array<vec4f>;
+@group(0) @binding(1) var<storage, read_write> velocities: array<vec4f>;
+@group(0) @binding(2) var<storage, read_write> masses: array<f32>;
+// Low order parts of the body state, see kick.wgsl
+@group(0) @binding(4) var<storage, read_write> position_lo: array<vec4f>;
+@group(0) @binding(5) var<storage, read_write> velocity_compensation: array<vec4f>;
+
+struct Simulation {
+  gravitation_const: f32,
+  time_step: f32,
+  body_count: u32,
+  potential_count: u32,
+  box_size: f32,
+  periodic: u32,
+  solver: u32,
+  split_scale: f32,
+  force_scale: f32,
+  velocity_damping: f32,
+  force_param: f32,
+  // Zero disables the post newtonian corrections
+  speed_of_light: f32,
+  post_newtonian_mass_threshold: f32,
+  post_newtonian_enabled: u32,
+  post_newtonian_pair_count: u32,
+  frame_rotation: f32,
+  compensated: u32,
+}
+
+struct Mesh {
+  // Fixed point units per unit of mass, set from the total mass so a cell
+  // holding all of it still fits
+  mass_scale: f32,
+}
+
+@group(1) @binding(0) var<uniform> sim: Simulation;
+
+// Mass is deposited as fixed point, as there are no float atomics
+@group(2) @binding(0) var<storage, read_write> density: array<atomic<i32>>;
+// Complex values, holds the density, then its transform, then the potential
+@group(2) @binding(1) var<storage, read_write> grid: array<vec2f>;
+@group(2) @binding(2) var<uniform> mesh: Mesh;
+
+// Must match physics::particle_mesh::PM_GRID
+const PM_GRID: u32 = 64;
+const LOG2_PM_GRID: u32 = 6;
+const SOLVER_P3M: u32 = 2;
+const PI: f32 = 3.14159265358979;
+
+fn cell_index(i: vec3i) -> u32 {
+    let n = i32(PM_GRID);
+    // Wrap periodically, the extra + n handles the negative side
+    let w = vec3u((i % n + n) % n);
+    return w.x + PM_GRID * (w.y + PM_GRID * w.z);
+}
+
+// Position in grid units, where cell centres lie on integers
+fn grid_coordinate(p: vec3f) -> vec3f {
+    return (p / sim.box_size + 0.5) * f32(PM_GRID) - 0.5;
+}
+
+fn cic_weight(o: vec3u, t: vec3f) -> f32 {
+    let w = select(1 - t, t, vec3<bool>(o));
+    return w.x * w.y * w.z;
+}
+
+@compute @workgroup_size(64)
+fn assign_mass(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.x >= sim.body_count {
+      return;
+    }
+    let u = grid_coordinate(positions[id.x].xyz);
+    let base = vec3i(floor(u));
+    let t = fract(u);
+    for (var corner = 0u; corner < 8; corner++) {
+      let o = vec3u(corner & 1, (corner >> 1) & 1, (corner >> 2) & 1);
+      let m = masses[id.x] * cic_weight(o, t) * mesh.mass_scale;
+      atomicAdd(&density[cell_index(base + vec3i(o))], i32(round(m)));
+    }
+}
+
+@compute @workgroup_size(64)
+fn load_density(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.x >= PM_GRID * PM_GRID * PM_GRID {
+      return;
+    }
+    let h = sim.box_size / f32(PM_GRID);
+    let mass = f32(atomicLoad(&density[id.x])) / mesh.mass_scale;
+    grid[id.x] = vec2(mass / (h * h * h), 0);
+}
+
+fn bit_reverse(i: u32) -> u32 {
+    return reverseBits(i) >> (32 - LOG2_PM_GRID);
+}
+
+fn complex_mul(a: vec2f, b: vec2f) -> vec2f {
+    return vec2(a.x * b.x - a.y * b.y, a.x * b.y + a.y * b.x);
+}
+
+// In place radix-2 FFT of one line of the grid, each invocation owns a whole
+// line so no synchronisation is needed. The inverse is left unnormalised.
+fn fft_line(start: u32, stride: u32, inverse: bool) {
+    var line: array<vec2f, PM_GRID>;
+    for (var i = 0u; i < PM_GRID; i++) {
+      line[bit_reverse(i)] = grid[start + i * stride];
+    }
+    let direction = select(-1., 1., inverse);
+    for (var size = 2u; size <= PM_GRID; size <<= 1) {
+      let half = size / 2;
+      for (var k = 0u; k < half; k++) {
+        let angle = direction * 2 * PI * f32(k) / f32(size);
+        let twiddle = vec2(cos(angle), sin(angle));
+        for (var j = k; j < PM_GRID; j += size) {
+          let even = line[j];
+          let odd = complex_mul(line[j + half], twiddle);
+          line[j] = even + odd;
+          line[j + half] = even - odd;
+        }
+      }
+    }
+    for (var i = 0u; i < PM_GRID; i++) {
+      grid[start + i * stride] = line[i];
+    }
+}
+
+// One invocation per line, id.x and id.y index the two other axes
+fn fft_axis(id: vec3<u32>, axis: u32, inverse: bool) {
+    if id.x >= PM_GRID || id.y >= PM_GRID {
+      return;
+    }
+    switch axis {
+      case 0u: { fft_line(PM_GRID * (id.x + PM_GRID * id.y), 1, inverse); }
+      case 1u: { fft_line(id.x + PM_GRID * PM_GRID * id.y, PM_GRID, inverse); }
+      default: { fft_line(id.x + PM_GRID * id.y, PM_GRID * PM_GRID, inverse); }
+    }
+}
+
+@compute @workgroup_size(8, 8)
+fn fft_forward_x(@builtin(global_invocation_id) id: vec3<u32>) { fft_axis(id, 0, false); }
+@compute @workgroup_size(8, 8)
+fn fft_forward_y(@builtin(global_invocation_id) id: vec3<u32>) { fft_axis(id, 1, false); }
+@compute @workgroup_size(8, 8)
+fn fft_forward_z(@builtin(global_invocation_id) id: vec3<u32>) { fft_axis(id, 2, false); }
+@compute @workgroup_size(8, 8)
+fn fft_inverse_x(@builtin(global_invocation_id) id: vec3<u32>) { fft_axis(id, 0, true); }
+@compute @workgroup_size(8, 8)
+fn fft_inverse_y(@builtin(global_invocation_id) id: vec3<u32>) { fft_axis(id, 1, true); }
+@compute @workgroup_size(8, 8)
+fn fft_inverse_z(@builtin(global_invocation_id) id: vec3<u32>) { fft_axis(id, 2, true); }
+
+fn sinc(x: f32) -> f32 {
+    if abs(x) < 1e-6 {
+      return 1.;
+    }
+    return sin(x) / x;
+}
+
+// Solves the poisson equation in fourier space, φ_k = -4πG ρ_k / k²
+@compute @workgroup_size(64)
+fn solve_potential(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.x >= PM_GRID * PM_GRID * PM_GRID {
+      return;
+    }
+    let i = vec3u(id.x % PM_GRID, (id.x / PM_GRID) % PM_GRID, id.x / (PM_GRID * PM_GRID));
+    // Wave numbers, upper half of the indices are the negative frequencies
+    let n = vec3f(vec3i(i) - select(vec3i(0), vec3i(i32(PM_GRID)), i >= vec3u(PM_GRID / 2)));
+    let k = n * 2 * PI / sim.box_size;
+    let k2 = dot(k, k);
+    if k2 == 0 {
+      grid[id.x] = vec2f(0);
+      return;
+    }
+
+    var green = -4 * PI * sim.gravitation_const * sim.force_scale / k2;
+    if sim.solver == SOLVER_P3M {
+      // Only the long range part, the short range is summed directly
+      green *= exp(-k2 * sim.split_scale * sim.split_scale);
+    }
+    // Deconvolve the CIC window, once for assignment and once for
+    // interpolation
+    let s = n * PI / f32(PM_GRID);
+    let window = sinc(s.x) * sinc(s.y) * sinc(s.z);
+    green /= pow(window, 4.);
+
+    // The 1/N³ normalises the inverse transform
+    let cells = f32(PM_GRID * PM_GRID * PM_GRID);
+    grid[id.x] *= green / cells;
+}
+
+fn potential(i: vec3i) -> f32 {
+    return grid[cell_index(i)].x;
+}
+
+// Central difference of the potential at a grid point
+fn acceleration_at(i: vec3i) -> vec3f {
+    let h = sim.box_size / f32(PM_GRID);
+    return -vec3(
+      potential(i + vec3i(1, 0, 0)) - potential(i - vec3i(1, 0, 0)),
+      potential(i + vec3i(0, 1, 0)) - potential(i - vec3i(0, 1, 0)),
+      potential(i + vec3i(0, 0, 1)) - potential(i - vec3i(0, 0, 1)),
+    ) / (2 * h);
+}
+
+@compute @workgroup_size(64)
+fn interpolate_forces(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.x >= sim.body_count {
+      return;
+    }
+    let u = grid_coordinate(positions[id.x].xyz);
+    let base = vec3i(floor(u));
+    let t = fract(u);
+    var a = vec3f(0);
+    for (var corner = 0u; corner < 8; corner++) {
+      let o = vec3u(corner & 1, (corner >> 1) & 1, (corner >> 2) & 1);
+      a += acceleration_at(base + vec3i(o)) * cic_weight(o, t);
+    }
+    kick(id.x, a * sim.time_step);
+}
@@ -3,24 +3,94 @@ struct Uniform {
   world_mat: mat4x4<f32>,
   width: u32,
   height: u32,
+  // World radius of a body of unit mass
+  sprite_size: f32,
+  // Sprites never shrink below this radius in pixels
+  min_sprite_pixels: f32,
+  // x and y scale of the perspective projection
+  projection_scale: vec2f,
+  // See graphics::coloring::Colormap, zero draws every body white
+  colormap: u32,
+  // See graphics::rendering::DepthMode
+  depth_mode: u32,
+  // Distance mapped to the far end of the logarithmic depth
+  log_depth_far: f32,
   p0_: u32,
-  p1_: u32
+  p1_: u32,
+  p2_: u32,
 }
 
 @group(0)
 @binding(0)
 var<uniform> inputs: Uniform;
 
+const DEPTH_LOGARITHMIC: u32 = 1;
+
+// Depth in [0, 1] of a clip space position, one at the camera and falling
+// away with distance, as the depth test is reversed
+fn ndc_depth(clip: vec4f) -> f32 {
+    if inputs.depth_mode == DEPTH_LOGARITHMIC {
+      return 1 - log2(1 + max(clip.w, 0.)) / log2(1 + inputs.log_depth_far);
+    }
+    return clip.z / clip.w;
+}
+
 @vertex
 fn vs_main(@location(0) vertex: vec4f) -> @builtin(position) vec4f {
+    // Removed bodies have a zero w, put them behind the far plane
+    if vertex.w == 0 {
+      return vec4f(0, 0, 2, 1);
+    }
     let w = inputs.world_mat;
     let mapped = w * vertex;
-    return vec4(mapped.xyz / mapped.w, 1);
+    return vec4(mapped.xy / mapped.w, ndc_depth(mapped), 1);
+}
+
+struct SpriteOutput {
+  @builtin(position) position: vec4f,
+  // Offset from the centre of the sprite, in [-1, 1]
+  @location(0) corner: vec2f,
+  @location(1) color: vec3f,
 }
 
-fn map_z(n: f32) -> f32 {
-    // Maps the z space from [0,1] -> [0,very large number]
-    return (1/pow(1 - 0.9999 * n, 50.)) - 1;
+// One instance per body, each a quad facing the camera. The radius grows with
+// the cube root of the mass, as for bodies of equal density
+@vertex
+fn vs_sprite(
+    @builtin(vertex_index) index: u32,
+    @location(0) body: vec4f,
+    @location(1) mass: f32,
+    // Scaled to [0, 1] by the coloring passes
+    @location(2) value: f32,
+) -> SpriteOutput {
+    var out: SpriteOutput;
+    let corner = vec2f(f32(index & 1), f32(index >> 1)) * 2 - 1;
+    out.corner = corner;
+    out.color = colormap(inputs.colormap, value);
+    let mapped = inputs.world_mat * vec4(body.xyz, 1);
+    // Removed bodies and those behind the camera go behind the far plane
+    if body.w == 0 || mapped.w <= 0 {
+      out.position = vec4f(0, 0, 2, 1);
+      return out;
+    }
+    let size = vec2f(f32(inputs.width), f32(inputs.height));
+    let radius = inputs.sprite_size * pow(max(mass, 0.), 1. / 3.);
+    // Perspective shrinks the sprite with distance, in pixels
+    let pixels = max(radius * inputs.projection_scale.y / mapped.w * size.y / 2, inputs.min_sprite_pixels);
+    let offset = corner * pixels * 2 / size;
+    out.position = vec4(mapped.xy / mapped.w + offset, ndc_depth(mapped), 1);
+    return out;
+}
+
+@fragment
+fn fs_sprite(in: SpriteOutput) -> @location(0) vec4f {
+    let r2 = dot(in.corner, in.corner);
+    if r2 > 1 {
+      discard;
+    }
+    // Soft edged disc, bright in the middle and fading out to the rim
+    let alpha = (1 - r2) * (1 - r2);
+    return vec4f(in.color, alpha);
 }
 
 @fragment
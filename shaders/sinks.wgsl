@@ -0,0 +1,133 @@
+@group(0) @binding(0) var<storage, read_write> positions: array<vec4f>;
+@group(0) @binding(1) var<storage, read_write> velocities: array<vec4f>;
+@group(0) @binding(2) var<storage, read_write> masses: array<f32>;
+@group(0) @binding(3) var<storage, read_write> species: array<u32>;
+// Low order parts of the body state, see kick.wgsl
+@group(0) @binding(4) var<storage, read_write> position_lo: array<vec4f>;
+@group(0) @binding(5) var<storage, read_write> velocity_compensation: array<vec4f>;
+
+struct Simulation {
+  gravitation_const: f32,
+  time_step: f32,
+  body_count: u32,
+  potential_count: u32,
+  box_size: f32,
+  periodic: u32,
+  solver: u32,
+  split_scale: f32,
+  force_scale: f32,
+  velocity_damping: f32,
+  force_param: f32,
+  speed_of_light: f32,
+  post_newtonian_mass_threshold: f32,
+  post_newtonian_enabled: u32,
+  post_newtonian_pair_count: u32,
+  frame_rotation: f32,
+  compensated: u32,
+}
+
+struct Sinks {
+  accretion_radius: f32,
+  // Simulation time of this step, recorded with each event
+  time: f32,
+}
+
+struct AccretionEvent {
+  time: f32,
+  sink: u32,
+  body: u32,
+  mass: f32,
+}
+
+@group(1) @binding(0) var<uniform> sim: Simulation;
+
+@group(2) @binding(0) var<uniform> sinks: Sinks;
+// The sink claiming each body, NO_SINK if none
+@group(2) @binding(1) var<storage, read_write> targets: array<atomic<u32>>;
+@group(2) @binding(2) var<storage, read_write> event_count: atomic<u32>;
+// A body can only be accreted once, so this holds one event per body
+@group(2) @binding(3) var<storage, read_write> events: array<AccretionEvent>;
+
+const SPECIES_SINK: u32 = 2;
+const NO_SINK: u32 = 0xffffffff;
+
+fn is_sink(i: u32) -> bool {
+    return i < sim.body_count && species[i] == SPECIES_SINK && positions[i].w != 0;
+}
+
+// Displacement from the sink x to the body y
+fn offset(x: u32, y: u32) -> vec3f {
+    let r = positions[y].xyz - positions[x].xyz;
+    if sim.periodic != 0 {
+      return r - sim.box_size * round(r / sim.box_size);
+    }
+    return r;
+}
+
+// One invocation per body, but only sinks do any work. Bodies inside several
+// sinks go to the lowest indexed one
+@compute @workgroup_size(64)
+fn claim_bodies(@builtin(global_invocation_id) id: vec3<u32>) {
+    let x = id.x;
+    if !is_sink(x) {
+      return;
+    }
+    for (var y = 0u; y < sim.body_count; y++) {
+      // Removed bodies and other sinks are never accreted
+      if species[y] == SPECIES_SINK || positions[y].w == 0 {
+        continue;
+      }
+      if length(offset(x, y)) < sinks.accretion_radius {
+        atomicMin(&targets[y], x);
+      }
+    }
+}
+
+// Adds the claimed bodies' mass and momentum to each sink, moving it to their
+// combined centre of mass
+@compute @workgroup_size(64)
+fn merge_bodies(@builtin(global_invocation_id) id: vec3<u32>) {
+    let x = id.x;
+    if !is_sink(x) {
+      return;
+    }
+    var mass = masses[x];
+    var momentum = velocities[x].xyz * masses[x];
+    var moment = vec3f(0);
+    for (var y = 0u; y < sim.body_count; y++) {
+      if atomicLoad(&targets[y]) != x {
+        continue;
+      }
+      mass += masses[y];
+      momentum += velocities[y].xyz * masses[y];
+      moment += offset(x, y) * masses[y];
+      events[atomicAdd(&event_count, 1u)] = AccretionEvent(sinks.time, x, y, masses[y]);
+    }
+    if mass == masses[x] || mass == 0 {
+      return;
+    }
+    var p = positions[x].xyz + moment / mass;
+    if sim.periodic != 0 {
+      p -= sim.box_size * floor(p / sim.box_size + 0.5);
+    }
+    positions[x] = vec4(p, 1);
+    velocities[x] = vec4(momentum / mass, 0);
+    masses[x] = mass;
+    // The new state replaces the old outright, so its carried rounding error
+    // no longer applies
+    reset_compensation(x);
+}
+
+// One invocation per body, removes the accreted ones
+@compute @workgroup_size(64)
+fn remove_bodies(@builtin(global_invocation_id) id: vec3<u32>) {
+    let y = id.x;
+    if y >= sim.body_count || atomicLoad(&targets[y]) == NO_SINK {
+      return;
+    }
+    positions[y].w = 0;
+    velocities[y] = vec4f(0);
+    masses[y] = 0;
+    reset_compensation(y);
+    atomicStore(&targets[y], NO_SINK);
+}
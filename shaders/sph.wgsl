@@ -0,0 +1,241 @@
+@group(0) @binding(0) var<storage, read_write> positions: array<vec4f>;
+@group(0) @binding(1) var<storage, read_write> velocities: array<vec4f>;
+@group(0) @binding(2) var<storage, read_write> masses: array<f32>;
+@group(0) @binding(3) var<storage, read_write> species: array<u32>;
+// Low order parts of the body state, see kick.wgsl
+@group(0) @binding(4) var<storage, read_write> position_lo: array<vec4f>;
+@group(0) @binding(5) var<storage, read_write> velocity_compensation: array<vec4f>;
+
+struct Simulation {
+  gravitation_const: f32,
+  time_step: f32,
+  body_count: u32,
+  potential_count: u32,
+  box_size: f32,
+  periodic: u32,
+  solver: u32,
+  split_scale: f32,
+  force_scale: f32,
+  velocity_damping: f32,
+  force_param: f32,
+  speed_of_light: f32,
+  post_newtonian_mass_threshold: f32,
+  post_newtonian_enabled: u32,
+  post_newtonian_pair_count: u32,
+  frame_rotation: f32,
+  compensated: u32,
+}
+
+struct Hydro {
+  smoothing_length: f32,
+  adiabatic_index: f32,
+  isothermal_sound_speed: f32,
+  equation_of_state: u32,
+  viscosity_alpha: f32,
+  viscosity_beta: f32,
+}
+
+@group(1) @binding(0) var<uniform> sim: Simulation;
+
+@group(2) @binding(0) var<uniform> hydro: Hydro;
+@group(2) @binding(1) var<storage, read_write> density: array<f32>;
+@group(2) @binding(2) var<storage, read_write> pressure: array<f32>;
+@group(2) @binding(3) var<storage, read_write> internal_energy: array<f32>;
+// Kept apart from the velocities, as the viscosity reads every neighbour's
+// velocity
+@group(2) @binding(4) var<storage, read_write> accelerations: array<vec4f>;
+@group(2) @binding(5) var<storage, read_write> cell_counts: array<atomic<u32>>;
+@group(2) @binding(6) var<storage, read_write> cell_bodies: array<u32>;
+
+// Must match physics::sph
+const HASH_TABLE_SIZE: u32 = 32768;
+const CELL_CAPACITY: u32 = 32;
+
+const SPECIES_GAS: u32 = 1;
+const EOS_ISOTHERMAL: u32 = 0;
+const PI: f32 = 3.14159265358979;
+
+// Cells are the size of the kernel support, so every neighbour is in one of
+// the 27 surrounding cells. With a periodic boundary the cells are stretched
+// slightly so a whole number of them fit the box
+fn cells_per_box() -> i32 {
+    return max(i32(floor(sim.box_size / (2 * hydro.smoothing_length))), 1);
+}
+
+fn cell_of(p: vec3f) -> vec3i {
+    if sim.periodic != 0 {
+      return vec3i(floor((p / sim.box_size + 0.5) * f32(cells_per_box())));
+    }
+    return vec3i(floor(p / (2 * hydro.smoothing_length)));
+}
+
+fn cell_hash(c: vec3i) -> u32 {
+    var cell = c;
+    if sim.periodic != 0 {
+      let n = cells_per_box();
+      cell = (c % n + n) % n;
+    }
+    let h = bitcast<vec3u>(cell) * vec3u(73856093, 19349663, 83492791);
+    return (h.x ^ h.y ^ h.z) % HASH_TABLE_SIZE;
+}
+
+fn separation(x: u32, y: u32) -> vec3f {
+    let r = positions[x].xyz - positions[y].xyz;
+    if sim.periodic != 0 {
+      return r - sim.box_size * round(r / sim.box_size);
+    }
+    return r;
+}
+
+// Cubic spline (M4) kernel with support 2h
+fn kernel(r: f32) -> f32 {
+    let h = hydro.smoothing_length;
+    let q = r / h;
+    let sigma = 1 / (PI * h * h * h);
+    if q < 1 {
+      return sigma * (1 - 1.5 * q * q + 0.75 * q * q * q);
+    }
+    if q < 2 {
+      return sigma * 0.25 * pow(2 - q, 3.);
+    }
+    return 0.;
+}
+
+// dW/dr
+fn kernel_derivative(r: f32) -> f32 {
+    let h = hydro.smoothing_length;
+    let q = r / h;
+    let sigma = 1 / (PI * h * h * h * h);
+    if q < 1 {
+      return sigma * (-3 * q + 2.25 * q * q);
+    }
+    if q < 2 {
+      return sigma * -0.75 * (2 - q) * (2 - q);
+    }
+    return 0.;
+}
+
+fn sound_speed(i: u32) -> f32 {
+    if hydro.equation_of_state == EOS_ISOTHERMAL {
+      return hydro.isothermal_sound_speed;
+    }
+    return sqrt(hydro.adiabatic_index * pressure[i] / density[i]);
+}
+
+// Each neighbour must only be visited once, even if the periodic wrap or a
+// hash collision puts its cell in the search twice
+fn already_visited(hashes: ptr<function, array<u32, 27>>, c: i32) -> bool {
+    for (var p = 0; p < c; p++) {
+      if (*hashes)[p] == (*hashes)[c] {
+        return true;
+      }
+    }
+    return false;
+}
+
+fn is_gas(i: u32) -> bool {
+    return i < sim.body_count && species[i] == SPECIES_GAS && positions[i].w != 0;
+}
+
+@compute @workgroup_size(64)
+fn insert_bodies(@builtin(global_invocation_id) id: vec3<u32>) {
+    if !is_gas(id.x) {
+      return;
+    }
+    let hash = cell_hash(cell_of(positions[id.x].xyz));
+    let slot = atomicAdd(&cell_counts[hash], 1u);
+    // Bodies past the capacity are dropped from the search, the cell size
+    // should be small enough that this is rare
+    if slot < CELL_CAPACITY {
+      cell_bodies[hash * CELL_CAPACITY + slot] = id.x;
+    }
+}
+
+@compute @workgroup_size(64)
+fn compute_density(@builtin(global_invocation_id) id: vec3<u32>) {
+    let x = id.x;
+    if !is_gas(x) {
+      return;
+    }
+    let cell = cell_of(positions[x].xyz);
+    var rho = masses[x] * kernel(0.);
+    var visited_hashes: array<u32, 27>;
+    for (var c = 0; c < 27; c++) {
+      let hash = cell_hash(cell + vec3i(c % 3 - 1, (c / 3) % 3 - 1, c / 9 - 1));
+      visited_hashes[c] = hash;
+      if already_visited(&visited_hashes, c) {
+        continue;
+      }
+      let count = min(atomicLoad(&cell_counts[hash]), CELL_CAPACITY);
+      for (var i = 0u; i < count; i++) {
+        let y = cell_bodies[hash * CELL_CAPACITY + i];
+        if y != x {
+          // Hash collisions bring in far away bodies, the kernel is zero
+          // for those
+          rho += masses[y] * kernel(length(separation(x, y)));
+        }
+      }
+    }
+    density[x] = rho;
+    if hydro.equation_of_state == EOS_ISOTHERMAL {
+      pressure[x] = hydro.isothermal_sound_speed * hydro.isothermal_sound_speed * rho;
+    } else {
+      pressure[x] = (hydro.adiabatic_index - 1) * rho * internal_energy[x];
+    }
+}
+
+// Pressure gradient and Monaghan artificial viscosity
+@compute @workgroup_size(64)
+fn compute_forces(@builtin(global_invocation_id) id: vec3<u32>) {
+    let x = id.x;
+    if !is_gas(x) {
+      return;
+    }
+    let h = hydro.smoothing_length;
+    let cell = cell_of(positions[x].xyz);
+    let own = pressure[x] / (density[x] * density[x]);
+    var a = vec3f(0);
+    var du = 0.;
+    var visited_hashes: array<u32, 27>;
+    for (var c = 0; c < 27; c++) {
+      let hash = cell_hash(cell + vec3i(c % 3 - 1, (c / 3) % 3 - 1, c / 9 - 1));
+      visited_hashes[c] = hash;
+      if already_visited(&visited_hashes, c) {
+        continue;
+      }
+      let count = min(atomicLoad(&cell_counts[hash]), CELL_CAPACITY);
+      for (var i = 0u; i < count; i++) {
+        let y = cell_bodies[hash * CELL_CAPACITY + i];
+        let r = separation(x, y);
+        let d = length(r);
+        if y == x || d >= 2 * h || d == 0 {
+          continue;
+        }
+        let v = velocities[x].xyz - velocities[y].xyz;
+        let vr = dot(v, r);
+        var viscosity = 0.;
+        if vr < 0 {
+          let mu = h * vr / (d * d + 0.01 * h * h);
+          let c_mean = 0.5 * (sound_speed(x) + sound_speed(y));
+          let rho_mean = 0.5 * (density[x] + density[y]);
+          viscosity = (-hydro.viscosity_alpha * c_mean * mu + hydro.viscosity_beta * mu * mu) / rho_mean;
+        }
+        let gradient = kernel_derivative(d) * r / d;
+        let term = own + pressure[y] / (density[y] * density[y]) + viscosity;
+        a -= masses[y] * term * gradient;
+        du += 0.5 * masses[y] * term * dot(v, gradient);
+      }
+    }
+    accelerations[x] = vec4(a, 0);
+    if hydro.equation_of_state != EOS_ISOTHERMAL {
+      internal_energy[x] = max(internal_energy[x] + du * sim.time_step, 0.);
+    }
+}
+
+@compute @workgroup_size(64)
+fn apply_forces(@builtin(global_invocation_id) id: vec3<u32>) {
+    if !is_gas(id.x) {
+      return;
+    }
+    kick(id.x, accelerations[id.x].xyz * sim.time_step);
+}
@@ -0,0 +1,81 @@
+struct FullscreenOutput {
+  @builtin(position) position: vec4f,
+  @location(0) uv: vec2f,
+}
+
+struct Post {
+  exposure: f32,
+  bloom_strength: f32,
+  tone_mapping: u32,
+  // Non zero when the surface doesn't convert to sRGB itself
+  encode_srgb: u32,
+  exposure_key: f32,
+  adaptation: f32,
+  auto_exposure: u32,
+  // Size of the scene relative to the surface
+  render_scale: f32,
+}
+
+@group(0) @binding(0) var hdr: texture_2d<f32>;
+@group(0) @binding(1) var bloom: texture_2d<f32>;
+@group(0) @binding(2) var linear_sampler: sampler;
+@group(0) @binding(3) var<uniform> post: Post;
+// Written each frame, by the auto exposure passes when enabled
+@group(0) @binding(4) var<storage, read> exposure: array<f32>;
+
+const TONE_MAPPING_ACES: u32 = 0;
+const TONE_MAPPING_REINHARD: u32 = 1;
+
+@vertex
+fn vs_fullscreen(@builtin(vertex_index) index: u32) -> FullscreenOutput {
+    var out: FullscreenOutput;
+    let uv = vec2f(f32((index << 1) & 2), f32(index & 2));
+    out.position = vec4(uv * vec2f(2, -2) + vec2f(-1, 1), 0, 1);
+    out.uv = uv;
+    return out;
+}
+
+// The scene at a pixel of the surface. A supersampled scene is averaged over
+// the texels the pixel covers, a smaller one interpolated
+fn scene_color(in: FullscreenOutput) -> vec3f {
+    let scale = post.render_scale;
+    if scale <= 1 {
+      return textureSampleLevel(hdr, linear_sampler, in.uv, 0.).rgb;
+    }
+    let pixel = floor(in.position.xy);
+    let low = vec2i(ceil(pixel * scale - 0.5));
+    let high = max(vec2i(ceil((pixel + 1) * scale - 0.5)), low + 1);
+    let last = vec2i(textureDimensions(hdr)) - 1;
+    var sum = vec3f(0);
+    for (var y = low.y; y < high.y; y++) {
+      for (var x = low.x; x < high.x; x++) {
+        sum += textureLoad(hdr, min(vec2i(x, y), last), 0).rgb;
+      }
+    }
+    let count = high - low;
+    return sum / f32(count.x * count.y);
+}
+
+// Narkowicz's fit to the ACES filmic curve
+fn aces(x: vec3f) -> vec3f {
+    return clamp(x * (2.51 * x + 0.03) / (x * (2.43 * x + 0.59) + 0.14), vec3f(0), vec3f(1));
+}
+
+fn reinhard(x: vec3f) -> vec3f {
+    return x / (1 + x);
+}
+
+@fragment
+fn fs_composite(in: FullscreenOutput) -> @location(0) vec4f {
+    let scene = scene_color(in);
+    let glow = textureSample(bloom, linear_sampler, in.uv).rgb;
+    let exposed = (scene + glow * post.bloom_strength) * exposure[0];
+    var color = aces(exposed);
+    if post.tone_mapping == TONE_MAPPING_REINHARD {
+      color = reinhard(exposed);
+    }
+    if post.encode_srgb != 0 {
+      color = pow(color, vec3f(1 / 2.2));
+    }
+    return vec4(color, 1);
+}
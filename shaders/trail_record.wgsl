@@ -0,0 +1,33 @@
+@group(0) @binding(0) var<storage, read_write> positions: array<vec4f>;
+
+struct Trail {
+  // Samples kept per body
+  length: u32,
+  // Slot of the newest sample
+  head: u32,
+  // Samples written so far, up to the length
+  filled: u32,
+  fade: f32,
+  color: vec3f,
+  // Bodies with trails
+  selected: u32,
+  box_size: f32,
+  periodic: u32,
+  p0_: u32,
+  p1_: u32,
+}
+
+@group(1) @binding(0) var<uniform> trail: Trail;
+// Slot major, so one sample of every selected body is contiguous
+@group(1) @binding(1) var<storage, read_write> history: array<vec4f>;
+// Indices of the bodies with trails
+@group(1) @binding(2) var<storage, read> bodies: array<u32>;
+
+@compute @workgroup_size(64)
+fn record_positions(@builtin(global_invocation_id) id: vec3<u32>) {
+    let x = id.x;
+    if x >= trail.selected {
+      return;
+    }
+    history[trail.head * trail.selected + x] = positions[bodies[x]];
+}
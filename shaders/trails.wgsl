@@ -0,0 +1,92 @@
+struct Uniform {
+  world_mat: mat4x4<f32>,
+  width: u32,
+  height: u32,
+  sprite_size: f32,
+  min_sprite_pixels: f32,
+  projection_scale: vec2f,
+  colormap: u32,
+  depth_mode: u32,
+  log_depth_far: f32,
+  p0_: u32,
+  p1_: u32,
+  p2_: u32,
+}
+
+struct Trail {
+  length: u32,
+  head: u32,
+  filled: u32,
+  // Exponent of the fade with age, larger gives shorter looking trails
+  fade: f32,
+  color: vec3f,
+  selected: u32,
+  box_size: f32,
+  periodic: u32,
+  p0_: u32,
+  p1_: u32,
+}
+
+@group(0) @binding(0) var<uniform> inputs: Uniform;
+
+const DEPTH_LOGARITHMIC: u32 = 1;
+
+// See render.wgsl
+fn ndc_depth(clip: vec4f) -> f32 {
+    if inputs.depth_mode == DEPTH_LOGARITHMIC {
+      return 1 - log2(1 + max(clip.w, 0.)) / log2(1 + inputs.log_depth_far);
+    }
+    return clip.z / clip.w;
+}
+
+@group(1) @binding(0) var<uniform> trail: Trail;
+// Recorded in selection order, one instance per selected body
+@group(1) @binding(1) var<storage, read> history: array<vec4f>;
+
+struct TrailOutput {
+  @builtin(position) position: vec4f,
+  @location(0) alpha: f32,
+}
+
+// Position of the `instance`th selected body `age` samples ago
+fn sample(instance: u32, age: u32) -> vec4f {
+    let slot = (trail.head + trail.length - age) % trail.length;
+    return history[slot * trail.selected + instance];
+}
+
+// Each instance is one body's trail as a line list, segment i joining the
+// samples i and i + 1 samples old
+@vertex
+fn vs_trail(
+    @builtin(vertex_index) index: u32,
+    @builtin(instance_index) instance: u32,
+) -> TrailOutput {
+    var out: TrailOutput;
+    let segment = index / 2;
+    let age = segment + (index & 1);
+    // Segments are dropped whole by putting both ends behind the far plane
+    out.position = vec4f(0, 0, 2, 1);
+    if segment + 1 >= trail.filled {
+      return out;
+    }
+    let start = sample(instance, segment);
+    let end = sample(instance, segment + 1);
+    if start.w == 0 || end.w == 0 {
+      return out;
+    }
+    // Don't draw across the box when a body wrapped round it
+    let jump = abs(end.xyz - start.xyz);
+    if trail.periodic != 0 && any(jump > vec3f(trail.box_size / 2)) {
+      return out;
+    }
+    let point = sample(instance, age);
+    let clip = inputs.world_mat * vec4(point.xyz, 1);
+    out.position = vec4(clip.xy, ndc_depth(clip) * clip.w, clip.w);
+    out.alpha = pow(1 - f32(age) / f32(trail.length), trail.fade);
+    return out;
+}
+
+@fragment
+fn fs_trail(in: TrailOutput) -> @location(0) vec4f {
+    return vec4f(trail.color, in.alpha);
+}
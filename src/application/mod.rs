@@ -1,14 +1,30 @@
 #![allow(dead_code, unused_variables)]
 
 use crate::graphics::{
-    rendering::{Camera, ViewModeLookAt, ViewModeLookTo},
+    coloring::ColorParams,
+    density::{DensityParams, RenderMode},
+    post_processing::PostParams,
+    quality::RenderQuality,
+    recording::RecordingParams,
+    rendering::{Camera, DepthMode, SpriteParams, ViewModeLookAt},
+    trails::TrailParams,
+    vertices::InitialConditions,
     Graphics,
 };
+use crate::physics::{
+    cosmology::Cosmology, escapes::EscapeCriterion, force_models::ForceModel,
+    particle_mesh::GravitySolver, periodic::Boundary, post_newtonian::PostNewtonian,
+    potentials::ExternalPotential, regularization::RegularizationParams, sinks::SinkParams,
+    sph::HydroParams, units::UnitSystem,
+};
 use crate::prelude::*;
+use std::time::Duration;
+
+mod scenario;
 
 use winit::{
     application::ApplicationHandler,
-    event::{self, DeviceId, KeyEvent, WindowEvent},
+    event::{self, WindowEvent},
     event_loop,
     window::{self, Window},
 };
@@ -18,7 +34,56 @@ pub struct UserOptions {
     pub mouse_sensitivity: f32,
     pub line_size: f32,
     pub scroll_sensitivity: f32,
-    pub gravitation_const: f32,
+    /// Size of the bodies on screen
+    pub sprites: SpriteParams,
+    /// Some to colour the bodies by a quantity, otherwise they're white
+    pub coloring: Option<ColorParams>,
+    /// Bloom, exposure and tone mapping of the drawn scene
+    pub post_processing: PostParams,
+    /// Some to draw the recent paths of the bodies
+    pub trails: Option<TrailParams>,
+    /// Sprites, or the projected or volumetric density for large runs
+    pub render_mode: RenderMode,
+    pub density: DensityParams,
+    /// MSAA, render scale and present mode, limited to what the adapter
+    /// supports
+    pub render_quality: RenderQuality,
+    /// Reversed-Z or logarithmic depth, both hold up from planetary to
+    /// galactic scales
+    pub depth_mode: DepthMode,
+    /// Size, time step and outputs of the offscreen recordings started with
+    /// F9
+    pub recording: RecordingParams,
+    /// Length, mass and time units of the simulation, G is derived from them
+    pub units: UnitSystem,
+    /// None keeps the simulation's default
+    pub time_step: Option<f32>,
+    /// Static potentials added on top of the pairwise gravity, e.g. a dark
+    /// matter halo
+    pub external_potentials: Vec<ExternalPotential>,
+    pub boundary: Boundary,
+    /// The mesh based solvers need a periodic `boundary`
+    pub gravity_solver: GravitySolver,
+    /// Some to run in comoving coordinates in an expanding universe
+    pub cosmology: Option<Cosmology>,
+    pub initial_conditions: InitialConditions,
+    pub force_model: ForceModel,
+    /// Some to enable the 1PN corrections
+    pub post_newtonian: Option<PostNewtonian>,
+    /// Some to give the gas bodies pressure and viscosity, otherwise they're
+    /// collisionless
+    pub hydrodynamics: Option<HydroParams>,
+    /// Some to let the sink bodies accrete
+    pub sinks: Option<SinkParams>,
+    /// Bin width of the accretion rate series, in simulation time
+    pub accretion_rate_interval: f64,
+    /// Some to watch for bodies escaping the system
+    pub escape_criterion: Option<EscapeCriterion>,
+    /// Some to integrate tight binaries with the KS sub-integrator
+    pub regularization: Option<RegularizationParams>,
+    /// Kahan summation and double-single positions for long integrations,
+    /// at some cost in speed
+    pub compensated_summation: bool,
 }
 
 impl Default for UserOptions {
@@ -26,8 +91,31 @@ impl Default for UserOptions {
         Self {
             mouse_sensitivity: 0.7,
             line_size: 0.5,
-            gravitation_const: 2e-5,
+            units: UnitSystem::default(),
+            time_step: None,
             scroll_sensitivity: 7.0,
+            sprites: SpriteParams::default(),
+            coloring: None,
+            post_processing: PostParams::default(),
+            trails: None,
+            render_mode: RenderMode::Points,
+            density: DensityParams::default(),
+            depth_mode: DepthMode::default(),
+            render_quality: RenderQuality::default(),
+            recording: RecordingParams::default(),
+            external_potentials: Vec::new(),
+            boundary: Boundary::Open,
+            gravity_solver: GravitySolver::DirectSum,
+            cosmology: None,
+            initial_conditions: InitialConditions::default(),
+            force_model: ForceModel::Newtonian,
+            post_newtonian: None,
+            hydrodynamics: None,
+            sinks: None,
+            accretion_rate_interval: 1.,
+            escape_criterion: None,
+            regularization: None,
+            compensated_summation: false,
         }
     }
 }
@@ -90,18 +178,61 @@ impl CursorState {
     }
 }
 
+/// Frame timings, logged as a mean and worst case every `WINDOW` frames so
+/// renderer changes can be compared
+#[derive(Debug, Default)]
+struct FrameTimes {
+    /// Cpu time spent recording and submitting in `Graphics::render`
+    render: Duration,
+    worst_render: Duration,
+    /// Time between frames, including waiting on the gpu and presentation
+    interval: Duration,
+    worst_interval: Duration,
+    count: u32,
+}
+
+impl FrameTimes {
+    const WINDOW: u32 = 300;
+    fn record(&mut self, render: Duration, interval: Duration) {
+        self.render += render;
+        self.worst_render = self.worst_render.max(render);
+        self.interval += interval;
+        self.worst_interval = self.worst_interval.max(interval);
+        self.count += 1;
+        if self.count < Self::WINDOW {
+            return;
+        }
+        info!(
+            "Frame times over {} frames - render: mean {:?}, worst {:?} - interval: mean {:?}, worst {:?}",
+            self.count,
+            self.render / self.count,
+            self.worst_render,
+            self.interval / self.count,
+            self.worst_interval,
+        );
+        *self = Self::default();
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct App<'app> {
     window: Option<Arc<Window>>,
     graphics: Option<crate::graphics::Graphics<'app>>,
     camera: Option<Camera<ViewModeLookAt>>,
     previous_frame: Option<std::time::Instant>,
+    frame_times: FrameTimes,
     cursor_state: CursorState,
     f11_state: bool,
     options: UserOptions,
+    snapshot_count: usize,
+    screenshot_count: usize,
 }
 
 impl<'app> App<'app> {
+    /// Applies a scenario file to the options, before the window is created
+    pub fn load_scenario(&mut self, path: impl AsRef<std::path::Path>) -> Result<()> {
+        self.options.load_scenario(path)
+    }
     fn create_camera(aspect_ratio: f32) -> Camera<ViewModeLookAt> {
         Camera::<ViewModeLookAt>::new(
             vec3(0., 0., 0.),
@@ -109,9 +240,55 @@ impl<'app> App<'app> {
             Vec3::Y,
             2.,
             aspect_ratio,
-            0.05,
+            // Reversed-Z keeps its precision with a near plane this close
+            1e-4,
         )
     }
+    /// Applies `quality`, keeping the options to what was supported
+    fn set_render_quality(&mut self, quality: RenderQuality) {
+        let graphics = self.graphics.as_mut().unwrap();
+        graphics.set_render_quality(quality);
+        self.options.render_quality = *graphics.render_quality();
+        info!("Render quality: {:?}", self.options.render_quality);
+    }
+    /// Matches the camera to the recording's frames while there is one,
+    /// otherwise to the window
+    fn update_aspect_ratio(&mut self) {
+        let (width, height) = match self.graphics.as_ref().unwrap().recording() {
+            Some(recording) => (recording.params().width, recording.params().height),
+            None => self.window.as_ref().unwrap().inner_size().into(),
+        };
+        self.camera.as_mut().unwrap().aspect_ratio = width as f32 / height as f32;
+    }
+    fn toggle_recording(&mut self) {
+        let graphics = self.graphics.as_mut().unwrap();
+        let toggled = match graphics.recording() {
+            Some(_) => graphics.stop_recording(),
+            None => graphics
+                .start_recording(self.options.recording.clone())
+                .inspect(|()| info!("Recording {:?}", self.options.recording)),
+        };
+        if let Err(e) = toggled {
+            error!("Failed to toggle recording: {:?}", e);
+        }
+        self.update_aspect_ratio();
+    }
+    /// Shows the simulation time and view distance in physical units, and
+    /// redshift in an expanding run
+    fn update_title(&self) {
+        let graphics = self.graphics.as_ref().unwrap();
+        let units = &self.options.units;
+        let distance = self.camera.as_ref().unwrap().get_orientation().length();
+        let mut title = format!(
+            "Gravity Simulation - t = {}, view distance = {}",
+            units.format_time(graphics.simulation_time()),
+            units.format_length(distance as f64)
+        );
+        if let Some(expansion) = graphics.expansion() {
+            title += &format!(", z = {:.3}", expansion.redshift());
+        }
+        self.window.as_ref().unwrap().set_title(&title);
+    }
     fn process_frame(&mut self, delta: f32) -> Result<()> {
         trace!("FPS: {:?}", 1. / delta);
 
@@ -144,14 +321,16 @@ impl<'app> App<'app> {
             self.camera.as_mut().unwrap().zoom(scroll);
         }
 
+        self.update_title();
+
         Ok(())
     }
 }
 
 impl<'app> ApplicationHandler for App<'app> {
     fn resumed(&mut self, event_loop: &winit::event_loop::ActiveEventLoop) {
-        use std::time::{Duration, Instant};
         use std::collections::HashMap;
+        use std::time::{Duration, Instant};
 
         let mut start = Instant::now();
         let mut times: HashMap<&'static str, Duration> = HashMap::new();
@@ -176,10 +355,116 @@ impl<'app> ApplicationHandler for App<'app> {
         start = Instant::now();
 
         self.graphics = Some(
-            Graphics::new(self.window.as_ref().unwrap().clone(), instance)
-                .with_context(|| "failed to create window")
-                .unwrap(),
+            Graphics::new(
+                self.window.as_ref().unwrap().clone(),
+                instance,
+                &self.options.initial_conditions,
+                &self.options.units,
+            )
+            .with_context(|| "failed to create window")
+            .unwrap(),
         );
+        if let Some(time_step) = self.options.time_step {
+            self.graphics.as_mut().unwrap().set_time_step(time_step);
+        }
+        let restricted_three_body = match &self.options.initial_conditions {
+            InitialConditions::RestrictedThreeBody(params) => Some(params.clone()),
+            _ => None,
+        };
+        // The primaries are static potentials in the rotating frame
+        let mut potentials = self.options.external_potentials.clone();
+        if let Some(params) = &restricted_three_body {
+            potentials.extend(params.potentials());
+        }
+        self.graphics
+            .as_mut()
+            .unwrap()
+            .set_external_potentials(potentials);
+        self.graphics
+            .as_mut()
+            .unwrap()
+            .set_restricted_three_body(restricted_three_body);
+        self.graphics
+            .as_mut()
+            .unwrap()
+            .set_boundary(self.options.boundary);
+        self.graphics
+            .as_mut()
+            .unwrap()
+            .set_gravity_solver(self.options.gravity_solver)
+            .with_context(|| "Failed to set gravity solver")
+            .unwrap();
+        self.graphics
+            .as_mut()
+            .unwrap()
+            .set_cosmology(self.options.cosmology);
+        self.graphics
+            .as_mut()
+            .unwrap()
+            .set_force_model(self.options.force_model.clone())
+            .with_context(|| "Failed to set force model")
+            .unwrap();
+        self.graphics
+            .as_mut()
+            .unwrap()
+            .set_post_newtonian(self.options.post_newtonian.clone())
+            .with_context(|| "Failed to set post newtonian corrections")
+            .unwrap();
+        self.graphics
+            .as_mut()
+            .unwrap()
+            .set_hydrodynamics(self.options.hydrodynamics);
+        self.graphics
+            .as_mut()
+            .unwrap()
+            .set_sinks(self.options.sinks);
+        self.graphics
+            .as_mut()
+            .unwrap()
+            .set_escape_criterion(self.options.escape_criterion)
+            .with_context(|| "Failed to set escape criterion")
+            .unwrap();
+        self.graphics
+            .as_mut()
+            .unwrap()
+            .set_regularization(self.options.regularization)
+            .with_context(|| "Failed to set regularization")
+            .unwrap();
+        self.graphics
+            .as_mut()
+            .unwrap()
+            .set_compensated_summation(self.options.compensated_summation);
+        self.graphics
+            .as_mut()
+            .unwrap()
+            .set_sprites(self.options.sprites);
+        self.graphics
+            .as_mut()
+            .unwrap()
+            .set_coloring(self.options.coloring);
+        self.graphics
+            .as_mut()
+            .unwrap()
+            .set_post_processing(self.options.post_processing);
+        self.graphics
+            .as_mut()
+            .unwrap()
+            .set_trails(self.options.trails.clone());
+        self.graphics
+            .as_mut()
+            .unwrap()
+            .set_density(self.options.density);
+        self.graphics
+            .as_mut()
+            .unwrap()
+            .set_render_mode(self.options.render_mode)
+            .with_context(|| "Failed to set render mode")
+            .unwrap();
+        self.graphics
+            .as_mut()
+            .unwrap()
+            .set_depth_mode(self.options.depth_mode);
+        self.set_render_quality(self.options.render_quality);
         times.insert("Graphics Instanciation", start.elapsed());
         // start = Instant::now();
 
@@ -216,32 +501,42 @@ impl<'app> ApplicationHandler for App<'app> {
             WindowEvent::MouseInput {
                 device_id,
                 state,
-                button,
+                button: winit::event::MouseButton::Left,
             } => {
-                if let winit::event::MouseButton::Left = button {
-                    self.cursor_state.set_pressed(state.is_pressed());
-                    // Clear current delta, so there isn't a jump every time
-                    // there is a left click
-                    let _ = self.cursor_state.pop_delta();
-                }
+                self.cursor_state.set_pressed(state.is_pressed());
+                // Clear current delta, so there isn't a jump every time
+                // there is a left click
+                let _ = self.cursor_state.pop_delta();
             }
             WindowEvent::Resized(size) => {
                 self.graphics.as_mut().unwrap().resize(size);
-                self.camera.as_mut().unwrap().aspect_ratio = size.width as f32 / size.height as f32;
+                self.update_aspect_ratio();
                 // self.window.as_ref().unwrap().request_redraw();
             }
             WindowEvent::RedrawRequested => {
                 let now = std::time::Instant::now();
-                let delta = now
-                    .duration_since(self.previous_frame.unwrap())
-                    .as_secs_f32();
+                let interval = now.duration_since(self.previous_frame.unwrap());
+                let delta = interval.as_secs_f32();
 
                 self.process_frame(delta).unwrap();
-                self.graphics
-                    .as_mut()
-                    .unwrap()
-                    .render(self.camera.as_ref().unwrap())
-                    .unwrap();
+                let render_start = std::time::Instant::now();
+                let graphics = self.graphics.as_mut().unwrap();
+                if graphics.recording().is_some() {
+                    // Frames go to the recording instead of the window,
+                    // one fixed time step apart however long they take
+                    if let Err(e) = graphics.record_frame(self.camera.as_ref().unwrap()) {
+                        error!("Stopping recording: {:?}", e);
+                        if let Err(e) = graphics.stop_recording() {
+                            error!("Failed to stop recording: {:?}", e);
+                        }
+                    }
+                    if graphics.recording().is_none() {
+                        self.update_aspect_ratio();
+                    }
+                } else {
+                    graphics.render(self.camera.as_ref().unwrap()).unwrap();
+                }
+                self.frame_times.record(render_start.elapsed(), interval);
                 self.window.as_ref().unwrap().request_redraw();
                 self.previous_frame = Some(now);
             }
@@ -257,8 +552,10 @@ impl<'app> ApplicationHandler for App<'app> {
                 if let winit::keyboard::PhysicalKey::Code(code) = event.physical_key {
                     match code {
                         winit::keyboard::KeyCode::F11 => {
-                            if event.state.is_pressed() != self.f11_state && event.state.is_pressed() {
-                                if let Some(_) = self.window.as_ref().unwrap().clone().fullscreen() {
+                            if event.state.is_pressed() != self.f11_state
+                                && event.state.is_pressed()
+                            {
+                                if self.window.as_ref().unwrap().clone().fullscreen().is_some() {
                                     self.window.as_ref().unwrap().clone().set_fullscreen(None);
                                 } else {
                                     self.window.as_ref().unwrap().clone().set_fullscreen(Some(
@@ -268,6 +565,211 @@ impl<'app> ApplicationHandler for App<'app> {
                             }
                             self.f11_state = event.state.is_pressed();
                         }
+                        winit::keyboard::KeyCode::KeyE
+                            if event.state.is_pressed() && !event.repeat =>
+                        {
+                            match self.graphics.as_ref().unwrap().measure_energy() {
+                                Ok(energy) => {
+                                    info!("Energy: {:?}, Total: {:?}", energy, energy.total())
+                                }
+                                Err(e) => error!("Failed to measure energy: {:?}", e),
+                            }
+                        }
+                        winit::keyboard::KeyCode::KeyS
+                            if event.state.is_pressed() && !event.repeat =>
+                        {
+                            let path = format!("snapshot_{:04}.txt", self.snapshot_count);
+                            match self.graphics.as_ref().unwrap().write_snapshot(&path) {
+                                Ok(()) => {
+                                    info!("Wrote snapshot {:?}", path);
+                                    self.snapshot_count += 1;
+                                }
+                                Err(e) => error!("Failed to write snapshot: {:?}", e),
+                            }
+                        }
+                        winit::keyboard::KeyCode::F12
+                            if event.state.is_pressed() && !event.repeat =>
+                        {
+                            let path = format!("screenshot_{:04}.png", self.screenshot_count);
+                            let size = self.window.as_ref().unwrap().inner_size();
+                            let screenshot = self
+                                .graphics
+                                .as_mut()
+                                .unwrap()
+                                .render_to_image(
+                                    self.camera.as_ref().unwrap(),
+                                    size.width,
+                                    size.height,
+                                )
+                                .and_then(|image| image.write_png(&path));
+                            match screenshot {
+                                Ok(()) => {
+                                    info!("Wrote screenshot {:?}", path);
+                                    self.screenshot_count += 1;
+                                }
+                                Err(e) => error!("Failed to write screenshot: {:?}", e),
+                            }
+                        }
+                        winit::keyboard::KeyCode::F9
+                            if event.state.is_pressed() && !event.repeat =>
+                        {
+                            self.toggle_recording();
+                        }
+                        winit::keyboard::KeyCode::KeyA
+                            if event.state.is_pressed() && !event.repeat =>
+                        {
+                            let graphics = self.graphics.as_ref().unwrap();
+                            let written = graphics.accretion_log().and_then(|log| {
+                                info!(
+                                    "{} bodies accreted, {} mass in total",
+                                    log.events.len(),
+                                    log.total_mass()
+                                );
+                                log.write("accretion_log.txt")?;
+                                log.write_rate_series(
+                                    "accretion_rate.txt",
+                                    self.options.accretion_rate_interval,
+                                    graphics.simulation_time(),
+                                )
+                            });
+                            if let Err(e) = written {
+                                error!("Failed to write accretion log: {:?}", e);
+                            }
+                        }
+                        winit::keyboard::KeyCode::KeyX
+                            if event.state.is_pressed() && !event.repeat =>
+                        {
+                            match self.graphics.as_ref().unwrap().escapes() {
+                                Some(escapes) => match escapes.write("escapes.txt") {
+                                    Ok(()) => info!("{} escapes recorded", escapes.records.len()),
+                                    Err(e) => error!("Failed to write escapes: {:?}", e),
+                                },
+                                None => warn!("Escapes are not being watched for"),
+                            }
+                        }
+                        winit::keyboard::KeyCode::KeyC
+                            if event.state.is_pressed() && !event.repeat =>
+                        {
+                            // Cycles through the quantities, then back to white
+                            self.options.coloring = match self.options.coloring {
+                                None => Some(ColorParams::default()),
+                                Some(params)
+                                    if params.quantity.next()
+                                        == ColorParams::default().quantity =>
+                                {
+                                    None
+                                }
+                                Some(params) => Some(ColorParams {
+                                    quantity: params.quantity.next(),
+                                    ..params
+                                }),
+                            };
+                            info!("Coloring: {:?}", self.options.coloring);
+                            self.graphics
+                                .as_mut()
+                                .unwrap()
+                                .set_coloring(self.options.coloring);
+                        }
+                        winit::keyboard::KeyCode::KeyH
+                            if event.state.is_pressed() && !event.repeat =>
+                        {
+                            let params = &mut self.options.post_processing;
+                            params.auto_exposure = !params.auto_exposure;
+                            info!("Auto exposure: {}", params.auto_exposure);
+                            self.graphics
+                                .as_mut()
+                                .unwrap()
+                                .set_post_processing(self.options.post_processing);
+                        }
+                        winit::keyboard::KeyCode::KeyT
+                            if event.state.is_pressed() && !event.repeat =>
+                        {
+                            let graphics = self.graphics.as_mut().unwrap();
+                            let trails = match graphics.trails() {
+                                Some(_) => None,
+                                None => Some(self.options.trails.clone().unwrap_or_default()),
+                            };
+                            info!("Trails: {}", trails.is_some());
+                            graphics.set_trails(trails);
+                        }
+                        winit::keyboard::KeyCode::KeyV
+                            if event.state.is_pressed() && !event.repeat =>
+                        {
+                            let graphics = self.graphics.as_mut().unwrap();
+                            let mode = graphics.render_mode().next();
+                            info!("Render mode: {:?}", mode);
+                            if let Err(e) = graphics.set_render_mode(mode) {
+                                error!("Failed to set render mode: {:?}", e);
+                            }
+                        }
+                        winit::keyboard::KeyCode::KeyM
+                            if event.state.is_pressed() && !event.repeat =>
+                        {
+                            if let Some(params) = &mut self.options.coloring {
+                                params.colormap = params.colormap.next();
+                                info!("Colormap: {:?}", params.colormap);
+                            }
+                            self.graphics
+                                .as_mut()
+                                .unwrap()
+                                .set_coloring(self.options.coloring);
+                        }
+                        winit::keyboard::KeyCode::KeyL
+                            if event.state.is_pressed() && !event.repeat =>
+                        {
+                            if let Some(params) = &mut self.options.coloring {
+                                params.log_scale = !params.log_scale;
+                                info!("Logarithmic color scale: {}", params.log_scale);
+                            }
+                            self.graphics
+                                .as_mut()
+                                .unwrap()
+                                .set_coloring(self.options.coloring);
+                        }
+                        winit::keyboard::KeyCode::KeyQ
+                            if event.state.is_pressed() && !event.repeat =>
+                        {
+                            let supported =
+                                self.graphics.as_ref().unwrap().supported_msaa_samples();
+                            let samples = self.options.render_quality.msaa_samples;
+                            let msaa_samples = supported
+                                .iter()
+                                .copied()
+                                .find(|&count| count > samples)
+                                .unwrap_or(1);
+                            self.set_render_quality(RenderQuality {
+                                msaa_samples,
+                                ..self.options.render_quality
+                            });
+                        }
+                        winit::keyboard::KeyCode::KeyR
+                            if event.state.is_pressed() && !event.repeat =>
+                        {
+                            let scale = self.options.render_quality.render_scale;
+                            let render_scale = [0.5, 1., 1.5, 2.]
+                                .into_iter()
+                                .find(|&step| step > scale)
+                                .unwrap_or(0.5);
+                            self.set_render_quality(RenderQuality {
+                                render_scale,
+                                ..self.options.render_quality
+                            });
+                        }
+                        winit::keyboard::KeyCode::KeyP
+                            if event.state.is_pressed() && !event.repeat =>
+                        {
+                            let supported =
+                                self.graphics.as_ref().unwrap().supported_present_modes();
+                            // Vsync is always supported, so this stops
+                            let mut present_mode = self.options.render_quality.present_mode.next();
+                            while !supported.contains(&present_mode) {
+                                present_mode = present_mode.next();
+                            }
+                            self.set_render_quality(RenderQuality {
+                                present_mode,
+                                ..self.options.render_quality
+                            });
+                        }
                         _ => (),
                     }
                 }
@@ -0,0 +1,109 @@
+use crate::graphics::vertices::InitialConditions;
+use crate::physics::{
+    periodic::Boundary,
+    units::{Dimension, UnitSystem},
+};
+use crate::prelude::*;
+
+use super::UserOptions;
+
+impl UserOptions {
+    /// Reads `name = value` lines, see `set_option`. Blank lines and anything
+    /// after a `#` are skipped, and the lines apply in order so `units` should
+    /// come first
+    pub fn load_scenario(&mut self, path: impl AsRef<std::path::Path>) -> Result<()> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read scenario {:?}", path))?;
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let Some((name, value)) = line.split_once('=') else {
+                bail!("Expected name = value on line {} of {:?}", number + 1, path);
+            };
+            self.set_option(name.trim(), value.trim())
+                .with_context(|| format!("On line {} of {:?}", number + 1, path))?;
+        }
+        Ok(())
+    }
+    /// Sets a physical option from a quantity like "8.5 kpc" or "220 km/s",
+    /// converted to the current units. A bare number is already in them.
+    /// `units` picks the unit system, one of astronomical, galactic or si
+    pub fn set_option(&mut self, name: &str, value: &str) -> Result<()> {
+        let units = self.units;
+        let parse = |dimension| units.parse(value, dimension).map(|v| v as f32);
+        match (name, &mut self.initial_conditions) {
+            ("units", _) => {
+                self.units = match value {
+                    "astronomical" => UnitSystem::ASTRONOMICAL,
+                    "galactic" => UnitSystem::GALACTIC,
+                    "si" => UnitSystem::SI,
+                    _ => bail!("Unknown unit system {:?}", value),
+                }
+            }
+            ("time_step", _) => self.time_step = Some(parse(Dimension::TIME)?),
+            ("box_size", _) => {
+                self.boundary = Boundary::Periodic {
+                    box_size: parse(Dimension::LENGTH)?,
+                }
+            }
+            ("max_radius", InitialConditions::Galaxy(params)) => {
+                params.max_radius = parse(Dimension::LENGTH)?
+            }
+            ("separation", InitialConditions::RestrictedThreeBody(params)) => {
+                params.separation = parse(Dimension::LENGTH)?
+            }
+            ("primary_mass", InitialConditions::RestrictedThreeBody(params)) => {
+                params.primary_mass = parse(Dimension::MASS)?
+            }
+            ("secondary_mass", InitialConditions::RestrictedThreeBody(params)) => {
+                params.secondary_mass = parse(Dimension::MASS)?
+            }
+            ("speed_of_light", _) => {
+                let Some(post_newtonian) = &mut self.post_newtonian else {
+                    bail!("speed_of_light needs the post newtonian corrections enabled");
+                };
+                post_newtonian.speed_of_light = parse(Dimension::VELOCITY)?
+            }
+            _ => bail!(
+                "Unknown option {:?} for {:?}",
+                name,
+                self.initial_conditions
+            ),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::physics::restricted_three_body::RestrictedThreeBody;
+
+    #[test]
+    fn options_take_physical_units() {
+        let mut options = UserOptions {
+            initial_conditions: InitialConditions::RestrictedThreeBody(
+                RestrictedThreeBody::default(),
+            ),
+            ..Default::default()
+        };
+        options.set_option("units", "galactic").unwrap();
+        options.set_option("separation", "500 pc").unwrap();
+        options.set_option("time_step", "1 Myr").unwrap();
+        options.set_option("box_size", "3").unwrap();
+        let InitialConditions::RestrictedThreeBody(params) = &options.initial_conditions else {
+            unreachable!();
+        };
+        assert!((params.separation - 0.5).abs() < 1e-6);
+        assert!((options.time_step.unwrap() - 1e-3).abs() < 1e-9);
+        assert_eq!(options.boundary, Boundary::Periodic { box_size: 3. });
+
+        // A length where a time is expected
+        assert!(options.set_option("time_step", "1 kpc").is_err());
+        // Only the galaxy has a max radius
+        assert!(options.set_option("max_radius", "1 kpc").is_err());
+    }
+}
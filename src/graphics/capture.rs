@@ -0,0 +1,109 @@
+use std::{fs::File, io::BufWriter, path::Path};
+
+use super::vertices::read_buffer;
+use crate::prelude::*;
+
+/// Format drawn into when there's no surface to match
+pub const OUTPUT_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+/// A frame read back from the gpu, 8 bit sRGB RGBA rows from the top
+#[derive(Debug, Clone)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+impl Image {
+    pub fn write_png(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let file = File::create(path).with_context(|| format!("Failed to create {path:?}"))?;
+        let mut encoder = png::Encoder::new(BufWriter::new(file), self.width, self.height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_source_srgb(png::SrgbRenderingIntent::Perceptual);
+        encoder
+            .write_header()
+            .and_then(|mut writer| writer.write_image_data(&self.pixels))
+            .with_context(|| format!("Failed to write {path:?}"))
+    }
+}
+
+/// A texture the scene can be drawn into and then read back from
+pub fn generate_output_texture(
+    device: &wgpu::Device,
+    format: wgpu::TextureFormat,
+    width: u32,
+    height: u32,
+) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Output"),
+        size: wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        view_formats: &[],
+    })
+}
+
+/// Copies an 8 bit RGBA or BGRA texture back to the cpu. Stalls like
+/// `read_buffer`
+pub fn read_texture(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    texture: &wgpu::Texture,
+) -> Result<Image> {
+    use wgpu::TextureFormat as TF;
+    let bgra = match texture.format() {
+        TF::Rgba8Unorm | TF::Rgba8UnormSrgb => false,
+        TF::Bgra8Unorm | TF::Bgra8UnormSrgb => true,
+        format => bail!("Can't read back {format:?} textures"),
+    };
+    let (width, height) = (texture.width(), texture.height());
+    // Rows are copied at a fixed alignment, the padding dropped below
+    let row_bytes = 4 * width;
+    let padded_row_bytes = row_bytes.next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+    let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Readback"),
+        size: (padded_row_bytes * height) as u64,
+        usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+    let mut encoder = device.create_command_encoder(&Default::default());
+    encoder.copy_texture_to_buffer(
+        texture.as_image_copy(),
+        wgpu::TexelCopyBufferInfo {
+            buffer: &buffer,
+            layout: wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(padded_row_bytes),
+                rows_per_image: None,
+            },
+        },
+        texture.size(),
+    );
+    queue.submit(Some(encoder.finish()));
+
+    let padded = read_buffer::<u8>(&buffer, device)?;
+    let mut pixels: Vec<u8> = padded
+        .chunks_exact(padded_row_bytes as usize)
+        .flat_map(|row| &row[..row_bytes as usize])
+        .copied()
+        .collect();
+    if bgra {
+        pixels
+            .chunks_exact_mut(4)
+            .for_each(|pixel| pixel.swap(0, 2));
+    }
+    Ok(Image {
+        width,
+        height,
+        pixels,
+    })
+}
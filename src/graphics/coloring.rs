@@ -0,0 +1,331 @@
+use std::borrow::Cow;
+
+use bytemuck::bytes_of;
+
+use crate::physics::sph::{CELL_CAPACITY, HASH_TABLE_SIZE};
+use crate::prelude::*;
+
+/// The per body scalar the bodies are coloured by
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum ColorQuantity {
+    #[default]
+    Speed,
+    Mass,
+    KineticEnergy,
+    /// Mass within `ColorParams::density_radius` over the volume it encloses,
+    /// found through a spatial hash so it's linear in the body count
+    LocalDensity,
+    /// Arbitrary per body labels, e.g. the galaxy each body started in, see
+    /// `Graphics::set_body_tags`
+    Tag,
+    Index,
+}
+
+impl ColorQuantity {
+    /// The quantity after this one, for cycling through them at run time
+    pub fn next(self) -> Self {
+        match self {
+            Self::Speed => Self::Mass,
+            Self::Mass => Self::KineticEnergy,
+            Self::KineticEnergy => Self::LocalDensity,
+            Self::LocalDensity => Self::Tag,
+            Self::Tag => Self::Index,
+            Self::Index => Self::Speed,
+        }
+    }
+    /// Mirrors the `QUANTITY_*` constants in coloring.wgsl
+    fn uniform_value(self) -> u32 {
+        match self {
+            Self::Speed => 0,
+            Self::Mass => 1,
+            Self::KineticEnergy => 2,
+            Self::LocalDensity => 3,
+            Self::Tag => 4,
+            Self::Index => 5,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum Colormap {
+    #[default]
+    Viridis,
+    Inferno,
+    /// Blue through grey to red, for quantities with a meaningful midpoint
+    Diverging,
+}
+
+impl Colormap {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Viridis => Self::Inferno,
+            Self::Inferno => Self::Diverging,
+            Self::Diverging => Self::Viridis,
+        }
+    }
+    /// Mirrors the `COLORMAP_*` constants in colormaps.wgsl, zero is plain white
+    pub fn uniform_value(colormap: Option<Self>) -> u32 {
+        match colormap {
+            None => 0,
+            Some(Self::Viridis) => 1,
+            Some(Self::Inferno) => 2,
+            Some(Self::Diverging) => 3,
+        }
+    }
+}
+
+/// Compiles `source` with the `colormap` function of colormaps.wgsl appended
+pub fn generate_colormapped_shader(
+    device: &wgpu::Device,
+    label: &str,
+    source: &str,
+) -> wgpu::ShaderModule {
+    let source = format!(
+        "{}\n{}",
+        source,
+        include_str!("../../shaders/colormaps.wgsl")
+    );
+    device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some(label),
+        source: wgpu::ShaderSource::Wgsl(Cow::Owned(source)),
+    })
+}
+
+#[derive(Builder, Debug, Clone, Copy, PartialEq)]
+#[builder(default)]
+pub struct ColorParams {
+    pub quantity: ColorQuantity,
+    pub colormap: Colormap,
+    /// Maps the logarithm of the quantity, for ones spanning decades
+    pub log_scale: bool,
+    /// Radius of the sphere the local density is measured in
+    pub density_radius: f32,
+}
+
+impl Default for ColorParams {
+    fn default() -> Self {
+        Self {
+            quantity: ColorQuantity::default(),
+            colormap: Colormap::default(),
+            log_scale: false,
+            density_radius: 0.05,
+        }
+    }
+}
+
+/// Mirrors the `Coloring` struct in coloring.wgsl
+#[derive(Debug, Pod, Zeroable, Copy, Clone)]
+#[repr(C)]
+struct ColorUniform {
+    quantity: u32,
+    log_scale: u32,
+    density_radius: f32,
+    padding: u32,
+}
+
+/// Computes the chosen quantity of each body and scales it to [0, 1] over
+/// its range this frame. The scaled values are read by the sprite pipeline
+/// as a per instance vertex buffer. Like the sinks, the passes read the
+/// bodies through groups 0 and 1 and the colouring state is at group 2
+#[derive(Debug)]
+pub struct Coloring {
+    /// None draws every body white
+    params: Option<ColorParams>,
+    uniform: wgpu::Buffer,
+    values: wgpu::Buffer,
+    range: wgpu::Buffer,
+    tags: wgpu::Buffer,
+    cell_counts: wgpu::Buffer,
+    cell_bodies: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    insert_pipeline: wgpu::ComputePipeline,
+    values_pipeline: wgpu::ComputePipeline,
+    normalize_pipeline: wgpu::ComputePipeline,
+    body_count: usize,
+}
+
+impl Coloring {
+    /// Entries of group 2, the uniform, the values, their range, the tags and
+    /// the neighbour grid
+    pub fn generate_bind_group_layout_entries() -> Vec<wgpu::BindGroupLayoutEntry> {
+        let entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: match binding {
+                    0 => wgpu::BufferBindingType::Uniform,
+                    3 => wgpu::BufferBindingType::Storage { read_only: true },
+                    _ => wgpu::BufferBindingType::Storage { read_only: false },
+                },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        (0..6).map(entry).collect()
+    }
+    fn generate_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Coloring"),
+            entries: &Self::generate_bind_group_layout_entries(),
+        })
+    }
+    /// `body_layout` and `simulation_layout` are the layouts of groups 0 and 1
+    /// of the other compute pipelines
+    pub fn new(
+        device: &wgpu::Device,
+        body_layout: &wgpu::BindGroupLayout,
+        simulation_layout: &wgpu::BindGroupLayout,
+        body_count: usize,
+    ) -> Self {
+        let buffer = |label, size, usage| {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size: size as u64,
+                usage,
+                mapped_at_creation: false,
+            })
+        };
+        use wgpu::BufferUsages as BU;
+        let uniform = buffer(
+            "Coloring Uniform",
+            size_of::<ColorUniform>(),
+            BU::UNIFORM | BU::COPY_DST,
+        );
+        let values = buffer(
+            "Color Values",
+            size_of::<f32>() * body_count.max(1),
+            BU::STORAGE | BU::VERTEX,
+        );
+        let range = buffer(
+            "Color Range",
+            size_of::<[u32; 2]>(),
+            BU::STORAGE | BU::COPY_DST,
+        );
+        let tags = buffer(
+            "Body Tags",
+            size_of::<u32>() * body_count.max(1),
+            BU::STORAGE | BU::COPY_DST,
+        );
+        let cell_counts = buffer(
+            "Coloring Cell Counts",
+            size_of::<u32>() * HASH_TABLE_SIZE as usize,
+            BU::STORAGE | BU::COPY_DST,
+        );
+        let cell_bodies = buffer(
+            "Coloring Cell Bodies",
+            size_of::<u32>() * (HASH_TABLE_SIZE * CELL_CAPACITY) as usize,
+            BU::STORAGE,
+        );
+
+        let color_layout = Self::generate_bind_group_layout(device);
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Coloring"),
+            layout: &color_layout,
+            entries: &[&uniform, &values, &range, &tags, &cell_counts, &cell_bodies]
+                .iter()
+                .enumerate()
+                .map(|(binding, buffer)| wgpu::BindGroupEntry {
+                    binding: binding as u32,
+                    resource: buffer.as_entire_binding(),
+                })
+                .collect::<Vec<_>>(),
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Coloring"),
+            bind_group_layouts: &[body_layout, simulation_layout, &color_layout],
+            push_constant_ranges: &[],
+        });
+        let module = device.create_shader_module(include_wgsl!("../../shaders/coloring.wgsl"));
+        let pipeline = |entry_point: &str| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(entry_point),
+                layout: Some(&layout),
+                module: &module,
+                entry_point: Some(entry_point),
+                compilation_options: Default::default(),
+                cache: None,
+            })
+        };
+
+        Self {
+            insert_pipeline: pipeline("insert_bodies"),
+            values_pipeline: pipeline("compute_values"),
+            normalize_pipeline: pipeline("normalize_values"),
+            params: None,
+            uniform,
+            values,
+            range,
+            tags,
+            cell_counts,
+            cell_bodies,
+            bind_group,
+            body_count,
+        }
+    }
+    pub fn params(&self) -> Option<&ColorParams> {
+        self.params.as_ref()
+    }
+    pub fn set_params(&mut self, params: Option<ColorParams>) {
+        self.params = params;
+    }
+    /// The scaled values, one f32 per body
+    pub fn values(&self) -> &wgpu::Buffer {
+        &self.values
+    }
+    pub fn set_tags(&self, queue: &wgpu::Queue, tags: &[u32]) -> Result<()> {
+        if tags.len() != self.body_count {
+            bail!(
+                "Expected one tag per body ({}), got {}",
+                self.body_count,
+                tags.len()
+            );
+        }
+        queue.write_buffer(&self.tags, 0, bytemuck::cast_slice(tags));
+        Ok(())
+    }
+    /// Records the passes that fill the values buffer, nothing while
+    /// colouring is off
+    pub fn encode(
+        &self,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        body_bind_group: &wgpu::BindGroup,
+        simulation_bind_group: &wgpu::BindGroup,
+        body_count: u32,
+    ) {
+        let Some(params) = self.params else {
+            return;
+        };
+        let uniform = ColorUniform {
+            quantity: params.quantity.uniform_value(),
+            log_scale: params.log_scale as u32,
+            density_radius: params.density_radius,
+            padding: 0,
+        };
+        queue.write_buffer(&self.uniform, 0, bytes_of(&uniform));
+        // An empty range, widened by every body
+        queue.write_buffer(&self.range, 0, bytes_of(&[u32::MAX, 0]));
+        let local_density = params.quantity == ColorQuantity::LocalDensity;
+        if local_density {
+            encoder.clear_buffer(&self.cell_counts, 0, None);
+        }
+
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Coloring"),
+            timestamp_writes: None,
+        });
+        pass.set_bind_group(0, body_bind_group, &[]);
+        pass.set_bind_group(1, simulation_bind_group, &[]);
+        pass.set_bind_group(2, &self.bind_group, &[]);
+        if local_density {
+            pass.set_pipeline(&self.insert_pipeline);
+            pass.dispatch_workgroups(body_count.div_ceil(64), 1, 1);
+        }
+        for pipeline in [&self.values_pipeline, &self.normalize_pipeline] {
+            pass.set_pipeline(pipeline);
+            pass.dispatch_workgroups(body_count.div_ceil(64), 1, 1);
+        }
+    }
+}
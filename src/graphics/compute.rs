@@ -1,7 +1,7 @@
 #![allow(dead_code, unused_variables)]
 
 // use crate::graphics::vertices::Verticies;
-use crate::prelude::*;
+// use crate::prelude::*;
 
 const INVERSE_SINGULARITY_THRESHOLD: f32 = 5e2;
 
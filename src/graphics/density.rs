@@ -0,0 +1,635 @@
+use bytemuck::bytes_of;
+
+use super::coloring::{self, Colormap};
+use super::post_processing::HDR_FORMAT;
+use super::rendering;
+use super::vertices::{read_buffer, BodyData, Compute};
+use crate::physics::particle_mesh::ParticleMesh;
+use crate::prelude::*;
+
+/// How the bodies are drawn
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum RenderMode {
+    /// A sprite per body
+    #[default]
+    Points,
+    /// The projected density, from a gaussian kernel splatted per body
+    Density,
+    /// Rays marched through the density deposited on a 3D grid
+    Volume,
+}
+
+impl RenderMode {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Points => Self::Density,
+            Self::Density => Self::Volume,
+            Self::Volume => Self::Points,
+        }
+    }
+}
+
+#[derive(Builder, Debug, Clone, Copy, PartialEq)]
+#[builder(default)]
+pub struct DensityParams {
+    /// World radius of each body's kernel in the density mode
+    pub kernel_size: f32,
+    /// Kernels never shrink below this radius in pixels
+    pub min_kernel_pixels: f32,
+    /// Orders of magnitude below the peak density that are shown
+    pub decades: f32,
+    pub colormap: Colormap,
+    /// Half width of the cube the volume covers, centred on the origin.
+    /// Bodies outside it are left out
+    pub extent: f32,
+    /// Cells along each side of the volume's grid
+    pub resolution: u32,
+    /// Samples along each ray through the volume
+    pub steps: u32,
+    /// Absorption of the densest cells, per cell crossed
+    pub opacity: f32,
+}
+
+impl Default for DensityParams {
+    fn default() -> Self {
+        Self {
+            kernel_size: 0.02,
+            min_kernel_pixels: 1.5,
+            decades: 4.,
+            colormap: Colormap::Inferno,
+            extent: 2.,
+            resolution: 64,
+            steps: 128,
+            opacity: 0.5,
+        }
+    }
+}
+
+/// Mirrors the `Density` struct in density.wgsl and density_grid.wgsl
+#[derive(Debug, Pod, Zeroable, Copy, Clone)]
+#[repr(C)]
+struct DensityUniform {
+    inverse_world_mat: [[f32; 4]; 4],
+    kernel_size: f32,
+    min_kernel_pixels: f32,
+    decades: f32,
+    colormap: u32,
+    extent: f32,
+    resolution: u32,
+    steps: u32,
+    opacity: f32,
+    /// Fixed point units of the volume grid per unit of mass, see
+    /// `ParticleMesh::mass_scale`
+    mass_scale: f32,
+    padding: [u32; 3],
+}
+
+/// Half floats always blend, the splat is scaled by the heaviest body to
+/// keep it in their range
+const SPLAT_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R16Float;
+
+/// Bind groups of the splat texture and grid, rebuilt along with them
+#[derive(Debug)]
+struct Targets {
+    splat: wgpu::TextureView,
+    grid: wgpu::Buffer,
+    /// Group 2 of the compute passes
+    state_group: wgpu::BindGroup,
+    /// Group 2 of the density and volume pipelines
+    sample_group: wgpu::BindGroup,
+}
+
+/// The alternate render paths to the sprites, for runs with too many bodies
+/// to make out individually. The compute passes read the bodies through
+/// groups 0 and 1 like the sinks, with the density state at group 2. Both
+/// modes are shown on a log scale below the brightest pixel or cell
+#[derive(Debug)]
+pub struct DensityField {
+    params: DensityParams,
+    uniform: wgpu::Buffer,
+    peaks: wgpu::Buffer,
+    state_layout: wgpu::BindGroupLayout,
+    sample_layout: wgpu::BindGroupLayout,
+    /// Group 1 of the render pipelines
+    render_group: wgpu::BindGroup,
+    targets: Targets,
+    splat_pipeline: wgpu::RenderPipeline,
+    density_pipeline: wgpu::RenderPipeline,
+    volume_pipeline: wgpu::RenderPipeline,
+    heaviest_pipeline: wgpu::ComputePipeline,
+    deposit_pipeline: wgpu::ComputePipeline,
+    cell_peak_pipeline: wgpu::ComputePipeline,
+    splat_peak_pipeline: wgpu::ComputePipeline,
+    size: (u32, u32),
+    /// Bounds the mass of any one grid cell
+    total_mass: f64,
+}
+
+impl DensityField {
+    fn generate_layout_entries(
+        entries: &[(wgpu::ShaderStages, wgpu::BindingType)],
+    ) -> Vec<wgpu::BindGroupLayoutEntry> {
+        entries
+            .iter()
+            .enumerate()
+            .map(|(binding, &(visibility, ty))| wgpu::BindGroupLayoutEntry {
+                binding: binding as u32,
+                visibility,
+                ty,
+                count: None,
+            })
+            .collect()
+    }
+    fn generate_bind_group_layout(
+        device: &wgpu::Device,
+        label: &str,
+        entries: &[(wgpu::ShaderStages, wgpu::BindingType)],
+    ) -> wgpu::BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some(label),
+            entries: &Self::generate_layout_entries(entries),
+        })
+    }
+    /// Entries of group 2 of the grid passes, the uniform, grid, peaks and
+    /// splat
+    pub fn generate_state_layout_entries() -> Vec<wgpu::BindGroupLayoutEntry> {
+        Self::generate_layout_entries(&Self::STATE_ENTRIES)
+    }
+    const STATE_ENTRIES: [(wgpu::ShaderStages, wgpu::BindingType); 4] = [
+        (
+            wgpu::ShaderStages::COMPUTE,
+            Self::buffer_binding(wgpu::BufferBindingType::Uniform),
+        ),
+        (
+            wgpu::ShaderStages::COMPUTE,
+            Self::buffer_binding(wgpu::BufferBindingType::Storage { read_only: false }),
+        ),
+        (
+            wgpu::ShaderStages::COMPUTE,
+            Self::buffer_binding(wgpu::BufferBindingType::Storage { read_only: false }),
+        ),
+        (wgpu::ShaderStages::COMPUTE, Self::SPLAT_BINDING),
+    ];
+    const fn buffer_binding(ty: wgpu::BufferBindingType) -> wgpu::BindingType {
+        wgpu::BindingType::Buffer {
+            ty,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        }
+    }
+    const SPLAT_BINDING: wgpu::BindingType = wgpu::BindingType::Texture {
+        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+        view_dimension: wgpu::TextureViewDimension::D2,
+        multisampled: false,
+    };
+    #[allow(clippy::too_many_arguments)]
+    fn generate_targets(
+        device: &wgpu::Device,
+        uniform: &wgpu::Buffer,
+        peaks: &wgpu::Buffer,
+        state_layout: &wgpu::BindGroupLayout,
+        sample_layout: &wgpu::BindGroupLayout,
+        (width, height): (u32, u32),
+        resolution: u32,
+    ) -> Targets {
+        let splat = device
+            .create_texture(&wgpu::TextureDescriptor {
+                label: Some("Density Splat"),
+                size: wgpu::Extent3d {
+                    width: width.max(1),
+                    height: height.max(1),
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: SPLAT_FORMAT,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                    | wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            })
+            .create_view(&Default::default());
+        let grid = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Density Grid"),
+            size: (size_of::<u32>() * (resolution as usize).pow(3)) as u64,
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let state_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Density State"),
+            layout: state_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: grid.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: peaks.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&splat),
+                },
+            ],
+        });
+        let sample_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Density Samples"),
+            layout: sample_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&splat),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: grid.as_entire_binding(),
+                },
+            ],
+        });
+        Targets {
+            splat,
+            grid,
+            state_group,
+            sample_group,
+        }
+    }
+    /// `body_layout` and `simulation_layout` are the layouts of groups 0 and 1
+    /// of the other compute pipelines, `depth_stencil` and `multisample` those
+    /// of the scene's render pass, and `width` and `height` the scene's size.
+    /// `total_mass` bounds the mass the volume grid can hold in one cell
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        device: &wgpu::Device,
+        body_layout: &wgpu::BindGroupLayout,
+        simulation_layout: &wgpu::BindGroupLayout,
+        depth_stencil: wgpu::DepthStencilState,
+        multisample: wgpu::MultisampleState,
+        width: u32,
+        height: u32,
+        params: DensityParams,
+        total_mass: f64,
+    ) -> Self {
+        let params = DensityParams {
+            resolution: params.resolution.max(1),
+            ..params
+        };
+        let uniform = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Density Uniform"),
+            size: size_of::<DensityUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let peaks = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Density Peaks"),
+            size: size_of::<[u32; 4]>() as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        use wgpu::BufferBindingType as BT;
+        use wgpu::ShaderStages as SS;
+        let state_layout =
+            Self::generate_bind_group_layout(device, "Density State", &Self::STATE_ENTRIES);
+        let render_layout = Self::generate_bind_group_layout(
+            device,
+            "Density",
+            &[
+                (SS::VERTEX_FRAGMENT, Self::buffer_binding(BT::Uniform)),
+                (
+                    SS::VERTEX_FRAGMENT,
+                    Self::buffer_binding(BT::Storage { read_only: true }),
+                ),
+            ],
+        );
+        let sample_layout = Self::generate_bind_group_layout(
+            device,
+            "Density Samples",
+            &[
+                (SS::FRAGMENT, Self::SPLAT_BINDING),
+                (
+                    SS::FRAGMENT,
+                    Self::buffer_binding(BT::Storage { read_only: true }),
+                ),
+            ],
+        );
+        let render_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Density"),
+            layout: &render_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: peaks.as_entire_binding(),
+                },
+            ],
+        });
+
+        let uniform_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: None,
+            entries: &[rendering::Uniform::generate_bind_group_layout_entry(
+                device, 0,
+            )],
+        });
+        let module = coloring::generate_colormapped_shader(
+            device,
+            "density.wgsl",
+            include_str!("../../shaders/density.wgsl"),
+        );
+        let splat_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Density Splat"),
+            bind_group_layouts: &[&uniform_layout, &render_layout],
+            push_constant_ranges: &[],
+        });
+        let splat_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Density Splat"),
+            layout: Some(&splat_layout),
+            vertex: wgpu::VertexState {
+                module: &module,
+                entry_point: Some("vs_splat"),
+                compilation_options: Default::default(),
+                // Positions and masses, the colour values aren't needed
+                buffers: &BodyData::<Compute>::get_sprite_buffer_layouts()[..2],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &module,
+                entry_point: Some("fs_splat"),
+                compilation_options: Default::default(),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: SPLAT_FORMAT,
+                    blend: Some(wgpu::BlendState {
+                        color: wgpu::BlendComponent {
+                            src_factor: wgpu::BlendFactor::One,
+                            dst_factor: wgpu::BlendFactor::One,
+                            operation: wgpu::BlendOperation::Add,
+                        },
+                        alpha: wgpu::BlendComponent::REPLACE,
+                    }),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleStrip,
+                ..Default::default()
+            },
+            depth_stencil: None,
+            multisample: Default::default(),
+            multiview: None,
+            cache: None,
+        });
+        let display_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Density Display"),
+            bind_group_layouts: &[&uniform_layout, &render_layout, &sample_layout],
+            push_constant_ranges: &[],
+        });
+        // Full screen, drawn in the scene's pass in place of the sprites
+        let display_pipeline = |entry_point| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(entry_point),
+                layout: Some(&display_layout),
+                vertex: wgpu::VertexState {
+                    module: &module,
+                    entry_point: Some("vs_fullscreen"),
+                    compilation_options: Default::default(),
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &module,
+                    entry_point: Some(entry_point),
+                    compilation_options: Default::default(),
+                    targets: &[Some(HDR_FORMAT.into())],
+                }),
+                primitive: Default::default(),
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    depth_write_enabled: false,
+                    depth_compare: wgpu::CompareFunction::Always,
+                    ..depth_stencil.clone()
+                }),
+                multisample,
+                multiview: None,
+                cache: None,
+            })
+        };
+
+        let compute_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Density"),
+            bind_group_layouts: &[body_layout, simulation_layout, &state_layout],
+            push_constant_ranges: &[],
+        });
+        let grid_module =
+            device.create_shader_module(include_wgsl!("../../shaders/density_grid.wgsl"));
+        let compute_pipeline = |entry_point: &str| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(entry_point),
+                layout: Some(&compute_layout),
+                module: &grid_module,
+                entry_point: Some(entry_point),
+                compilation_options: Default::default(),
+                cache: None,
+            })
+        };
+
+        Self {
+            targets: Self::generate_targets(
+                device,
+                &uniform,
+                &peaks,
+                &state_layout,
+                &sample_layout,
+                (width, height),
+                params.resolution,
+            ),
+            density_pipeline: display_pipeline("fs_density"),
+            volume_pipeline: display_pipeline("fs_volume"),
+            heaviest_pipeline: compute_pipeline("find_heaviest"),
+            deposit_pipeline: compute_pipeline("deposit_mass"),
+            cell_peak_pipeline: compute_pipeline("find_cell_peak"),
+            splat_peak_pipeline: compute_pipeline("find_splat_peak"),
+            splat_pipeline,
+            params,
+            uniform,
+            peaks,
+            state_layout,
+            sample_layout,
+            render_group,
+            size: (width, height),
+            total_mass,
+        }
+    }
+    pub fn total_mass(&self) -> f64 {
+        self.total_mass
+    }
+    fn rebuild_targets(&mut self, device: &wgpu::Device) {
+        self.targets = Self::generate_targets(
+            device,
+            &self.uniform,
+            &self.peaks,
+            &self.state_layout,
+            &self.sample_layout,
+            self.size,
+            self.params.resolution,
+        );
+    }
+    /// Mass in each cell of the volume from the last volume frame, stalling
+    /// like `BodyData::read_back`
+    pub fn read_grid(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> Result<Vec<f32>> {
+        let grid = &self.targets.grid;
+        let readable = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: grid.size(),
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let mut encoder = device.create_command_encoder(&Default::default());
+        encoder.copy_buffer_to_buffer(grid, 0, &readable, 0, readable.size());
+        queue.submit(Some(encoder.finish()));
+        let mass_scale = ParticleMesh::mass_scale(self.total_mass);
+        Ok(read_buffer::<u32>(&readable, device)?
+            .into_iter()
+            .map(|m| m as f32 / mass_scale)
+            .collect())
+    }
+    /// Rebuilds the splat texture for a new scene size
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        self.size = (width, height);
+        self.rebuild_targets(device);
+    }
+    pub fn params(&self) -> &DensityParams {
+        &self.params
+    }
+    pub fn set_params(&mut self, device: &wgpu::Device, params: DensityParams) {
+        let params = DensityParams {
+            resolution: params.resolution.max(1),
+            ..params
+        };
+        let rebuild = params.resolution != self.params.resolution;
+        self.params = params;
+        if rebuild {
+            self.rebuild_targets(device);
+        }
+    }
+    /// Records the passes that fill the splat texture or grid for `mode`.
+    /// `uniform_bind_group` is the one the sprites are drawn with and
+    /// `inverse_world_mat` the inverse of its world matrix
+    #[allow(clippy::too_many_arguments)]
+    pub fn encode(
+        &self,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        mode: RenderMode,
+        body_data: &BodyData<Compute>,
+        body_bind_group: &wgpu::BindGroup,
+        simulation_bind_group: &wgpu::BindGroup,
+        uniform_bind_group: &wgpu::BindGroup,
+        inverse_world_mat: Mat4,
+    ) {
+        if mode == RenderMode::Points {
+            return;
+        }
+        let params = &self.params;
+        let uniform = DensityUniform {
+            inverse_world_mat: inverse_world_mat.to_cols_array_2d(),
+            kernel_size: params.kernel_size,
+            min_kernel_pixels: params.min_kernel_pixels,
+            decades: params.decades,
+            colormap: Colormap::uniform_value(Some(params.colormap)),
+            extent: params.extent,
+            resolution: params.resolution,
+            steps: params.steps,
+            opacity: params.opacity,
+            mass_scale: ParticleMesh::mass_scale(self.total_mass),
+            padding: [0; 3],
+        };
+        queue.write_buffer(&self.uniform, 0, bytes_of(&uniform));
+        encoder.clear_buffer(&self.peaks, 0, None);
+        if mode == RenderMode::Volume {
+            encoder.clear_buffer(&self.targets.grid, 0, None);
+        }
+
+        let body_count = body_data.len as u32;
+        let compute_pass = |encoder: &mut wgpu::CommandEncoder, pipelines: &[(&_, u32)]| {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Density"),
+                timestamp_writes: None,
+            });
+            pass.set_bind_group(0, body_bind_group, &[]);
+            pass.set_bind_group(1, simulation_bind_group, &[]);
+            pass.set_bind_group(2, &self.targets.state_group, &[]);
+            for &(pipeline, workgroups) in pipelines {
+                pass.set_pipeline(pipeline);
+                pass.dispatch_workgroups(workgroups, 1, 1);
+            }
+        };
+
+        let bodies = body_count.div_ceil(64);
+        if mode == RenderMode::Volume {
+            let cells = params.resolution.pow(3).div_ceil(64);
+            compute_pass(
+                encoder,
+                &[
+                    (&self.deposit_pipeline, bodies),
+                    (&self.cell_peak_pipeline, cells),
+                ],
+            );
+            return;
+        }
+
+        compute_pass(encoder, &[(&self.heaviest_pipeline, bodies)]);
+        {
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Density Splat"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &self.targets.splat,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                ..Default::default()
+            });
+            pass.set_pipeline(&self.splat_pipeline);
+            pass.set_bind_group(0, uniform_bind_group, &[]);
+            pass.set_bind_group(1, &self.render_group, &[]);
+            pass.set_vertex_buffer(0, body_data.positions.slice(..));
+            pass.set_vertex_buffer(1, body_data.mass.slice(..));
+            pass.draw(0..4, 0..body_count);
+        }
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Density Peak"),
+            timestamp_writes: None,
+        });
+        pass.set_pipeline(&self.splat_peak_pipeline);
+        pass.set_bind_group(0, body_bind_group, &[]);
+        pass.set_bind_group(1, simulation_bind_group, &[]);
+        pass.set_bind_group(2, &self.targets.state_group, &[]);
+        pass.dispatch_workgroups(self.size.0.div_ceil(8), self.size.1.div_ceil(8), 1);
+    }
+    /// Draws the density or volume over the whole of a pass of the scene
+    pub fn draw(
+        &self,
+        rpass: &mut wgpu::RenderPass,
+        mode: RenderMode,
+        uniform_bind_group: &wgpu::BindGroup,
+    ) {
+        let pipeline = match mode {
+            RenderMode::Points => return,
+            RenderMode::Density => &self.density_pipeline,
+            RenderMode::Volume => &self.volume_pipeline,
+        };
+        rpass.set_pipeline(pipeline);
+        rpass.set_bind_group(0, uniform_bind_group, &[]);
+        rpass.set_bind_group(1, &self.render_group, &[]);
+        rpass.set_bind_group(2, &self.targets.sample_group, &[]);
+        rpass.draw(0..3, 0..1);
+    }
+}
@@ -1,58 +1,201 @@
 #![allow(dead_code, unused_variables)]
-use std::{borrow::Borrow, time::Duration};
+use std::time::Duration;
 
-use rendering::ViewMode;
-use wgpu::BindGroupLayoutDescriptor;
+use capture::Image;
+use coloring::{ColorParams, Coloring, Colormap};
+use density::{DensityField, DensityParams, RenderMode};
+use post_processing::{PostParams, PostProcessing, HDR_FORMAT};
+use quality::{PresentMode, RenderQuality};
+use recording::{Recording, RecordingParams};
+use rendering::{DepthMode, SpriteParams, ViewMode};
+use trails::{TrailParams, TrailSelection, Trails};
 
+use crate::physics::{
+    cosmology::{Cosmology, Expansion},
+    diagnostics::EnergyReport,
+    escapes::{EscapeCriterion, EscapeTracker},
+    force_models::ForceModel,
+    particle_mesh::{GravitySolver, ParticleMesh},
+    periodic::{Boundary, EwaldTable},
+    post_newtonian::{PostNewtonian, PostNewtonianKick},
+    potentials::{ExternalPotential, PotentialBuffer},
+    precision::Compensation,
+    regularization::{Regularization, RegularizationParams, RegularizedPair},
+    restricted_three_body::RestrictedThreeBody,
+    sinks::{AccretionLog, SinkParams, Sinks},
+    snapshot::Snapshot,
+    sph::{HydroParams, Hydrodynamics},
+    units::UnitSystem,
+    SimulationUniform, SimulationUniformBuilder,
+};
 use crate::prelude::*;
 
+pub mod capture;
+pub mod coloring;
 pub mod compute;
+pub mod density;
+pub mod post_processing;
+pub mod quality;
+pub mod recording;
 pub mod rendering;
+pub mod trails;
 pub mod vertices;
 
-use vertices::{BodyData, Compute};
+use vertices::{BodyData, Compute, InitialConditions};
+
+/// A float depth buffer, whose precision reversed-Z relies on
+const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+/// Storage buffers the largest compute layout binds, six for the bodies, four
+/// in the simulation group and six for the gas. The limit counts every group
+/// of a layout, so it's well over the default of eight
+pub const STORAGE_BUFFERS_PER_STAGE: u32 = 16;
 
 #[derive(Debug)]
 pub struct Graphics<'s> {
-    surface: wgpu::Surface<'s>,
+    /// None when headless, see `new_headless`
+    surface: Option<wgpu::Surface<'s>>,
     adapter: wgpu::Adapter,
     device: wgpu::Device,
     queue: wgpu::Queue,
     surface_config: wgpu::SurfaceConfiguration,
+    /// The supported settings in use, see `set_render_quality`
+    quality: RenderQuality,
+    /// Reused every frame, recreated in `resize`
+    depth_texture: wgpu::TextureView,
+    /// Drawn into in place of the HDR target and resolved into it, None
+    /// without MSAA
+    msaa_target: Option<wgpu::TextureView>,
+    /// Written each frame rather than recreated
+    uniform_buffer: wgpu::Buffer,
+    uniform_bind_group: wgpu::BindGroup,
+    /// Groups 0 and 1 of the compute passes, see `rebuild_compute_bind_groups`
+    body_bind_group: wgpu::BindGroup,
+    simulation_bind_group: wgpu::BindGroup,
     render_pipeline: wgpu::RenderPipeline,
+    sprites: SpriteParams,
+    post_processing: PostProcessing,
+    coloring: Coloring,
+    box_pipeline: wgpu::RenderPipeline,
     compute_pipeline: wgpu::ComputePipeline,
+    external_pipeline: wgpu::ComputePipeline,
+    force_model: ForceModel,
+    charge_buffer: wgpu::Buffer,
+    post_newtonian_pair_buffer: wgpu::Buffer,
+    /// The pairs in `post_newtonian_pair_buffer`, kept to follow the bodies
+    /// when escapes are compacted
+    post_newtonian_pairs: Vec<[u32; 2]>,
+    /// None while the post newtonian corrections are off
+    post_newtonian: Option<PostNewtonianKick>,
     incriment_pipeline: wgpu::ComputePipeline,
     body_data: BodyData<Compute>,
+    /// Low order parts of the body state, see `set_compensated_summation`
+    compensation: Compensation,
+    simulation: SimulationUniform,
+    simulation_buffer: wgpu::Buffer,
+    external_potentials: Vec<ExternalPotential>,
+    potential_buffer: PotentialBuffer,
+    boundary: Boundary,
+    ewald_buffer: wgpu::Buffer,
+    /// Line list of the periodic box's edges, None while the boundary is open
+    box_outline: Option<wgpu::Buffer>,
+    solver: GravitySolver,
+    /// Only created once a mesh based solver is selected
+    particle_mesh: Option<ParticleMesh>,
+    /// None while gas bodies are treated as collisionless
+    hydrodynamics: Option<Hydrodynamics>,
+    /// None while sink bodies don't accrete
+    sinks: Option<Sinks>,
+    trails: Option<Trails>,
+    depth_mode: DepthMode,
+    render_mode: RenderMode,
+    density_params: DensityParams,
+    /// Created the first time a density mode is chosen
+    density: Option<DensityField>,
+    /// Labels from the initial conditions or `set_body_tags`, empty if neither
+    /// gave any
+    body_tags: Vec<u32>,
+    /// Some while frames are being recorded, see `start_recording`
+    recording: Option<Recording>,
+    /// None unless escapes are being watched for
+    escapes: Option<EscapeTracker>,
+    /// None unless tight pairs are handed to the KS sub-integrator
+    regularization: Option<Regularization>,
+    /// Set while running the restricted three body problem's rotating frame
+    restricted_three_body: Option<RestrictedThreeBody>,
+    /// Line list of the zero velocity curves and primaries, and its vertex
+    /// count
+    three_body_overlay: Option<(wgpu::Buffer, u32)>,
+    /// Simulation time elapsed, advanced by the time step each frame
+    time: f64,
+    /// None unless the run is in comoving coordinates
+    expansion: Option<Expansion>,
 }
 
 impl<'s> Graphics<'s> {
+    /// Depth is reversed, see `DepthMode`
     fn generate_depth_stencil_state() -> wgpu::DepthStencilState {
         wgpu::DepthStencilState {
-            format: wgpu::TextureFormat::Depth24PlusStencil8,
+            format: DEPTH_FORMAT,
             bias: wgpu::DepthBiasState::default(),
-            depth_compare: wgpu::CompareFunction::Less,
+            depth_compare: wgpu::CompareFunction::Greater,
             depth_write_enabled: true,
             stencil: wgpu::StencilState::default(),
         }
     }
-    fn generate_depth_texture(&self) -> wgpu::TextureView {
+    fn generate_multisample_state(samples: u32) -> wgpu::MultisampleState {
+        wgpu::MultisampleState {
+            count: samples,
+            ..Default::default()
+        }
+    }
+    fn generate_depth_texture(
+        device: &wgpu::Device,
+        (width, height): (u32, u32),
+        samples: u32,
+    ) -> wgpu::TextureView {
         let desc = wgpu::TextureDescriptor {
             label: Some("Depth Stencil"),
             size: wgpu::Extent3d {
-                width: self.surface_config.width,
-                height: self.surface_config.height,
+                width,
+                height,
                 depth_or_array_layers: 1,
             },
             mip_level_count: 1,
-            sample_count: 1,
+            sample_count: samples,
             dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Depth24PlusStencil8,
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            format: DEPTH_FORMAT,
+            // Never sampled, and a sampleable multisampled depth texture
+            // breaks the colour resolve on the GL backend
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
             view_formats: &[],
         };
-        let texture = self.device.create_texture(&desc);
+        let texture = device.create_texture(&desc);
         texture.create_view(&wgpu::TextureViewDescriptor::default())
     }
+    fn generate_msaa_target(
+        device: &wgpu::Device,
+        (width, height): (u32, u32),
+        samples: u32,
+    ) -> Option<wgpu::TextureView> {
+        if samples == 1 {
+            return None;
+        }
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("MSAA Target"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: samples,
+            dimension: wgpu::TextureDimension::D2,
+            format: HDR_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        });
+        Some(texture.create_view(&Default::default()))
+    }
     fn generate_pipeline_layout(device: &wgpu::Device) -> wgpu::PipelineLayout {
         let bg_0 = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             label: None,
@@ -66,26 +209,85 @@ impl<'s> Graphics<'s> {
             push_constant_ranges: &[],
         })
     }
-    fn generate_compute_bind_groups(&self) -> wgpu::BindGroup {
-        self.device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: None,
-            layout: &self.compute_pipeline.get_bind_group_layout(0),
+    fn generate_body_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        body_data: &BodyData<Compute>,
+        compensation: &Compensation,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Bodies"),
+            layout,
             entries: &[
                 wgpu::BindGroupEntry {
                     binding: 0,
-                    resource: self.body_data.positions.as_entire_binding(),
+                    resource: body_data.positions.as_entire_binding(),
                 },
                 wgpu::BindGroupEntry {
                     binding: 1,
-                    resource: self.body_data.velocities.as_entire_binding(),
+                    resource: body_data.velocities.as_entire_binding(),
                 },
                 wgpu::BindGroupEntry {
                     binding: 2,
-                    resource: self.body_data.mass.as_entire_binding(),
+                    resource: body_data.mass.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: body_data.species.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: compensation.position_lo.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: compensation.velocity.as_entire_binding(),
                 },
             ],
         })
     }
+    /// `buffers` are the simulation uniform, potentials, Ewald table, charges
+    /// and post newtonian pairs
+    fn generate_simulation_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        buffers: [&wgpu::Buffer; 5],
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Simulation"),
+            layout,
+            entries: &buffers
+                .iter()
+                .enumerate()
+                .map(|(binding, buffer)| wgpu::BindGroupEntry {
+                    binding: binding as u32,
+                    resource: buffer.as_entire_binding(),
+                })
+                .collect::<Vec<_>>(),
+        })
+    }
+    /// Rebuilds the compute bind groups, which must follow any of their
+    /// buffers or the compute pipeline being replaced
+    fn rebuild_compute_bind_groups(&mut self) {
+        self.body_bind_group = Self::generate_body_bind_group(
+            &self.device,
+            &self.compute_pipeline.get_bind_group_layout(0),
+            &self.body_data,
+            &self.compensation,
+        );
+        self.simulation_bind_group = Self::generate_simulation_bind_group(
+            &self.device,
+            &self.compute_pipeline.get_bind_group_layout(1),
+            [
+                &self.simulation_buffer,
+                &self.potential_buffer.buffer,
+                &self.ewald_buffer,
+                &self.charge_buffer,
+                &self.post_newtonian_pair_buffer,
+            ],
+        );
+    }
+    /// Group 0 of the compute passes, the body state
     fn generate_compute_pipeline_bg_entries() -> Vec<wgpu::BindGroupLayoutEntry> {
         let bingroup_layout_entry = |index: _| wgpu::BindGroupLayoutEntry {
             binding: index,
@@ -97,7 +299,18 @@ impl<'s> Graphics<'s> {
             },
             count: None, //Some(std::num::NonZero::new(self.body_data.len as u32).unwrap())
         };
-        (0..3).map(bingroup_layout_entry).collect()
+        (0..6).map(bingroup_layout_entry).collect()
+    }
+    /// Group 1 of the compute passes, the simulation uniform and the buffers
+    /// of the force kernel
+    fn generate_simulation_bg_entries() -> Vec<wgpu::BindGroupLayoutEntry> {
+        vec![
+            SimulationUniform::generate_bind_group_layout_entry(0),
+            PotentialBuffer::generate_bind_group_layout_entry(1),
+            EwaldTable::generate_bind_group_layout_entry(2),
+            ForceModel::generate_bind_group_layout_entry(3),
+            PostNewtonian::generate_bind_group_layout_entry(4),
+        ]
     }
     fn generate_compute_pipeline_layout(device: &wgpu::Device) -> wgpu::PipelineLayout {
         // @Todo | update min_binding_size to account for length of buffers,
@@ -107,33 +320,47 @@ impl<'s> Graphics<'s> {
         device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: None,
             push_constant_ranges: &[],
-            bind_group_layouts: &[&device.create_bind_group_layout(
-                &wgpu::BindGroupLayoutDescriptor {
+            bind_group_layouts: &[
+                &device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
                     label: None,
                     entries: &entries,
-                },
-            )],
+                }),
+                &device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    label: None,
+                    entries: &Self::generate_simulation_bg_entries(),
+                }),
+            ],
         })
     }
-    fn generate_compute_pipeline(device: &wgpu::Device) -> wgpu::ComputePipeline {
-        let module = device.create_shader_module(include_wgsl!("../../shaders/compute.wgsl"));
+    fn generate_compute_pipeline(
+        device: &wgpu::Device,
+        force_model: &ForceModel,
+        entry_point: &str,
+    ) -> wgpu::ComputePipeline {
+        let module = force_model.generate_shader_module(device);
 
         device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
             label: None,
             layout: Some(&Self::generate_compute_pipeline_layout(device)),
             module: &module,
-            entry_point: Some("cs_entry"),
+            entry_point: Some(entry_point),
             compilation_options: Default::default(),
             cache: None,
         })
     }
+    fn generate_render_shader(device: &wgpu::Device) -> wgpu::ShaderModule {
+        coloring::generate_colormapped_shader(
+            device,
+            "render.wgsl",
+            include_str!("../../shaders/render.wgsl"),
+        )
+    }
     fn generate_render_pipeline(
         device: &wgpu::Device,
-        surface: &wgpu::Surface,
-        adapter: &wgpu::Adapter,
+        topology: wgpu::PrimitiveTopology,
+        samples: u32,
     ) -> wgpu::RenderPipeline {
-        let shaders = device.create_shader_module(include_wgsl!("../../shaders/render.wgsl"));
-        let surface_format = surface.get_capabilities(&adapter).formats[0];
+        let shaders = Self::generate_render_shader(device);
         device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
             label: None,
             layout: Some(&Self::generate_pipeline_layout(device)),
@@ -147,11 +374,10 @@ impl<'s> Graphics<'s> {
                 module: &shaders,
                 entry_point: Some("fs_main"),
                 compilation_options: Default::default(),
-                targets: &[Some(surface_format.into())],
+                targets: &[Some(HDR_FORMAT.into())],
             }),
-            // Specify point primitives
             primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::PointList,
+                topology,
                 front_face: wgpu::FrontFace::Ccw,
                 polygon_mode: wgpu::PolygonMode::Fill,
                 unclipped_depth: false,
@@ -161,7 +387,50 @@ impl<'s> Graphics<'s> {
             cache: None,
             multiview: None,
             depth_stencil: Some(Self::generate_depth_stencil_state()),
-            multisample: Default::default(),
+            multisample: Self::generate_multisample_state(samples),
+        })
+    }
+    /// Instanced quads, added onto the HDR target so overlapping bodies
+    /// brighten rather than hide each other
+    fn generate_sprite_pipeline(device: &wgpu::Device, samples: u32) -> wgpu::RenderPipeline {
+        let shaders = Self::generate_render_shader(device);
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Sprite Pipeline"),
+            layout: Some(&Self::generate_pipeline_layout(device)),
+            vertex: wgpu::VertexState {
+                module: &shaders,
+                entry_point: Some("vs_sprite"),
+                compilation_options: Default::default(),
+                buffers: &BodyData::<Compute>::get_sprite_buffer_layouts(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shaders,
+                entry_point: Some("fs_sprite"),
+                compilation_options: Default::default(),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: HDR_FORMAT,
+                    blend: Some(wgpu::BlendState {
+                        color: wgpu::BlendComponent {
+                            src_factor: wgpu::BlendFactor::SrcAlpha,
+                            dst_factor: wgpu::BlendFactor::One,
+                            operation: wgpu::BlendOperation::Add,
+                        },
+                        alpha: wgpu::BlendComponent::OVER,
+                    }),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleStrip,
+                ..Default::default()
+            },
+            cache: None,
+            multiview: None,
+            depth_stencil: Some(wgpu::DepthStencilState {
+                depth_write_enabled: false,
+                ..Self::generate_depth_stencil_state()
+            }),
+            multisample: Self::generate_multisample_state(samples),
         })
     }
     fn generate_incriment_pipeline(device: &wgpu::Device) -> wgpu::ComputePipeline {
@@ -177,7 +446,12 @@ impl<'s> Graphics<'s> {
             cache: None,
         })
     }
-    pub fn new(window: Arc<winit::window::Window>, instance: wgpu::Instance) -> Result<Self> {
+    pub fn new(
+        window: Arc<winit::window::Window>,
+        instance: wgpu::Instance,
+        initial_conditions: &InitialConditions,
+        units: &UnitSystem,
+    ) -> Result<Self> {
         use std::{collections::HashMap, time::Instant};
 
         let mut start = Instant::now();
@@ -202,8 +476,7 @@ impl<'s> Graphics<'s> {
         times.insert("Adapter Creation", start.elapsed());
         start = Instant::now();
 
-        let (device, queue) = block_on(adapter.request_device(&Default::default(), None))
-            .with_context(|| "Failed to obtain device")?;
+        let (device, queue) = Self::request_device(&adapter)?;
         times.insert("Device Creation", start.elapsed());
         start = Instant::now();
 
@@ -212,25 +485,115 @@ impl<'s> Graphics<'s> {
         let surface_config = surface
             .get_default_config(&adapter, size.width, size.height)
             .ok_or_else(|| anyhow!("faild to create surface configuration"))?;
-
-        surface.configure(&device, &surface_config);
         times.insert("Surface configuration", start.elapsed());
-        start = Instant::now();
+
+        info!("Graphics Instanciation Times - {:?}", times);
+
+        Self::from_device(
+            Some(surface),
+            adapter,
+            (device, queue),
+            surface_config,
+            initial_conditions,
+            units,
+        )
+    }
+    /// Draws into images rather than a window, see `render_to_image`. Falls
+    /// back to a software adapter when there's no other, so it runs on
+    /// machines without a gpu. `width` and `height` are the size `render_to`
+    /// expects its targets to be
+    pub fn new_headless(
+        instance: wgpu::Instance,
+        (width, height): (u32, u32),
+        initial_conditions: &InitialConditions,
+        units: &UnitSystem,
+    ) -> Result<Self> {
+        let options = |force_fallback_adapter| wgpu::RequestAdapterOptionsBase {
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            compatible_surface: None,
+            force_fallback_adapter,
+        };
+        let adapter = block_on(instance.request_adapter(&options(false)))
+            .or_else(|| block_on(instance.request_adapter(&options(true))))
+            .ok_or_else(|| anyhow!("Failed to find an adapter"))?;
+        info!("Headless adapter - {:?}", adapter.get_info());
+        let devices = Self::request_device(&adapter)?;
+        // Never configured, it only holds the output's size and format
+        let surface_config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format: capture::OUTPUT_FORMAT,
+            width: width.max(1),
+            height: height.max(1),
+            present_mode: wgpu::PresentMode::Fifo,
+            desired_maximum_frame_latency: 2,
+            alpha_mode: wgpu::CompositeAlphaMode::Auto,
+            view_formats: Vec::new(),
+        };
+        Self::from_device(
+            None,
+            adapter,
+            devices,
+            surface_config,
+            initial_conditions,
+            units,
+        )
+    }
+    fn request_device(adapter: &wgpu::Adapter) -> Result<(wgpu::Device, wgpu::Queue)> {
+        let limits = adapter.limits();
+        if limits.max_storage_buffers_per_shader_stage < STORAGE_BUFFERS_PER_STAGE {
+            bail!(
+                "{} binds at most {} storage buffers per shader stage, the compute passes need {}",
+                adapter.get_info().name,
+                limits.max_storage_buffers_per_shader_stage,
+                STORAGE_BUFFERS_PER_STAGE
+            );
+        }
+        let descriptor = wgpu::DeviceDescriptor {
+            // Lets MSAA go beyond the sample counts every adapter has
+            required_features: adapter.features()
+                & wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES,
+            // Everything the adapter has, the default limits bind too few
+            // storage buffers
+            required_limits: limits,
+            ..Default::default()
+        };
+        block_on(adapter.request_device(&descriptor, None))
+            .with_context(|| "Failed to obtain device")
+    }
+    /// The rest of `new` and `new_headless`, `surface` being None for the
+    /// latter
+    fn from_device(
+        surface: Option<wgpu::Surface<'s>>,
+        adapter: wgpu::Adapter,
+        (device, queue): (wgpu::Device, wgpu::Queue),
+        mut surface_config: wgpu::SurfaceConfiguration,
+        initial_conditions: &InitialConditions,
+        units: &UnitSystem,
+    ) -> Result<Self> {
+        use std::{collections::HashMap, time::Instant};
+
+        let mut start = Instant::now();
+        let mut times: HashMap<&'static str, Duration> = HashMap::new();
+
+        let quality = RenderQuality::default().supported(
+            &adapter,
+            &device,
+            &Self::present_modes(surface.as_ref(), &adapter),
+            (surface_config.width, surface_config.height),
+        );
+        surface_config.present_mode = quality.present_mode.wgpu_present_mode();
+        if let Some(surface) = &surface {
+            surface.configure(&device, &surface_config);
+        }
 
         let mut encoder = device.create_command_encoder(&Default::default());
         times.insert("Encoder Creation", start.elapsed());
         start = Instant::now();
 
-        let body_data = BodyData::<Compute>::generate_galaxy(
-            1.,
-            3.14 / 8.,
-            7000,
-            Vec3::Z,
-            6e-3 * 0.2,
-            &device,
-            &mut encoder,
-        )
-        .with_context(|| "Failed to create unit points")?;
+        let gravitation_const = units.gravitation_const() as f32;
+        let (body_data, body_tags) = initial_conditions
+            .generate(gravitation_const, &device, &mut encoder)
+            .with_context(|| "Failed to create initial conditions")?;
         info!("BodyData length : {:?}", body_data.positions);
 
         times.insert("Creating Body Data", start.elapsed());
@@ -239,79 +602,1222 @@ impl<'s> Graphics<'s> {
         times.insert("Queue submission", start.elapsed());
         // start = Instant::now();
 
-        info!("Graphics Instanciation Times - {:?}", times);
+        info!("Device Setup Times - {:?}", times);
+
+        let simulation = SimulationUniformBuilder::default()
+            .body_count(body_data.len as u32)
+            .gravitation_const(gravitation_const)
+            .build()
+            .with_context(|| {
+                "Failed to generate SimulationUniform from SimulationUniformBuilder"
+            })?;
+
+        let compensation = Compensation::new(&device, body_data.len);
+        let render_size = quality.render_size(surface_config.width, surface_config.height);
+        let mut post_processing = PostProcessing::new(
+            &device,
+            surface_config.format,
+            surface_config.width,
+            surface_config.height,
+            PostParams::default(),
+        );
+        post_processing.resize(&device, render_size.0, render_size.1, quality.render_scale);
+        let compute_pipeline =
+            Self::generate_compute_pipeline(&device, &ForceModel::Newtonian, "cs_entry");
+        let simulation_buffer = simulation.generate_buffer(&device);
+        let potential_buffer = PotentialBuffer::new(&device);
+        let ewald_buffer = EwaldTable::generate_placeholder_buffer(&device);
+        let charge_buffer = ForceModel::Newtonian.generate_charge_buffer(&device);
+        let post_newtonian_pair_buffer = PostNewtonian::generate_pair_buffer(&[], &device);
+        let body_bind_group = Self::generate_body_bind_group(
+            &device,
+            &compute_pipeline.get_bind_group_layout(0),
+            &body_data,
+            &compensation,
+        );
+        let simulation_bind_group = Self::generate_simulation_bind_group(
+            &device,
+            &compute_pipeline.get_bind_group_layout(1),
+            [
+                &simulation_buffer,
+                &potential_buffer.buffer,
+                &ewald_buffer,
+                &charge_buffer,
+                &post_newtonian_pair_buffer,
+            ],
+        );
+        let uniform_buffer = rendering::Uniform::default().generate_buffer(&device);
+        let coloring = Coloring::new(
+            &device,
+            &compute_pipeline.get_bind_group_layout(0),
+            &compute_pipeline.get_bind_group_layout(1),
+            body_data.len,
+        );
+        if !body_tags.is_empty() {
+            coloring.set_tags(&queue, &body_tags)?;
+        }
 
         Ok(Graphics {
-            render_pipeline: Self::generate_render_pipeline(&device, &surface, &adapter),
-            compute_pipeline: Self::generate_compute_pipeline(&device),
+            depth_texture: Self::generate_depth_texture(&device, render_size, quality.msaa_samples),
+            msaa_target: Self::generate_msaa_target(&device, render_size, quality.msaa_samples),
+            uniform_bind_group: Self::generate_uniform_bind_group(&device, &uniform_buffer),
+            uniform_buffer,
+            body_bind_group,
+            simulation_bind_group,
+            simulation_buffer,
+            potential_buffer,
+            // Bodies are drawn as sprites, the periodic box as lines
+            render_pipeline: Self::generate_sprite_pipeline(&device, quality.msaa_samples),
+            box_pipeline: Self::generate_render_pipeline(
+                &device,
+                wgpu::PrimitiveTopology::LineList,
+                quality.msaa_samples,
+            ),
+            ewald_buffer,
+            compute_pipeline,
+            external_pipeline: Self::generate_compute_pipeline(
+                &device,
+                &ForceModel::Newtonian,
+                "cs_external",
+            ),
+            charge_buffer,
+            post_newtonian_pair_buffer,
+            post_newtonian_pairs: Vec::new(),
+            post_newtonian: None,
             incriment_pipeline: Self::generate_incriment_pipeline(&device),
             surface,
             adapter,
             device,
             queue,
             surface_config,
+            quality,
+            compensation,
+            sprites: SpriteParams::default(),
+            post_processing,
+            coloring,
             body_data,
+            simulation,
+            external_potentials: Vec::new(),
+            boundary: Boundary::Open,
+            box_outline: None,
+            solver: GravitySolver::DirectSum,
+            particle_mesh: None,
+            hydrodynamics: None,
+            sinks: None,
+            trails: None,
+            depth_mode: DepthMode::default(),
+            render_mode: RenderMode::Points,
+            density_params: DensityParams::default(),
+            density: None,
+            body_tags,
+            recording: None,
+            escapes: None,
+            regularization: None,
+            restricted_three_body: None,
+            three_body_overlay: None,
+            time: 0.,
+            expansion: None,
+            force_model: ForceModel::Newtonian,
         })
     }
+    fn write_simulation_uniform(&self) {
+        self.queue.write_buffer(
+            &self.simulation_buffer,
+            0,
+            bytemuck::bytes_of(&self.simulation),
+        );
+    }
+    /// Replaces the static potentials every body feels
+    pub fn set_external_potentials(&mut self, potentials: Vec<ExternalPotential>) {
+        if self
+            .potential_buffer
+            .write(&self.device, &self.queue, &potentials)
+        {
+            self.rebuild_compute_bind_groups();
+        }
+        self.simulation.potential_count = potentials.len() as u32;
+        self.external_potentials = potentials;
+        self.write_simulation_uniform();
+    }
+    pub fn external_potentials(&self) -> &[ExternalPotential] {
+        &self.external_potentials
+    }
+    /// Switches the gravity solver between open and periodic boundaries. The
+    /// Ewald table is only generated the first time a periodic boundary is set
+    pub fn set_boundary(&mut self, boundary: Boundary) {
+        if let Boundary::Periodic { .. } = boundary {
+            // The placeholder only holds a single entry
+            if self.ewald_buffer.size() <= size_of::<[f32; 4]>() as u64 {
+                self.ewald_buffer = EwaldTable::generate().generate_buffer(&self.device);
+                self.rebuild_compute_bind_groups();
+            }
+            self.box_outline = Some(self.device.create_buffer_init(
+                &wgpu::util::BufferInitDescriptor {
+                    label: Some("Box Outline"),
+                    contents: bytemuck::cast_slice(&boundary.outline_vertices()),
+                    usage: wgpu::BufferUsages::VERTEX,
+                },
+            ));
+        } else {
+            self.box_outline = None;
+            if self.solver.uses_mesh() {
+                warn!(
+                    "The particle mesh needs a periodic boundary, falling back to direct summation"
+                );
+                self.solver = GravitySolver::DirectSum;
+                (self.simulation.solver, self.simulation.split_scale) =
+                    self.solver.uniform_values();
+            }
+        }
+        (self.simulation.periodic, self.simulation.box_size) = boundary.uniform_values();
+        self.boundary = boundary;
+        self.write_simulation_uniform();
+        self.drop_unsupported_escapes();
+    }
+    pub fn set_sprites(&mut self, sprites: SpriteParams) {
+        self.sprites = sprites;
+    }
+    pub fn set_post_processing(&mut self, params: PostParams) {
+        self.post_processing
+            .set_params(&self.device, &self.queue, params);
+    }
+    pub fn post_processing(&self) -> &PostParams {
+        self.post_processing.params()
+    }
+    /// Colours the bodies by a quantity, or plain white with None
+    pub fn set_coloring(&mut self, params: Option<ColorParams>) {
+        self.coloring.set_params(params);
+    }
+    pub fn coloring(&self) -> Option<&ColorParams> {
+        self.coloring.params()
+    }
+    /// Labels for `ColorQuantity::Tag` and `TrailSelection::Groups`, one per
+    /// body, in place of the initial conditions' ones, see the `TAG_*`
+    /// constants in vertices
+    pub fn set_body_tags(&mut self, tags: &[u32]) -> Result<()> {
+        self.coloring.set_tags(&self.queue, tags)?;
+        self.body_tags = tags.to_vec();
+        Ok(())
+    }
+    /// Draws the recent paths of the selected bodies, or none with None
+    pub fn set_trails(&mut self, params: Option<TrailParams>) {
+        let Some(params) = params else {
+            self.trails = None;
+            return;
+        };
+        let selected = params
+            .selection
+            .resolve(self.simulation.body_count as usize, &self.body_tags);
+        if let Some(trails) = self.trails.as_mut().filter(|trails| {
+            trails.params().length == params.length.max(2) && trails.selected() == selected
+        }) {
+            trails.set_params(params);
+            return;
+        }
+        self.trails = Some(Trails::new(
+            &self.device,
+            &self.compute_pipeline.get_bind_group_layout(0),
+            Self::generate_depth_stencil_state(),
+            Self::generate_multisample_state(self.quality.msaa_samples),
+            params,
+            &selected,
+        ));
+    }
+    pub fn trails(&self) -> Option<&TrailParams> {
+        self.trails.as_ref().map(Trails::params)
+    }
+    /// Draws the bodies as sprites, or as their projected or volumetric
+    /// density. The first density mode reads the masses back, to size the
+    /// volume grid
+    pub fn set_render_mode(&mut self, mode: RenderMode) -> Result<()> {
+        if mode != RenderMode::Points && self.density.is_none() {
+            let total_mass = self.total_mass()?;
+            self.density = Some(self.generate_density_field(total_mass));
+        }
+        self.render_mode = mode;
+        Ok(())
+    }
+    fn generate_density_field(&self, total_mass: f64) -> DensityField {
+        let (width, height) = self.render_size();
+        DensityField::new(
+            &self.device,
+            &self.compute_pipeline.get_bind_group_layout(0),
+            &self.compute_pipeline.get_bind_group_layout(1),
+            Self::generate_depth_stencil_state(),
+            Self::generate_multisample_state(self.quality.msaa_samples),
+            width,
+            height,
+            self.density_params,
+            total_mass,
+        )
+    }
+    /// Sum of the body masses, read back from the gpu. Mass is never added,
+    /// so this bounds what any grid cell can hold from then on
+    fn total_mass(&self) -> Result<f64> {
+        let bodies = self
+            .body_data
+            .read_back(&self.device, &self.queue)
+            .with_context(|| "Failed to read back body data")?;
+        Ok(bodies.mass.iter().map(|&m| m as f64).sum())
+    }
+    pub fn render_mode(&self) -> RenderMode {
+        self.render_mode
+    }
+    pub fn set_depth_mode(&mut self, mode: DepthMode) {
+        self.depth_mode = mode;
+    }
+    pub fn depth_mode(&self) -> DepthMode {
+        self.depth_mode
+    }
+    pub fn set_density(&mut self, params: DensityParams) {
+        self.density_params = params;
+        if let Some(density) = &mut self.density {
+            density.set_params(&self.device, params);
+        }
+    }
+    /// Kahan sums the pairwise forces and kicks, and keeps the positions as
+    /// double-single hi/lo pairs, so round off doesn't build up over long
+    /// runs. The renderers only ever see the high halves
+    pub fn set_compensated_summation(&mut self, compensated: bool) {
+        // The low halves are stale from any earlier run with it enabled
+        if compensated && self.simulation.compensated == 0 {
+            self.compensation.clear(&self.queue);
+        }
+        self.simulation.compensated = compensated as u32;
+        self.write_simulation_uniform();
+    }
+    /// Selects how the pairwise gravity is computed. The mesh based solvers
+    /// need a periodic boundary, as the box defines the mesh. The first one
+    /// reads the masses back to size the fixed point density, stalling once
+    pub fn set_gravity_solver(&mut self, solver: GravitySolver) -> Result<()> {
+        if solver.uses_mesh() {
+            if self.boundary == Boundary::Open {
+                bail!("{:?} requires a periodic boundary", solver);
+            }
+            if self.particle_mesh.is_none() {
+                let total_mass = self.total_mass()?;
+                self.particle_mesh = Some(ParticleMesh::new(
+                    &self.device,
+                    &self.compute_pipeline.get_bind_group_layout(0),
+                    &self.compute_pipeline.get_bind_group_layout(1),
+                    total_mass,
+                ));
+            }
+        }
+        (self.simulation.solver, self.simulation.split_scale) = solver.uniform_values();
+        self.solver = solver;
+        self.write_simulation_uniform();
+        Ok(())
+    }
+    /// Rebuilds the pairwise kernel for a different force law. Softening,
+    /// screening length and acceleration scale must be positive, and every
+    /// live body needs mass for the coulomb model
+    pub fn set_force_model(&mut self, force_model: ForceModel) -> Result<()> {
+        let parameter = match force_model {
+            ForceModel::Plummer { softening } => Some(("softening", softening)),
+            ForceModel::Yukawa { screening_length } => Some(("screening length", screening_length)),
+            ForceModel::Mond { acceleration_scale } => {
+                Some(("acceleration scale", acceleration_scale))
+            }
+            ForceModel::Newtonian | ForceModel::Coulomb { .. } => None,
+        };
+        if let Some((name, value)) = parameter {
+            if !value.is_finite() || value <= 0. {
+                bail!("The {name} must be positive, got {value}");
+            }
+        }
+        if let Some(charges) = force_model.charges() {
+            if charges.len() != self.body_data.len {
+                bail!(
+                    "Expected a charge for each of the {} bodies, got {}",
+                    self.body_data.len,
+                    charges.len()
+                );
+            }
+            // The charge to mass ratio sets the acceleration
+            let data = self
+                .body_data
+                .read_back(&self.device, &self.queue)
+                .with_context(|| "Failed to read back body data")?;
+            if let Some(body) =
+                (0..data.mass.len()).find(|&i| data.positions[i][3] != 0. && data.mass[i] <= 0.)
+            {
+                bail!("Coulomb forces need every body to have mass, body {body} has none");
+            }
+        }
+        self.compute_pipeline =
+            Self::generate_compute_pipeline(&self.device, &force_model, "cs_entry");
+        self.external_pipeline =
+            Self::generate_compute_pipeline(&self.device, &force_model, "cs_external");
+        self.charge_buffer = force_model.generate_charge_buffer(&self.device);
+        self.rebuild_compute_bind_groups();
+        self.simulation.force_param = force_model.uniform_value();
+        self.force_model = force_model;
+        if self.post_newtonian.is_some() {
+            self.post_newtonian = Some(self.generate_post_newtonian_kick());
+        }
+        self.write_simulation_uniform();
+        self.drop_unsupported_escapes();
+        Ok(())
+    }
+    fn generate_post_newtonian_kick(&self) -> PostNewtonianKick {
+        PostNewtonianKick::new(
+            &self.device,
+            &self.force_model.generate_shader_module(&self.device),
+            &self.compute_pipeline.get_bind_group_layout(0),
+            &self.compute_pipeline.get_bind_group_layout(1),
+            self.body_data.len,
+        )
+    }
+    /// Enables the 1PN corrections, or disables them with None
+    pub fn set_post_newtonian(&mut self, post_newtonian: Option<PostNewtonian>) -> Result<()> {
+        let Some(post_newtonian) = post_newtonian else {
+            self.post_newtonian = None;
+            self.post_newtonian_pairs.clear();
+            self.simulation.speed_of_light = 0.;
+            self.simulation.post_newtonian_enabled = 0;
+            self.simulation.post_newtonian_pair_count = 0;
+            self.write_simulation_uniform();
+            return Ok(());
+        };
+        if post_newtonian.speed_of_light <= 0. {
+            bail!("The speed of light must be positive");
+        }
+        if let Some(pair) = post_newtonian
+            .pairs
+            .iter()
+            .find(|pair| pair.iter().any(|&i| i as usize >= self.body_data.len))
+        {
+            bail!(
+                "Post newtonian pair {:?} is out of range for {} bodies",
+                pair,
+                self.body_data.len
+            );
+        }
+        self.post_newtonian_pair_buffer =
+            PostNewtonian::generate_pair_buffer(&post_newtonian.pairs, &self.device);
+        self.rebuild_compute_bind_groups();
+        if self.post_newtonian.is_none() {
+            self.post_newtonian = Some(self.generate_post_newtonian_kick());
+        }
+        self.simulation.speed_of_light = post_newtonian.speed_of_light;
+        self.simulation.post_newtonian_mass_threshold = post_newtonian.mass_threshold;
+        // An infinite threshold leaves only the listed pairs
+        self.simulation.post_newtonian_enabled = post_newtonian.mass_threshold.is_finite() as u32;
+        self.simulation.post_newtonian_pair_count = post_newtonian.pairs.len() as u32;
+        self.post_newtonian_pairs = post_newtonian.pairs;
+        self.write_simulation_uniform();
+        Ok(())
+    }
+    /// Enables pressure and viscous forces between the gas bodies, or disables
+    /// them with None so the gas only feels gravity
+    pub fn set_hydrodynamics(&mut self, params: Option<HydroParams>) {
+        let Some(params) = params else {
+            self.hydrodynamics = None;
+            return;
+        };
+        if let Some(hydrodynamics) = &mut self.hydrodynamics {
+            hydrodynamics.set_params(&self.queue, params);
+            return;
+        }
+        self.hydrodynamics = Some(Hydrodynamics::new(
+            &self.device,
+            &self.compute_pipeline.get_bind_group_layout(0),
+            &self.compute_pipeline.get_bind_group_layout(1),
+            params,
+            self.body_data.len,
+        ));
+    }
+    pub fn hydrodynamics(&self) -> Option<&HydroParams> {
+        self.hydrodynamics.as_ref().map(Hydrodynamics::params)
+    }
+    /// Lets the sink bodies absorb whatever comes within their accretion
+    /// radius, or stops them with None. Disabling the sinks drops their log
+    pub fn set_sinks(&mut self, params: Option<SinkParams>) {
+        let Some(params) = params else {
+            self.sinks = None;
+            return;
+        };
+        if let Some(sinks) = &mut self.sinks {
+            sinks.set_params(params);
+            return;
+        }
+        self.sinks = Some(Sinks::new(
+            &self.device,
+            &self.compute_pipeline.get_bind_group_layout(0),
+            &self.compute_pipeline.get_bind_group_layout(1),
+            params,
+            self.body_data.len,
+        ));
+    }
+    /// Reads back every accretion since the sinks were enabled
+    pub fn accretion_log(&self) -> Result<AccretionLog> {
+        let Some(sinks) = &self.sinks else {
+            bail!("Sinks are not enabled");
+        };
+        sinks.read_log(&self.device, &self.queue)
+    }
+    /// Starts watching for escaping bodies, or stops with None. Changing the
+    /// criterion keeps the bodies already recorded. A later switch to a box or
+    /// force law the escape energy can't be summed for stops the watch, with a
+    /// warning
+    pub fn set_escape_criterion(&mut self, criterion: Option<EscapeCriterion>) -> Result<()> {
+        if criterion.is_some() {
+            self.check_escape_energy()?;
+        }
+        match (criterion, &mut self.escapes) {
+            (None, _) => self.escapes = None,
+            (Some(criterion), Some(escapes)) => escapes.criterion = criterion,
+            (Some(criterion), None) => {
+                self.escapes = Some(EscapeTracker::new(criterion, self.body_data.len))
+            }
+        }
+        Ok(())
+    }
+    /// Stops watching for escapes once the box or force law no longer lets
+    /// their energy be summed, see `check_escape_energy`
+    fn drop_unsupported_escapes(&mut self) {
+        if self.escapes.is_none() {
+            return;
+        }
+        if let Err(e) = self.check_escape_energy() {
+            warn!("{e}, no longer watching for escapes");
+            self.escapes = None;
+        }
+    }
+    /// The escape energy is only summed for an open, static box with a
+    /// pairwise potential
+    fn check_escape_energy(&self) -> Result<()> {
+        if self.boundary != Boundary::Open {
+            bail!("Escapes can't be found in a periodic box");
+        }
+        if self.expansion.is_some() {
+            bail!("Escapes can't be found in an expanding box");
+        }
+        if self.force_model.pair_potential(1., 1., 1.).is_none() {
+            bail!(
+                "Escapes can't be found with {:?}, it has no potential",
+                self.force_model
+            );
+        }
+        Ok(())
+    }
+    pub fn escapes(&self) -> Option<&EscapeTracker> {
+        self.escapes.as_ref()
+    }
+    /// Reads the bodies back and flags any new escapes, removing them if the
+    /// criterion asks for it. The read back is synchronous, stalling the frame
+    fn check_escapes(&mut self) -> Result<()> {
+        let Some(escapes) = &mut self.escapes else {
+            return Ok(());
+        };
+        let data = self
+            .body_data
+            .read_back(&self.device, &self.queue)
+            .with_context(|| "Failed to read back body data")?;
+        let escaped = escapes.check(
+            &data,
+            self.simulation.gravitation_const,
+            &self.force_model,
+            &self.external_potentials,
+            self.time,
+        );
+        if !escaped.is_empty() {
+            info!("{} bodies escaped at t = {}", escaped.len(), self.time);
+        }
+        if escapes.criterion.remove && !escaped.is_empty() {
+            self.compact_bodies(escaped)?;
+        }
+        Ok(())
+    }
+    /// Takes `removed` out of the simulation, swapping each with the last
+    /// live body and shrinking the body count so the passes skip them.
+    /// Everything kept per body follows the bodies to their new slots
+    fn compact_bodies(&mut self, mut removed: Vec<u32>) -> Result<()> {
+        // A regularized pair leaves together, its secondary already holds a
+        // removed slot
+        if let Some(regularization) = &mut self.regularization {
+            let (leaving, staying) = std::mem::take(&mut regularization.pairs)
+                .into_iter()
+                .partition::<Vec<_>, _>(|pair| removed.contains(&pair.primary));
+            regularization.pairs = staying;
+            removed.extend(leaving.iter().map(|pair| pair.secondary));
+        }
+        removed.sort_unstable_by(|a, b| b.cmp(a));
+        removed.dedup();
+
+        let scratch = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Swap Scratch"),
+            size: 2 * size_of::<[f32; 4]>() as u64,
+            usage: wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let mut encoder = self.device.create_command_encoder(&Default::default());
+        // The body now in each slot, by the slot it came from
+        let mut slots: Vec<u32> = (0..self.body_data.len as u32).collect();
+        // Highest first, so the slots still to be removed are never the ones
+        // swapped in
+        for body in removed {
+            self.body_data.remove(&self.queue, body);
+            let last = self.simulation.body_count - 1;
+            if body < last {
+                self.body_data
+                    .encode_swap(&mut encoder, &scratch, body, last);
+                self.compensation
+                    .encode_swap(&mut encoder, &scratch, body, last);
+                if let Some(hydrodynamics) = &self.hydrodynamics {
+                    hydrodynamics.encode_swap(&mut encoder, &scratch, body, last);
+                }
+                slots.swap(body as usize, last as usize);
+            }
+            self.simulation.body_count = last;
+        }
+        self.queue.submit(Some(encoder.finish()));
+        self.write_simulation_uniform();
+
+        let mut moved_to = vec![0; slots.len()];
+        for (slot, &from) in slots.iter().enumerate() {
+            moved_to[from as usize] = slot as u32;
+        }
+        let follow = |body: u32| moved_to[body as usize];
+        fn reorder<T: Copy>(values: &[T], slots: &[u32]) -> Vec<T> {
+            slots.iter().map(|&i| values[i as usize]).collect()
+        }
+        if !self.body_tags.is_empty() {
+            self.set_body_tags(&reorder(&self.body_tags, &slots))?;
+        }
+        if let ForceModel::Coulomb { charges } = &mut self.force_model {
+            *charges = reorder(charges, &slots);
+            self.charge_buffer = self.force_model.generate_charge_buffer(&self.device);
+            self.rebuild_compute_bind_groups();
+        }
+        if !self.post_newtonian_pairs.is_empty() {
+            for pair in &mut self.post_newtonian_pairs {
+                *pair = pair.map(follow);
+            }
+            self.post_newtonian_pair_buffer =
+                PostNewtonian::generate_pair_buffer(&self.post_newtonian_pairs, &self.device);
+            self.rebuild_compute_bind_groups();
+        }
+        if let Some(regularization) = &mut self.regularization {
+            for pair in &mut regularization.pairs {
+                (pair.primary, pair.secondary) = (follow(pair.primary), follow(pair.secondary));
+            }
+        }
+        if let Some(escapes) = &mut self.escapes {
+            escapes.reorder(&slots);
+        }
+        if let Some(mut params) = self.trails().cloned() {
+            if let TrailSelection::Bodies(bodies) = &mut params.selection {
+                for body in bodies {
+                    *body = follow(*body);
+                }
+            }
+            self.set_trails(Some(params));
+            // Each slot's path now belongs to another body
+            if let Some(trails) = &mut self.trails {
+                trails.clear();
+            }
+        }
+        Ok(())
+    }
+    /// Hands tight bound pairs to the cpu KS sub-integrator, or stops with
+    /// None, returning every pair to the gpu
+    pub fn set_regularization(&mut self, params: Option<RegularizationParams>) -> Result<()> {
+        if let Some(params) = params {
+            if !params.accuracy.is_finite() || params.accuracy <= 0. {
+                bail!("The regularization accuracy must be positive");
+            }
+            if params.release_radius <= params.binding_radius {
+                bail!(
+                    "The release radius {} must be larger than the binding radius {}",
+                    params.release_radius,
+                    params.binding_radius
+                );
+            }
+        }
+        match (params, &mut self.regularization) {
+            (None, Some(regularization)) => {
+                let pairs = std::mem::take(&mut regularization.pairs);
+                self.regularization = None;
+                self.release_pairs(&pairs)?;
+            }
+            (None, None) => (),
+            (Some(params), Some(regularization)) => regularization.params = params,
+            (Some(params), None) => self.regularization = Some(Regularization::new(params)),
+        }
+        Ok(())
+    }
+    pub fn regularized_pairs(&self) -> &[RegularizedPair] {
+        self.regularization
+            .as_ref()
+            .map_or(&[], |regularization| &regularization.pairs)
+    }
+    /// Writes both bodies of each pair back, split about the centre of mass
+    /// the gpu has been moving
+    fn release_pairs(&self, pairs: &[RegularizedPair]) -> Result<()> {
+        if pairs.is_empty() {
+            return Ok(());
+        }
+        let data = self
+            .body_data
+            .read_back(&self.device, &self.queue)
+            .with_context(|| "Failed to read back body data")?;
+        for pair in pairs {
+            let primary = pair.primary as usize;
+            if data.positions[primary][3] == 0. {
+                warn!(
+                    "Regularized pair {:?} was removed while off the gpu",
+                    [pair.primary, pair.secondary]
+                );
+                continue;
+            }
+            let centre = Vec4::from_array(data.positions[primary]).truncate();
+            let drift = Vec4::from_array(data.velocities[primary]).truncate();
+            let split = pair.split(centre, drift);
+            for ((body, (position, velocity)), mass) in [pair.primary, pair.secondary]
+                .into_iter()
+                .zip(split)
+                .zip(pair.masses)
+            {
+                self.body_data
+                    .write_body(&self.queue, body, position, velocity, mass);
+                self.compensation.reset(&self.queue, body);
+            }
+        }
+        Ok(())
+    }
+    /// Advances the regularized pairs by a frame, releasing the ones that
+    /// have separated and searching for new ones when due
+    fn step_regularization(&mut self) -> Result<()> {
+        let Some(regularization) = &mut self.regularization else {
+            return Ok(());
+        };
+        let released = regularization.advance(
+            self.simulation.time_step as f64,
+            self.simulation.gravitation_const,
+        );
+        let search_due = regularization.frame_due();
+        self.release_pairs(&released)?;
+        if !search_due {
+            return Ok(());
+        }
+        let data = self
+            .body_data
+            .read_back(&self.device, &self.queue)
+            .with_context(|| "Failed to read back body data")?;
+        let Some(regularization) = &mut self.regularization else {
+            return Ok(());
+        };
+        for pair in regularization.search(&data, self.simulation.gravitation_const) {
+            let [(p1, v1), (p2, v2)] = [pair.primary, pair.secondary].map(|i| {
+                (
+                    Vec4::from_array(data.positions[i as usize]).truncate(),
+                    Vec4::from_array(data.velocities[i as usize]).truncate(),
+                )
+            });
+            let [m1, m2] = pair.masses;
+            let m = m1 + m2;
+            self.body_data.write_body(
+                &self.queue,
+                pair.primary,
+                (p1 * m1 + p2 * m2) / m,
+                (v1 * m1 + v2 * m2) / m,
+                m,
+            );
+            self.compensation.reset(&self.queue, pair.primary);
+            self.body_data.remove(&self.queue, pair.secondary);
+        }
+        Ok(())
+    }
+    /// Runs in the frame rotating with the primaries and overlays their zero
+    /// velocity curves and positions, or returns to an inertial frame with None. The
+    /// primaries' potentials are set separately with the other external
+    /// potentials
+    pub fn set_restricted_three_body(&mut self, params: Option<RestrictedThreeBody>) {
+        let gravitation_const = self.simulation.gravitation_const;
+        self.simulation.frame_rotation = params
+            .as_ref()
+            .map_or(0., |params| params.angular_velocity(gravitation_const));
+        self.three_body_overlay = params.as_ref().map(|params| {
+            let mut lines = params.zero_velocity_curves(gravitation_const);
+            lines.extend(params.primary_markers());
+            (
+                self.device
+                    .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                        label: Some("Restricted Three Body Overlay"),
+                        contents: bytemuck::cast_slice(&lines),
+                        usage: wgpu::BufferUsages::VERTEX,
+                    }),
+                lines.len() as u32,
+            )
+        });
+        self.restricted_three_body = params;
+        self.write_simulation_uniform();
+    }
+    /// Turns the rotating frame back to the inertial one for drawing, unless
+    /// the camera co-rotates
+    fn frame_view_rotation(&self) -> Mat4 {
+        match &self.restricted_three_body {
+            Some(params) if !params.co_rotating_camera => {
+                Mat4::from_rotation_z((self.simulation.frame_rotation as f64 * self.time) as f32)
+            }
+            _ => Mat4::IDENTITY,
+        }
+    }
+    /// Switches to comoving coordinates in an expanding universe, or back to
+    /// static space with None. The scale factor restarts at the cosmology's
+    /// initial redshift
+    pub fn set_cosmology(&mut self, cosmology: Option<Cosmology>) {
+        self.expansion = cosmology.map(Expansion::new);
+        if self.expansion.is_none() {
+            self.simulation.force_scale = 1.;
+            self.simulation.velocity_damping = 1.;
+            self.write_simulation_uniform();
+        }
+        self.drop_unsupported_escapes();
+    }
+    pub fn expansion(&self) -> Option<&Expansion> {
+        self.expansion.as_ref()
+    }
+    pub fn simulation_time(&self) -> f64 {
+        self.time
+    }
+    /// Reads the bodies back from the gpu and writes them to `path`, along with
+    /// the current time and scale factor
+    pub fn write_snapshot(&self, path: impl AsRef<std::path::Path>) -> Result<()> {
+        Snapshot {
+            time: self.time,
+            scale_factor: self.expansion.map(|e| e.scale_factor),
+            data: self
+                .body_data
+                .read_back(&self.device, &self.queue)
+                .with_context(|| "Failed to read back body data")?,
+        }
+        .write(path)
+    }
+    /// Reads the bodies back from the gpu and measures the total energy,
+    /// including the external potentials
+    pub fn measure_energy(&self) -> Result<EnergyReport> {
+        let data = self
+            .body_data
+            .read_back(&self.device, &self.queue)
+            .with_context(|| "Failed to read back body data")?;
+        Ok(EnergyReport::measure(
+            &data,
+            self.simulation.gravitation_const,
+            &self.external_potentials,
+        ))
+    }
     fn reconfigure_surface(&self) {
-        self.surface.configure(&self.device, &self.surface_config);
+        if let Some(surface) = &self.surface {
+            surface.configure(&self.device, &self.surface_config);
+        }
+    }
+    /// Those of `surface`, only vsync without one
+    fn present_modes(
+        surface: Option<&wgpu::Surface>,
+        adapter: &wgpu::Adapter,
+    ) -> Vec<wgpu::PresentMode> {
+        match surface {
+            Some(surface) => surface.get_capabilities(adapter).present_modes,
+            None => vec![wgpu::PresentMode::Fifo],
+        }
+    }
+    /// Size the scene is drawn at, see `RenderQuality::render_scale`
+    fn render_size(&self) -> (u32, u32) {
+        self.quality
+            .render_size(self.surface_config.width, self.surface_config.height)
+    }
+    /// Recreates the textures the scene is drawn into, after the output's
+    /// size or the render quality changed
+    fn rebuild_scene_targets(&mut self) {
+        let size = self.render_size();
+        let samples = self.quality.msaa_samples;
+        self.depth_texture = Self::generate_depth_texture(&self.device, size, samples);
+        self.msaa_target = Self::generate_msaa_target(&self.device, size, samples);
+        self.post_processing
+            .resize(&self.device, size.0, size.1, self.quality.render_scale);
+        if let Some(density) = &mut self.density {
+            density.resize(&self.device, size.0, size.1);
+        }
+    }
+    /// Resizes the scene without touching the surface
+    fn set_output_size(&mut self, width: u32, height: u32) {
+        self.surface_config.width = width;
+        self.surface_config.height = height;
+        self.rebuild_scene_targets();
     }
     pub fn resize(&mut self, size: winit::dpi::PhysicalSize<u32>) {
-        self.surface_config.width = size.width;
-        self.surface_config.height = size.height;
+        // The recording keeps its own size until it stops
+        if let Some(recording) = &mut self.recording {
+            recording.previous_size = (size.width, size.height);
+            return;
+        }
+        self.set_output_size(size.width, size.height);
         self.reconfigure_surface();
     }
-    fn create_uniform_bind_group(&self, uniform: rendering::Uniform) -> wgpu::BindGroup {
-        self.device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: None,
-            layout: &self
-                .device
-                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                    label: None,
-                    entries: &[rendering::Uniform::generate_bind_group_layout_entry(
-                        &self.device,
-                        0,
-                    )],
-                }),
+    /// Changes the MSAA sample count, render scale and present mode, each
+    /// limited to what the adapter supports, see `RenderQuality::supported`
+    pub fn set_render_quality(&mut self, quality: RenderQuality) {
+        let quality = quality.supported(
+            &self.adapter,
+            &self.device,
+            &Self::present_modes(self.surface.as_ref(), &self.adapter),
+            (self.surface_config.width, self.surface_config.height),
+        );
+        let resample = quality.msaa_samples != self.quality.msaa_samples;
+        self.quality = quality;
+        self.surface_config.present_mode = quality.present_mode.wgpu_present_mode();
+        self.reconfigure_surface();
+        self.rebuild_scene_targets();
+        if !resample {
+            return;
+        }
+        let samples = quality.msaa_samples;
+        self.render_pipeline = Self::generate_sprite_pipeline(&self.device, samples);
+        self.box_pipeline = Self::generate_render_pipeline(
+            &self.device,
+            wgpu::PrimitiveTopology::LineList,
+            samples,
+        );
+        if let Some(trails) = &mut self.trails {
+            trails.set_multisample(
+                &self.device,
+                Self::generate_depth_stencil_state(),
+                Self::generate_multisample_state(samples),
+            );
+        }
+        // Holds nothing across frames but its mass bound
+        if let Some(density) = self.density.take() {
+            self.density = Some(self.generate_density_field(density.total_mass()));
+        }
+    }
+    pub fn render_quality(&self) -> &RenderQuality {
+        &self.quality
+    }
+    pub fn supported_msaa_samples(&self) -> Vec<u32> {
+        RenderQuality::supported_msaa_samples(&self.adapter, &self.device)
+    }
+    pub fn supported_present_modes(&self) -> Vec<PresentMode> {
+        let modes = Self::present_modes(self.surface.as_ref(), &self.adapter);
+        [
+            PresentMode::Vsync,
+            PresentMode::Mailbox,
+            PresentMode::Immediate,
+        ]
+        .into_iter()
+        .filter(|mode| modes.contains(&mode.wgpu_present_mode()))
+        .collect()
+    }
+    fn generate_uniform_bind_group(
+        device: &wgpu::Device,
+        uniform_buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Uniform"),
+            layout: &device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: None,
+                entries: &[rendering::Uniform::generate_bind_group_layout_entry(
+                    device, 0,
+                )],
+            }),
             entries: &[wgpu::BindGroupEntry {
                 binding: 0,
-                resource: uniform.generate_buffer(&self.device).as_entire_binding(),
+                resource: uniform_buffer.as_entire_binding(),
             }],
         })
     }
+    /// Steps the simulation and draws it to the window
     pub fn render<M: ViewMode + Default>(&mut self, camera: &rendering::Camera<M>) -> Result<()> {
-        let uniform = rendering::UniformBuilder::default()
-            .height(self.surface_config.height)
-            .width(self.surface_config.width)
-            .world_mat(camera.generate_world_matrix_columns())
-            .build()
-            .with_context(|| "Failed to generate Uniform Struct from UniformBuilder")?;
-
         let surface_tex = self
             .surface
+            .as_ref()
+            .ok_or_else(|| anyhow!("There's no surface to render to, see render_to"))?
             .get_current_texture()
             .with_context(|| "Failed to get current surface texture")?;
 
         let view = surface_tex.texture.create_view(&Default::default());
 
         let mut command_encoder = self.device.create_command_encoder(&Default::default());
+        self.encode_step(&mut command_encoder);
+        self.encode_scene(camera, &mut command_encoder, &view)?;
+        self.queue.submit(Some(command_encoder.finish()));
+
+        surface_tex.present();
+
+        self.complete_step()
+    }
+    /// Steps the simulation and draws it into `target`, which has the
+    /// format of `output_format` and the size last given to `resize` or
+    /// `new_headless`
+    pub fn render_to<M: ViewMode + Default>(
+        &mut self,
+        camera: &rendering::Camera<M>,
+        target: &wgpu::TextureView,
+    ) -> Result<()> {
+        let mut command_encoder = self.device.create_command_encoder(&Default::default());
+        self.encode_step(&mut command_encoder);
+        self.encode_scene(camera, &mut command_encoder, target)?;
+        self.queue.submit(Some(command_encoder.finish()));
+
+        self.complete_step()
+    }
+    /// Draws the current state into a `width` by `height` image, without
+    /// stepping the simulation. `camera`'s aspect ratio should match. This
+    /// stalls until the gpu is done, so it shouldn't be used per frame
+    pub fn render_to_image<M: ViewMode + Default>(
+        &mut self,
+        camera: &rendering::Camera<M>,
+        width: u32,
+        height: u32,
+    ) -> Result<Image> {
+        self.check_output_size(width, height)?;
+        let output_size = (self.surface_config.width, self.surface_config.height);
+        let resized = output_size != (width, height);
+        if resized {
+            self.set_output_size(width, height);
+        }
+
+        let texture =
+            capture::generate_output_texture(&self.device, self.output_format(), width, height);
+        let mut command_encoder = self.device.create_command_encoder(&Default::default());
+        let drawn = self.encode_scene(
+            camera,
+            &mut command_encoder,
+            &texture.create_view(&Default::default()),
+        );
+        if drawn.is_ok() {
+            self.queue.submit(Some(command_encoder.finish()));
+        }
+        let image = drawn.and_then(|_| capture::read_texture(&self.device, &self.queue, &texture));
+
+        if resized {
+            self.set_output_size(output_size.0, output_size.1);
+        }
+        image
+    }
+    fn check_output_size(&self, width: u32, height: u32) -> Result<()> {
+        let limit = self.device.limits().max_texture_dimension_2d;
+        let (render_width, render_height) = self.quality.render_size(width, height);
+        if width == 0 || height == 0 || render_width.max(render_height) > limit {
+            bail!(
+                "Can't render a {width}x{height} image, the scene is limited to {limit} pixels across"
+            );
+        }
+        Ok(())
+    }
+    pub fn set_time_step(&mut self, time_step: f32) {
+        self.simulation.time_step = time_step;
+        self.write_simulation_uniform();
+    }
+    pub fn time_step(&self) -> f32 {
+        self.simulation.time_step
+    }
+    /// Steps the simulation without drawing it
+    pub fn step(&mut self) -> Result<()> {
+        let mut command_encoder = self.device.create_command_encoder(&Default::default());
+        self.encode_step(&mut command_encoder);
+        self.queue.submit(Some(command_encoder.finish()));
+
+        self.complete_step()
+    }
+    /// Starts rendering frames offscreen at the size, and stepping at the
+    /// time step, of `params`, one per `record_frame`. The output size and
+    /// time step are restored by `stop_recording`
+    pub fn start_recording(&mut self, params: RecordingParams) -> Result<()> {
+        if self.recording.is_some() {
+            bail!("Already recording");
+        }
+        self.check_output_size(params.width, params.height)?;
+        let recording = Recording::new(
+            &self.device,
+            self.output_format(),
+            params,
+            self.simulation.time_step,
+            (self.surface_config.width, self.surface_config.height),
+        )?;
+        let params = recording.params();
+        self.set_time_step(params.time_step);
+        self.set_output_size(params.width, params.height);
+        self.recording = Some(recording);
+        Ok(())
+    }
+    /// Steps the simulation `steps_per_frame` times and writes the frame
+    /// seen by `camera`, whose aspect ratio should match the recording's.
+    /// Stops the recording after its last frame
+    pub fn record_frame<M: ViewMode + Default>(
+        &mut self,
+        camera: &rendering::Camera<M>,
+    ) -> Result<()> {
+        let Some(mut recording) = self.recording.take() else {
+            bail!("Not recording");
+        };
+        let written = (|| {
+            for _ in 1..recording.params().steps_per_frame {
+                self.step()?;
+            }
+            self.render_to(camera, recording.view())?;
+            let image = capture::read_texture(&self.device, &self.queue, recording.texture())?;
+            recording.write_frame(&image)
+        })();
+        self.recording = Some(recording);
+        written.with_context(|| "Failed to record frame")?;
+        if self.recording.as_ref().is_some_and(Recording::finished) {
+            self.stop_recording()?;
+        }
+        Ok(())
+    }
+    /// Finishes the recording, waiting for its encoder if it has one
+    pub fn stop_recording(&mut self) -> Result<()> {
+        let Some(recording) = self.recording.take() else {
+            bail!("Not recording");
+        };
+        self.set_time_step(recording.previous_time_step);
+        let (width, height) = recording.previous_size;
+        self.set_output_size(width, height);
+        self.reconfigure_surface();
+        info!("Recorded {} frames", recording.frame());
+        recording.finish()
+    }
+    pub fn recording(&self) -> Option<&Recording> {
+        self.recording.as_ref()
+    }
+    /// Format of the window's surface, or of the images when headless
+    pub fn output_format(&self) -> wgpu::TextureFormat {
+        self.surface_config.format
+    }
+    /// Records the passes that advance the bodies by one time step
+    fn encode_step(&mut self, command_encoder: &mut wgpu::CommandEncoder) {
+        if let Some(expansion) = &self.expansion {
+            self.simulation.force_scale = expansion.force_scale();
+            self.simulation.velocity_damping =
+                expansion.velocity_damping(self.simulation.time_step as f64);
+            self.write_simulation_uniform();
+        }
 
-        let depth_texture = self.generate_depth_texture();
+        if let Some(post_newtonian) = &self.post_newtonian {
+            post_newtonian.encode_copy(command_encoder, &self.body_data.velocities);
+        }
+        {
+            let mut cpass = command_encoder.begin_compute_pass(&Default::default());
+            cpass.set_bind_group(0, &self.body_bind_group, &[]);
+            cpass.set_bind_group(1, &self.simulation_bind_group, &[]);
+            if self.solver.uses_direct_sum() {
+                cpass.set_pipeline(&self.compute_pipeline);
+                cpass.dispatch_workgroups(self.simulation.body_count.div_ceil(64), 1, 1);
+            }
+            cpass.set_pipeline(&self.external_pipeline);
+            cpass.dispatch_workgroups(self.simulation.body_count.div_ceil(64), 1, 1);
+        }
+        if let Some(post_newtonian) = &self.post_newtonian {
+            post_newtonian.encode(
+                command_encoder,
+                &self.body_bind_group,
+                &self.simulation_bind_group,
+                self.simulation.body_count,
+            );
+        }
+
+        if let Some(particle_mesh) = self
+            .particle_mesh
+            .as_ref()
+            .filter(|_| self.solver.uses_mesh())
+        {
+            particle_mesh.encode(
+                command_encoder,
+                &self.body_bind_group,
+                &self.simulation_bind_group,
+                self.simulation.body_count,
+            );
+        }
+
+        if let Some(hydrodynamics) = &self.hydrodynamics {
+            hydrodynamics.encode(
+                command_encoder,
+                &self.body_bind_group,
+                &self.simulation_bind_group,
+                self.simulation.body_count,
+            );
+        }
+
+        {
+            let mut ipass = command_encoder.begin_compute_pass(&Default::default());
+            ipass.set_pipeline(&self.incriment_pipeline);
+            ipass.set_bind_group(0, &self.body_bind_group, &[]);
+            ipass.set_bind_group(1, &self.simulation_bind_group, &[]);
+            ipass.dispatch_workgroups(self.simulation.body_count, 1, 1);
+        }
+
+        if let Some(sinks) = &self.sinks {
+            sinks.encode(
+                &self.queue,
+                command_encoder,
+                &self.body_bind_group,
+                &self.simulation_bind_group,
+                self.simulation.body_count,
+                self.time + self.simulation.time_step as f64,
+            );
+        }
+
+        if let Some(trails) = &mut self.trails {
+            trails.encode(
+                &self.queue,
+                command_encoder,
+                &self.body_bind_group,
+                (self.simulation.periodic, self.simulation.box_size),
+            );
+        }
+    }
+    /// Records the passes that draw the bodies into `target`, through the
+    /// HDR target and post processing
+    fn encode_scene<M: ViewMode + Default>(
+        &mut self,
+        camera: &rendering::Camera<M>,
+        command_encoder: &mut wgpu::CommandEncoder,
+        target: &wgpu::TextureView,
+    ) -> Result<()> {
+        let world_mat = Mat4::from_cols_array_2d(&camera.generate_world_matrix_columns())
+            * self.frame_view_rotation();
+        let (depth_mode, log_depth_far) = self.depth_mode.uniform_values();
+        let (width, height) = self.render_size();
+        let uniform = rendering::UniformBuilder::default()
+            .height(height)
+            .width(width)
+            .sprite_size(self.sprites.size)
+            // The minimum is in the surface's pixels
+            .min_sprite_pixels(self.sprites.min_pixels * self.quality.render_scale)
+            .projection_scale(camera.projection_scale())
+            .colormap(Colormap::uniform_value(
+                self.coloring.params().map(|params| params.colormap),
+            ))
+            .depth_mode(depth_mode)
+            .log_depth_far(log_depth_far)
+            .world_mat(world_mat.to_cols_array_2d())
+            .build()
+            .with_context(|| "Failed to generate Uniform Struct from UniformBuilder")?;
 
         let render_pass_descriptor = &wgpu::RenderPassDescriptor {
             label: None,
             color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                view: &view,
-                resolve_target: None,
+                view: self
+                    .msaa_target
+                    .as_ref()
+                    .unwrap_or(self.post_processing.hdr_view()),
+                resolve_target: self
+                    .msaa_target
+                    .as_ref()
+                    .map(|_| self.post_processing.hdr_view()),
                 ops: wgpu::Operations {
                     load: wgpu::LoadOp::Clear(crate::CLEAR_COLOR),
                     store: wgpu::StoreOp::Store,
                 },
             })],
             depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                view: &depth_texture,
+                view: &self.depth_texture,
                 depth_ops: Some(wgpu::Operations {
-                    load: wgpu::LoadOp::Clear(1.0),
+                    // Zero is infinitely far
+                    load: wgpu::LoadOp::Clear(0.0),
                     store: wgpu::StoreOp::Store,
                 }),
                 stencil_ops: None,
@@ -319,38 +1825,766 @@ impl<'s> Graphics<'s> {
             ..Default::default()
         };
 
-        let compute_bindgroups = self.generate_compute_bind_groups();
+        self.coloring.encode(
+            &self.queue,
+            command_encoder,
+            &self.body_bind_group,
+            &self.simulation_bind_group,
+            self.simulation.body_count,
+        );
 
+        self.queue
+            .write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&uniform));
+        if let Some(density) = &self.density {
+            density.encode(
+                &self.queue,
+                command_encoder,
+                self.render_mode,
+                &self.body_data,
+                &self.body_bind_group,
+                &self.simulation_bind_group,
+                &self.uniform_bind_group,
+                world_mat.inverse(),
+            );
+        }
         {
-            let mut cpass = command_encoder.begin_compute_pass(&Default::default());
-            cpass.set_pipeline(&self.compute_pipeline);
-            cpass.set_bind_group(0, &compute_bindgroups, &[]);
-            cpass.dispatch_workgroups(self.body_data.len as u32, self.body_data.len as u32, 1);
+            let mut rpass = command_encoder.begin_render_pass(render_pass_descriptor);
+
+            match (self.render_mode, &self.density) {
+                (RenderMode::Points, _) | (_, None) => {
+                    rpass.set_pipeline(&self.render_pipeline);
+
+                    rpass.set_bind_group(0, &self.uniform_bind_group, &[]);
+
+                    rpass.set_vertex_buffer(0, self.body_data.positions.slice(..));
+                    rpass.set_vertex_buffer(1, self.body_data.mass.slice(..));
+                    rpass.set_vertex_buffer(2, self.coloring.values().slice(..));
+
+                    rpass.draw(0..4, 0..self.simulation.body_count);
+                }
+                (mode, Some(density)) => density.draw(&mut rpass, mode, &self.uniform_bind_group),
+            }
+
+            if let Some(trails) = &self.trails {
+                trails.draw(&mut rpass, &self.uniform_bind_group);
+            }
+
+            if let Some((overlay, vertex_count)) = &self.three_body_overlay {
+                rpass.set_pipeline(&self.box_pipeline);
+                rpass.set_vertex_buffer(0, overlay.slice(..));
+                rpass.draw(0..*vertex_count, 0..1);
+            }
+
+            if let Some(outline) = &self.box_outline {
+                rpass.set_pipeline(&self.box_pipeline);
+                rpass.set_vertex_buffer(0, outline.slice(..));
+                rpass.draw(0..24, 0..1);
+            }
+        }
+        self.post_processing
+            .encode(&self.queue, command_encoder, target);
+
+        Ok(())
+    }
+    /// Advances the clock and runs the cpu side work of a submitted step
+    fn complete_step(&mut self) -> Result<()> {
+        self.time += self.simulation.time_step as f64;
+        if let Some(expansion) = &mut self.expansion {
+            expansion.advance(self.simulation.time_step as f64);
+        }
+
+        self.step_regularization()
+            .with_context(|| "Failed to step the regularized pairs")?;
+
+        if self.escapes.as_mut().is_some_and(EscapeTracker::frame_due) {
+            self.check_escapes()
+                .with_context(|| "Failed to check for escapes")?;
         }
 
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::physics::{imf::InitialMassFunction, restricted_three_body::RestrictedThreeBody};
+    use coloring::ColorQuantity;
+    use rendering::{Camera, ViewModeLookAt};
+    use vertices::GalaxyParams;
+
+    fn storage_buffers(entries: &[wgpu::BindGroupLayoutEntry]) -> u32 {
+        entries
+            .iter()
+            .filter(|entry| {
+                entry.visibility.contains(wgpu::ShaderStages::COMPUTE)
+                    && matches!(
+                        entry.ty,
+                        wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { .. },
+                            ..
+                        }
+                    )
+            })
+            .count() as u32
+    }
+
+    /// wgpu only checks each group against the limit, not their sum
+    #[test]
+    fn compute_layouts_fit_the_storage_buffer_limit() {
+        let shared = storage_buffers(&Graphics::generate_compute_pipeline_bg_entries())
+            + storage_buffers(&Graphics::generate_simulation_bg_entries());
+        for (name, entries) in [
+            ("Force kernel", Vec::new()),
+            (
+                "Post newtonian",
+                vec![PostNewtonian::generate_bind_group_layout_entry(0)],
+            ),
+            (
+                "Particle mesh",
+                ParticleMesh::generate_bind_group_layout_entries(),
+            ),
+            (
+                "Hydrodynamics",
+                Hydrodynamics::generate_bind_group_layout_entries(),
+            ),
+            ("Sinks", Sinks::generate_bind_group_layout_entries()),
+            ("Coloring", Coloring::generate_bind_group_layout_entries()),
+            ("Density", DensityField::generate_state_layout_entries()),
+        ] {
+            let count = shared + storage_buffers(&entries);
+            assert!(
+                count <= STORAGE_BUFFERS_PER_STAGE,
+                "{name} binds {count} storage buffers"
+            );
+        }
+    }
+
+    /// None on machines without an adapter, not even a software one
+    fn headless(initial_conditions: &InitialConditions) -> Option<Graphics<'static>> {
+        let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor {
+            backends: wgpu::Backends::all(),
+            ..Default::default()
+        });
+        if instance
+            .enumerate_adapters(wgpu::Backends::all())
+            .is_empty()
         {
-            let mut ipass = command_encoder.begin_compute_pass(&Default::default());
-            ipass.set_pipeline(&self.incriment_pipeline);
-            ipass.set_bind_group(0, &compute_bindgroups, &[]);
-            ipass.dispatch_workgroups(self.body_data.len as u32, 1, 1);
+            return None;
         }
+        let graphics = Graphics::new_headless(
+            instance,
+            (64, 48),
+            initial_conditions,
+            &UnitSystem::default(),
+        )
+        .unwrap();
+        graphics
+            .device
+            .on_uncaptured_error(Box::new(|error| panic!("{error}")));
+        Some(graphics)
+    }
+
+    /// Masses far below and far above one, which a fixed scale would round
+    /// away or overflow
+    #[test]
+    fn mesh_holds_the_body_mass() {
+        for mass_unit in [1e-7, 1e6] {
+            let initial_conditions = InitialConditions::Galaxy(GalaxyParams {
+                star_count: 256,
+                ..Default::default()
+            });
+            let Some(mut graphics) = headless(&initial_conditions) else {
+                return;
+            };
+            let bodies = graphics
+                .body_data
+                .read_back(&graphics.device, &graphics.queue)
+                .unwrap();
+            for (i, ((p, v), m)) in bodies
+                .positions
+                .iter()
+                .zip(bodies.velocities.iter())
+                .zip(bodies.mass.iter())
+                .enumerate()
+            {
+                graphics.body_data.write_body(
+                    &graphics.queue,
+                    i as u32,
+                    Vec4::from(*p).truncate(),
+                    Vec4::from(*v).truncate(),
+                    m * mass_unit,
+                );
+            }
+            let body_mass: f64 = bodies.mass.iter().map(|&m| (m * mass_unit) as f64).sum();
+
+            graphics.set_boundary(Boundary::Periodic { box_size: 4. });
+            graphics
+                .set_gravity_solver(GravitySolver::ParticleMesh)
+                .unwrap();
+            graphics.step().unwrap();
+            let density = graphics
+                .particle_mesh
+                .as_ref()
+                .unwrap()
+                .read_density(&graphics.device, &graphics.queue)
+                .unwrap();
+            let mesh_mass: f64 = density.iter().map(|&m| m as f64).sum();
+            assert!(
+                (mesh_mass - body_mass).abs() < 1e-4 * body_mass,
+                "deposited {mesh_mass} of {body_mass}"
+            );
+        }
+    }
+
+    /// The mesh and gas kicks go through the compensated sum too, which should
+    /// only change the rounding
+    #[test]
+    fn compensated_kicks_match_plain_ones() {
+        let initial_conditions = InitialConditions::Galaxy(GalaxyParams {
+            star_count: 256,
+            gas_fraction: 0.5,
+            ..Default::default()
+        });
+        let Some(mut graphics) = headless(&initial_conditions) else {
+            return;
+        };
+        graphics.set_boundary(Boundary::Periodic { box_size: 4. });
+        graphics
+            .set_gravity_solver(GravitySolver::ParticleMesh)
+            .unwrap();
+        graphics.set_hydrodynamics(Some(HydroParams::default()));
+        let start = graphics
+            .body_data
+            .read_back(&graphics.device, &graphics.queue)
+            .unwrap();
+
+        let mut run = |compensated| {
+            for (i, ((p, v), m)) in start
+                .positions
+                .iter()
+                .zip(start.velocities.iter())
+                .zip(start.mass.iter())
+                .enumerate()
+            {
+                graphics.body_data.write_body(
+                    &graphics.queue,
+                    i as u32,
+                    Vec4::from(*p).truncate(),
+                    Vec4::from(*v).truncate(),
+                    *m,
+                );
+            }
+            graphics.set_compensated_summation(compensated);
+            for _ in 0..4 {
+                graphics.step().unwrap();
+            }
+            graphics
+                .body_data
+                .read_back(&graphics.device, &graphics.queue)
+                .unwrap()
+                .velocities
+        };
+        let plain = run(false);
+        let compensated = run(true);
+
+        let largest = plain
+            .iter()
+            .map(|v| Vec4::from(*v).length())
+            .fold(0., f32::max);
+        let mut kicked = false;
+        for ((a, b), v) in plain
+            .iter()
+            .zip(compensated.iter())
+            .zip(start.velocities.iter())
+        {
+            let (a, b) = (Vec4::from(*a), Vec4::from(*b));
+            assert!(b.is_finite());
+            assert!(a.distance(b) < 1e-3 * largest, "{a} vs {b}");
+            kicked |= a != Vec4::from(*v);
+        }
+        assert!(kicked);
+    }
+
+    #[test]
+    fn periodic_box_stops_the_escape_watch() {
+        let initial_conditions = InitialConditions::Galaxy(GalaxyParams {
+            star_count: 64,
+            ..Default::default()
+        });
+        let Some(mut graphics) = headless(&initial_conditions) else {
+            return;
+        };
+        let criterion = EscapeCriterion {
+            check_interval: 1,
+            ..Default::default()
+        };
+        graphics.set_escape_criterion(Some(criterion)).unwrap();
+        graphics.step().unwrap();
+        graphics.set_boundary(Boundary::Periodic { box_size: 4. });
+        assert!(graphics.escapes().is_none());
+        graphics.step().unwrap();
+        assert!(graphics.set_escape_criterion(Some(criterion)).is_err());
+
+        graphics.set_boundary(Boundary::Open);
+        graphics.set_escape_criterion(Some(criterion)).unwrap();
+        graphics
+            .set_force_model(ForceModel::Mond {
+                acceleration_scale: 1.,
+            })
+            .unwrap();
+        assert!(graphics.escapes().is_none());
+        graphics.step().unwrap();
+    }
+
+    #[test]
+    fn removed_escapes_leave_the_passes() {
+        let initial_conditions = InitialConditions::Galaxy(GalaxyParams {
+            star_count: 64,
+            ..Default::default()
+        });
+        let Some(mut graphics) = headless(&initial_conditions) else {
+            return;
+        };
+        let len = graphics.body_data.len as u32;
+        let tags: Vec<u32> = (0..len).collect();
+        graphics.set_body_tags(&tags).unwrap();
+        for (body, direction) in [(3, Vec3::X), (len - 1, Vec3::Y)] {
+            graphics.body_data.write_body(
+                &graphics.queue,
+                body,
+                direction * 50.,
+                direction * 100.,
+                1.,
+            );
+        }
+        graphics
+            .set_escape_criterion(Some(EscapeCriterion {
+                check_interval: 1,
+                ..Default::default()
+            }))
+            .unwrap();
+        graphics.step().unwrap();
+
+        assert_eq!(graphics.simulation.body_count, len - 2);
+        let mut escaped: Vec<u32> = graphics
+            .escapes()
+            .unwrap()
+            .records
+            .iter()
+            .map(|record| record.body)
+            .collect();
+        escaped.sort();
+        assert_eq!(escaped, [3, len - 1]);
+        // The last live body took the interior slot, its tag with it
+        assert_eq!(graphics.body_tags[3], len - 2);
+        let bodies = graphics
+            .body_data
+            .read_back(&graphics.device, &graphics.queue)
+            .unwrap();
+        assert_eq!(bodies.positions[3][3], 1.);
+        assert!(bodies.positions[len as usize - 2..]
+            .iter()
+            .all(|position| position[3] == 0.));
+        graphics.step().unwrap();
+    }
+
+    /// The restricted three body test particles are massless
+    #[test]
+    fn coulomb_needs_massive_bodies() {
+        let initial_conditions =
+            InitialConditions::RestrictedThreeBody(RestrictedThreeBody::default());
+        let Some(mut graphics) = headless(&initial_conditions) else {
+            return;
+        };
+        let charges = vec![1.; graphics.body_data.len];
+        assert!(graphics
+            .set_force_model(ForceModel::Coulomb {
+                charges: charges.clone()
+            })
+            .is_err());
+
+        let initial_conditions = InitialConditions::Galaxy(GalaxyParams {
+            star_count: 64,
+            ..Default::default()
+        });
+        let Some(mut graphics) = headless(&initial_conditions) else {
+            return;
+        };
+        let charges = (0..graphics.body_data.len)
+            .map(|i| if i % 2 == 0 { 1e-2 } else { -1e-2 })
+            .collect();
+        graphics
+            .set_force_model(ForceModel::Coulomb { charges })
+            .unwrap();
+        graphics.step().unwrap();
+        let bodies = graphics
+            .body_data
+            .read_back(&graphics.device, &graphics.queue)
+            .unwrap();
+        assert!(bodies.velocities.iter().all(|v| Vec4::from(*v).is_finite()));
+    }
 
+    /// Stars far lighter than the central body, which a scale relative to the
+    /// heaviest would round away
+    #[test]
+    fn volume_holds_the_light_bodies() {
+        let initial_conditions = InitialConditions::Galaxy(GalaxyParams {
+            star_count: 256,
+            ..Default::default()
+        });
+        let Some(mut graphics) = headless(&initial_conditions) else {
+            return;
+        };
+        let bodies = graphics
+            .body_data
+            .read_back(&graphics.device, &graphics.queue)
+            .unwrap();
+        let mass = |i: usize| if i == 0 { 1e5 } else { 1. };
+        for (i, (p, v)) in bodies
+            .positions
+            .iter()
+            .zip(bodies.velocities.iter())
+            .enumerate()
         {
-            let mut rpass = command_encoder.begin_render_pass(&render_pass_descriptor);
+            graphics.body_data.write_body(
+                &graphics.queue,
+                i as u32,
+                Vec4::from(*p).truncate(),
+                Vec4::from(*v).truncate(),
+                mass(i),
+            );
+        }
+        let body_mass: f64 = (0..bodies.mass.len()).map(|i| mass(i) as f64).sum();
 
-            rpass.set_pipeline(&self.render_pipeline);
+        graphics.set_render_mode(RenderMode::Volume).unwrap();
+        let camera = Camera::<ViewModeLookAt>::new(
+            Vec3::ZERO,
+            Vec3::splat(2.5),
+            Vec3::Y,
+            2.,
+            64. / 48.,
+            1e-4,
+        );
+        graphics.render_to_image(&camera, 64, 48).unwrap();
+        let grid = graphics
+            .density
+            .as_ref()
+            .unwrap()
+            .read_grid(&graphics.device, &graphics.queue)
+            .unwrap();
+        let grid_mass: f64 = grid.iter().map(|&m| m as f64).sum();
+        // The galaxy fits inside the volume, so none of it should be missing
+        assert!(
+            (grid_mass - body_mass).abs() < 1.,
+            "deposited {grid_mass} of {body_mass}"
+        );
+    }
 
-            rpass.set_bind_group(0, &self.create_uniform_bind_group(uniform), &[]);
+    #[test]
+    fn galaxy_orbits_the_enclosed_mass() {
+        let params = GalaxyParams {
+            star_count: 512,
+            mass_function: Some(InitialMassFunction::default()),
+            ..Default::default()
+        };
+        let Some(graphics) = headless(&InitialConditions::Galaxy(params.clone())) else {
+            return;
+        };
+        let bodies = graphics
+            .body_data
+            .read_back(&graphics.device, &graphics.queue)
+            .unwrap();
+        let radius = |i: usize| Vec4::from(bodies.positions[i]).truncate().length();
+        let stars_mass: f32 = bodies.mass[1..].iter().sum();
+        assert!((bodies.mass[0] - stars_mass / 8.).abs() < 1e-3 * bodies.mass[0]);
 
-            rpass.set_vertex_buffer(0, self.body_data.positions.slice(..));
+        let mut order: Vec<usize> = (1..bodies.mass.len()).collect();
+        order.sort_by(|&a, &b| radius(a).total_cmp(&radius(b)));
+        let mut enclosed = bodies.mass[0];
+        for i in order {
+            let speed = Vec4::from(bodies.velocities[i]).length();
+            let expected =
+                (params.rotation_support * graphics.simulation.gravitation_const * enclosed
+                    / radius(i))
+                .sqrt();
+            assert!(
+                (speed - expected).abs() < 1e-3 * expected,
+                "star {i} moves at {speed} rather than {expected}"
+            );
+            enclosed += bodies.mass[i];
+        }
+    }
 
-            rpass.draw(0..(self.body_data.len as u32), 0..1);
+    #[test]
+    fn post_newtonian_threshold_picks_the_pairs() {
+        let initial_conditions = InitialConditions::Galaxy(GalaxyParams {
+            star_count: 64,
+            ..Default::default()
+        });
+        let Some(mut graphics) = headless(&initial_conditions) else {
+            return;
+        };
+        let start = graphics
+            .body_data
+            .read_back(&graphics.device, &graphics.queue)
+            .unwrap();
+
+        let mut run = |post_newtonian| {
+            for (i, ((p, v), m)) in start
+                .positions
+                .iter()
+                .zip(start.velocities.iter())
+                .zip(start.mass.iter())
+                .enumerate()
+            {
+                graphics.body_data.write_body(
+                    &graphics.queue,
+                    i as u32,
+                    Vec4::from(*p).truncate(),
+                    Vec4::from(*v).truncate(),
+                    *m,
+                );
+            }
+            graphics.set_post_newtonian(post_newtonian).unwrap();
+            graphics.step().unwrap();
+            let velocities = graphics
+                .body_data
+                .read_back(&graphics.device, &graphics.queue)
+                .unwrap()
+                .velocities;
+            (velocities, graphics.simulation.post_newtonian_enabled)
+        };
+        let (newtonian, enabled) = run(None);
+        assert_eq!(enabled, 0);
+        let (listed, enabled) = run(Some(PostNewtonian {
+            speed_of_light: 10.,
+            mass_threshold: f32::INFINITY,
+            pairs: vec![[0, 1]],
+        }));
+        assert_eq!(enabled, 0);
+        let (every, enabled) = run(Some(PostNewtonian {
+            speed_of_light: 10.,
+            mass_threshold: 0.,
+            pairs: vec![],
+        }));
+        assert_eq!(enabled, 1);
+
+        for (i, ((n, l), e)) in newtonian
+            .iter()
+            .zip(listed.iter())
+            .zip(every.iter())
+            .enumerate()
+        {
+            if i > 1 {
+                assert_eq!(n, l, "body {i} was corrected without being listed");
+            } else {
+                assert_ne!(n, l, "listed body {i} was not corrected");
+            }
+            assert_ne!(n, e, "body {i} was not corrected by the threshold");
         }
+    }
 
-        self.queue.submit(Some(command_encoder.finish()));
+    #[test]
+    fn trails_record_the_selected_bodies() {
+        let initial_conditions = InitialConditions::Galaxy(GalaxyParams {
+            star_count: 64,
+            ..Default::default()
+        });
+        let Some(mut graphics) = headless(&initial_conditions) else {
+            return;
+        };
+        let selection = vec![7, 3, 1000];
+        graphics.set_trails(Some(TrailParams {
+            interval: 1,
+            selection: TrailSelection::Bodies(selection.clone()),
+            ..Default::default()
+        }));
+        for _ in 0..3 {
+            graphics.step().unwrap();
+        }
+        let trails = graphics.trails.as_ref().unwrap();
+        assert_eq!(trails.selected(), [7, 3]);
+        let newest = trails
+            .read_newest(&graphics.device, &graphics.queue)
+            .unwrap();
+        let bodies = graphics
+            .body_data
+            .read_back(&graphics.device, &graphics.queue)
+            .unwrap();
+        assert_eq!(newest, [bodies.positions[7], bodies.positions[3]]);
 
-        surface_tex.present();
+        // The same selection keeps the paths, another one starts afresh
+        graphics.set_trails(Some(TrailParams {
+            interval: 1,
+            fade: 2.,
+            selection: TrailSelection::Bodies(selection),
+            ..Default::default()
+        }));
+        let trails = graphics.trails.as_ref().unwrap();
+        assert_eq!(
+            trails
+                .read_newest(&graphics.device, &graphics.queue)
+                .unwrap(),
+            newest
+        );
+        graphics.set_trails(Some(TrailParams {
+            interval: 1,
+            selection: TrailSelection::Bodies(vec![5]),
+            ..Default::default()
+        }));
+        graphics.step().unwrap();
+        let trails = graphics.trails.as_ref().unwrap();
+        let bodies = graphics
+            .body_data
+            .read_back(&graphics.device, &graphics.queue)
+            .unwrap();
+        assert_eq!(
+            trails
+                .read_newest(&graphics.device, &graphics.queue)
+                .unwrap(),
+            [bodies.positions[5]]
+        );
+    }
 
-        Ok(())
+    #[test]
+    fn initial_conditions_tag_their_bodies() {
+        let initial_conditions = InitialConditions::Galaxy(GalaxyParams {
+            star_count: 256,
+            gas_fraction: 0.5,
+            ..Default::default()
+        });
+        let Some(mut graphics) = headless(&initial_conditions) else {
+            return;
+        };
+        let bodies = graphics
+            .body_data
+            .read_back(&graphics.device, &graphics.queue)
+            .unwrap();
+        assert_eq!(graphics.body_tags[0], vertices::TAG_GALAXY_CENTRE);
+        for (tag, species) in graphics.body_tags[1..].iter().zip(&bodies.species[1..]) {
+            let expected = match *species {
+                vertices::SPECIES_GAS => vertices::TAG_GALAXY_GAS,
+                _ => vertices::TAG_GALAXY_STAR,
+            };
+            assert_eq!(*tag, expected);
+        }
+        graphics.set_trails(Some(TrailParams {
+            selection: TrailSelection::Groups(vec![vertices::TAG_GALAXY_GAS]),
+            ..Default::default()
+        }));
+        let gas: Vec<u32> = (0..bodies.species.len() as u32)
+            .filter(|&body| bodies.species[body as usize] == vertices::SPECIES_GAS)
+            .collect();
+        assert!(!gas.is_empty());
+        assert_eq!(graphics.trails.as_ref().unwrap().selected(), gas);
+
+        let params = RestrictedThreeBody {
+            disc_count: 30,
+            trojan_count: 20,
+            ..Default::default()
+        };
+        let graphics = headless(&InitialConditions::RestrictedThreeBody(params)).unwrap();
+        assert_eq!(
+            graphics.body_tags,
+            [
+                [vertices::TAG_DISC; 30].as_slice(),
+                &[vertices::TAG_TROJAN; 20]
+            ]
+            .concat()
+        );
+    }
+
+    #[test]
+    fn every_subsystem_steps_and_draws() {
+        let initial_conditions = InitialConditions::Galaxy(GalaxyParams {
+            star_count: 512,
+            gas_fraction: 0.5,
+            central_sink: true,
+            ..Default::default()
+        });
+        let Some(mut graphics) = headless(&initial_conditions) else {
+            return;
+        };
+        graphics.set_boundary(Boundary::Periodic { box_size: 10. });
+        graphics
+            .set_gravity_solver(GravitySolver::P3M { split_scale: 0.2 })
+            .unwrap();
+        graphics
+            .set_post_newtonian(Some(PostNewtonian {
+                speed_of_light: 100.,
+                mass_threshold: f32::INFINITY,
+                pairs: vec![[0, 1]],
+            }))
+            .unwrap();
+        graphics.set_compensated_summation(true);
+        graphics.set_hydrodynamics(Some(HydroParams::default()));
+        graphics.set_sinks(Some(SinkParams::default()));
+        graphics.set_coloring(Some(ColorParams {
+            quantity: ColorQuantity::LocalDensity,
+            ..Default::default()
+        }));
+        graphics.set_trails(Some(TrailParams::default()));
+
+        let camera = Camera::<ViewModeLookAt>::new(
+            Vec3::ZERO,
+            Vec3::splat(2.5),
+            Vec3::Y,
+            2.,
+            64. / 48.,
+            1e-4,
+        );
+        for mode in [RenderMode::Points, RenderMode::Density, RenderMode::Volume] {
+            graphics.set_render_mode(mode).unwrap();
+            graphics.step().unwrap();
+            let image = graphics.render_to_image(&camera, 64, 48).unwrap();
+            assert_eq!(image.pixels.len(), 64 * 48 * 4);
+        }
+    }
+
+    /// Cpu time per frame, run with `cargo test --release -- --ignored
+    /// --nocapture frame_times`
+    #[test]
+    #[ignore = "timing rather than a check"]
+    fn frame_times() {
+        // Few enough bodies that drawing, not the pairwise sum, dominates
+        let initial_conditions = InitialConditions::Galaxy(GalaxyParams {
+            star_count: 256,
+            ..Default::default()
+        });
+        let Some(mut graphics) = headless(&initial_conditions) else {
+            return;
+        };
+        let (width, height) = (1280, 720);
+        graphics.set_output_size(width, height);
+        let target = capture::generate_output_texture(
+            &graphics.device,
+            graphics.output_format(),
+            width,
+            height,
+        )
+        .create_view(&Default::default());
+        let camera = Camera::<ViewModeLookAt>::new(
+            Vec3::ZERO,
+            Vec3::splat(2.5),
+            Vec3::Y,
+            2.,
+            width as f32 / height as f32,
+            1e-4,
+        );
+        let frames = 300;
+        let (mut render, mut interval) = (std::time::Duration::ZERO, std::time::Duration::ZERO);
+        for _ in 0..frames {
+            let start = std::time::Instant::now();
+            graphics.render_to(&camera, &target).unwrap();
+            render += start.elapsed();
+            graphics.device.poll(wgpu::Maintain::Wait);
+            interval += start.elapsed();
+        }
+        println!(
+            "render: mean {:?} - interval: mean {:?}",
+            render / frames,
+            interval / frames
+        );
     }
 }
@@ -0,0 +1,541 @@
+use bytemuck::bytes_of;
+
+use crate::prelude::*;
+
+/// Format the scene is drawn into before tone mapping
+pub const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum ToneMapping {
+    /// Filmic, keeps some contrast in the highlights
+    #[default]
+    Aces,
+    Reinhard,
+}
+
+impl ToneMapping {
+    /// Mirrors the `TONE_MAPPING_*` constants in tone_mapping.wgsl
+    fn uniform_value(self) -> u32 {
+        match self {
+            Self::Aces => 0,
+            Self::Reinhard => 1,
+        }
+    }
+}
+
+#[derive(Builder, Debug, Clone, Copy, PartialEq)]
+#[builder(default)]
+pub struct PostParams {
+    /// Scales the scene before tone mapping, the starting point when
+    /// `auto_exposure` is set
+    pub exposure: f32,
+    pub tone_mapping: ToneMapping,
+    /// How much of the blurred scene is added back, zero skips the bloom
+    pub bloom_strength: f32,
+    /// Number of times the scene is halved for the bloom, more gives a wider
+    /// glow
+    pub bloom_levels: u32,
+    /// Adjusts the exposure so the average lit pixel maps to `exposure_key`
+    pub auto_exposure: bool,
+    pub exposure_key: f32,
+    /// Fraction of the way the exposure adapts each frame
+    pub adaptation: f32,
+}
+
+impl Default for PostParams {
+    fn default() -> Self {
+        Self {
+            exposure: 1.,
+            tone_mapping: ToneMapping::default(),
+            bloom_strength: 0.05,
+            bloom_levels: 6,
+            auto_exposure: false,
+            exposure_key: 0.18,
+            adaptation: 0.05,
+        }
+    }
+}
+
+/// Mirrors the `Post` struct in tone_mapping.wgsl and exposure.wgsl
+#[derive(Debug, Pod, Zeroable, Copy, Clone)]
+#[repr(C)]
+struct PostUniform {
+    exposure: f32,
+    bloom_strength: f32,
+    tone_mapping: u32,
+    encode_srgb: u32,
+    exposure_key: f32,
+    adaptation: f32,
+    auto_exposure: u32,
+    render_scale: f32,
+}
+
+/// Side length of the grid the auto exposure samples, see exposure.wgsl
+const EXPOSURE_SAMPLE_GRID: u32 = 64;
+
+/// Textures sized to the scene, rebuilt on resize
+#[derive(Debug)]
+struct Targets {
+    hdr: wgpu::TextureView,
+    /// Each level half the size of the one before, the first half the
+    /// scene's size
+    bloom: Vec<wgpu::TextureView>,
+    downsample_groups: Vec<wgpu::BindGroup>,
+    /// `upsample_groups[i]` reads bloom level i + 1
+    upsample_groups: Vec<wgpu::BindGroup>,
+    composite_group: wgpu::BindGroup,
+    exposure_group: wgpu::BindGroup,
+}
+
+/// The scene is drawn additively into a float target, so dense regions keep
+/// getting brighter rather than clipping. This then blooms, exposes and tone
+/// maps it onto the surface
+#[derive(Debug)]
+pub struct PostProcessing {
+    params: PostParams,
+    /// Whether the surface format is linear, so the shader has to encode
+    encode_srgb: bool,
+    uniform: wgpu::Buffer,
+    exposure: wgpu::Buffer,
+    luminance: wgpu::Buffer,
+    sampler: wgpu::Sampler,
+    downsample_pipeline: wgpu::RenderPipeline,
+    upsample_pipeline: wgpu::RenderPipeline,
+    composite_pipeline: wgpu::RenderPipeline,
+    luminance_pipeline: wgpu::ComputePipeline,
+    adapt_pipeline: wgpu::ComputePipeline,
+    exposure_layout: wgpu::BindGroupLayout,
+    targets: Targets,
+    size: (u32, u32),
+    /// Of the scene to the surface, see `RenderQuality::render_scale`
+    render_scale: f32,
+}
+
+impl PostProcessing {
+    fn generate_fullscreen_pipeline(
+        device: &wgpu::Device,
+        module: &wgpu::ShaderModule,
+        entry_point: &str,
+        format: wgpu::TextureFormat,
+        blend: Option<wgpu::BlendState>,
+    ) -> wgpu::RenderPipeline {
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(entry_point),
+            layout: None,
+            vertex: wgpu::VertexState {
+                module,
+                entry_point: Some("vs_fullscreen"),
+                compilation_options: Default::default(),
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module,
+                entry_point: Some(entry_point),
+                compilation_options: Default::default(),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: Default::default(),
+            depth_stencil: None,
+            multisample: Default::default(),
+            multiview: None,
+            cache: None,
+        })
+    }
+    fn generate_texture(
+        device: &wgpu::Device,
+        label: &str,
+        width: u32,
+        height: u32,
+    ) -> wgpu::TextureView {
+        device
+            .create_texture(&wgpu::TextureDescriptor {
+                label: Some(label),
+                size: wgpu::Extent3d {
+                    width: width.max(1),
+                    height: height.max(1),
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: HDR_FORMAT,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                    | wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            })
+            .create_view(&Default::default())
+    }
+    fn generate_exposure_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        let buffer = |binding, ty| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let storage = wgpu::BufferBindingType::Storage { read_only: false };
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Auto Exposure"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                buffer(1, storage),
+                buffer(2, storage),
+                buffer(3, wgpu::BufferBindingType::Uniform),
+            ],
+        })
+    }
+    /// `surface_format` is what the composite pass writes. The scene starts
+    /// at the surface's size, see `resize`
+    pub fn new(
+        device: &wgpu::Device,
+        surface_format: wgpu::TextureFormat,
+        width: u32,
+        height: u32,
+        params: PostParams,
+    ) -> Self {
+        let uniform = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Post Processing Uniform"),
+            size: size_of::<PostUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let exposure = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Exposure"),
+            contents: bytes_of(&params.exposure),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        });
+        let luminance = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Luminance Sums"),
+            size: size_of::<[u32; 2]>() as u64,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Post Processing Sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let bloom = device.create_shader_module(include_wgsl!("../../shaders/bloom.wgsl"));
+        let tone_mapping =
+            device.create_shader_module(include_wgsl!("../../shaders/tone_mapping.wgsl"));
+        let additive = wgpu::BlendState {
+            color: wgpu::BlendComponent {
+                src_factor: wgpu::BlendFactor::One,
+                dst_factor: wgpu::BlendFactor::One,
+                operation: wgpu::BlendOperation::Add,
+            },
+            alpha: wgpu::BlendComponent::REPLACE,
+        };
+        let exposure_module =
+            device.create_shader_module(include_wgsl!("../../shaders/exposure.wgsl"));
+        // Shared by both exposure passes, which each use only some of it
+        let exposure_layout = Self::generate_exposure_layout(device);
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Auto Exposure"),
+            bind_group_layouts: &[&exposure_layout],
+            push_constant_ranges: &[],
+        });
+        let compute_pipeline = |entry_point: &str| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(entry_point),
+                layout: Some(&layout),
+                module: &exposure_module,
+                entry_point: Some(entry_point),
+                compilation_options: Default::default(),
+                cache: None,
+            })
+        };
+
+        let downsample_pipeline =
+            Self::generate_fullscreen_pipeline(device, &bloom, "fs_downsample", HDR_FORMAT, None);
+        let upsample_pipeline = Self::generate_fullscreen_pipeline(
+            device,
+            &bloom,
+            "fs_upsample",
+            HDR_FORMAT,
+            Some(additive),
+        );
+        let composite_pipeline = Self::generate_fullscreen_pipeline(
+            device,
+            &tone_mapping,
+            "fs_composite",
+            surface_format,
+            None,
+        );
+        let luminance_pipeline = compute_pipeline("accumulate_luminance");
+        let adapt_pipeline = compute_pipeline("adapt_exposure");
+
+        Self {
+            targets: Self::generate_targets(
+                device,
+                &downsample_pipeline,
+                &upsample_pipeline,
+                &composite_pipeline,
+                &exposure_layout,
+                [&uniform, &exposure, &luminance],
+                &sampler,
+                width,
+                height,
+                params.bloom_levels,
+            ),
+            params,
+            encode_srgb: !surface_format.is_srgb(),
+            uniform,
+            exposure,
+            luminance,
+            sampler,
+            downsample_pipeline,
+            upsample_pipeline,
+            composite_pipeline,
+            luminance_pipeline,
+            adapt_pipeline,
+            exposure_layout,
+            size: (width, height),
+            render_scale: 1.,
+        }
+    }
+    #[allow(clippy::too_many_arguments)]
+    fn generate_targets(
+        device: &wgpu::Device,
+        downsample_pipeline: &wgpu::RenderPipeline,
+        upsample_pipeline: &wgpu::RenderPipeline,
+        composite_pipeline: &wgpu::RenderPipeline,
+        exposure_layout: &wgpu::BindGroupLayout,
+        [uniform, exposure, luminance]: [&wgpu::Buffer; 3],
+        sampler: &wgpu::Sampler,
+        width: u32,
+        height: u32,
+        bloom_levels: u32,
+    ) -> Targets {
+        let hdr = Self::generate_texture(device, "HDR Target", width, height);
+        // Stop before the smallest level would be under a pixel across
+        let levels = bloom_levels.min(width.min(height).max(2).ilog2()).max(1);
+        let bloom: Vec<_> = (1..=levels)
+            .map(|i| Self::generate_texture(device, "Bloom Level", width >> i, height >> i))
+            .collect();
+
+        let sample_group = |pipeline: &wgpu::RenderPipeline, source: &wgpu::TextureView| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: None,
+                layout: &pipeline.get_bind_group_layout(0),
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(source),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(sampler),
+                    },
+                ],
+            })
+        };
+        let downsample_groups = std::iter::once(&hdr)
+            .chain(&bloom[..bloom.len() - 1])
+            .map(|source| sample_group(downsample_pipeline, source))
+            .collect();
+        let upsample_groups = bloom[1..]
+            .iter()
+            .map(|source| sample_group(upsample_pipeline, source))
+            .collect();
+
+        let composite_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Composite"),
+            layout: &composite_pipeline.get_bind_group_layout(0),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&hdr),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&bloom[0]),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: uniform.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: exposure.as_entire_binding(),
+                },
+            ],
+        });
+        let exposure_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Auto Exposure"),
+            layout: exposure_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&hdr),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: luminance.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: exposure.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: uniform.as_entire_binding(),
+                },
+            ],
+        });
+
+        Targets {
+            hdr,
+            bloom,
+            downsample_groups,
+            upsample_groups,
+            composite_group,
+            exposure_group,
+        }
+    }
+    /// Rebuilds the targets for a new scene size, `render_scale` times the
+    /// surface's
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32, render_scale: f32) {
+        self.size = (width, height);
+        self.render_scale = render_scale;
+        self.targets = Self::generate_targets(
+            device,
+            &self.downsample_pipeline,
+            &self.upsample_pipeline,
+            &self.composite_pipeline,
+            &self.exposure_layout,
+            [&self.uniform, &self.exposure, &self.luminance],
+            &self.sampler,
+            width,
+            height,
+            self.params.bloom_levels,
+        );
+    }
+    pub fn params(&self) -> &PostParams {
+        &self.params
+    }
+    pub fn set_params(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, params: PostParams) {
+        // Auto exposure starts from the manual exposure
+        if params.auto_exposure && !self.params.auto_exposure {
+            queue.write_buffer(&self.exposure, 0, bytes_of(&params.exposure));
+        }
+        let rebuild = params.bloom_levels != self.params.bloom_levels;
+        self.params = params;
+        if rebuild {
+            self.resize(device, self.size.0, self.size.1, self.render_scale);
+        }
+    }
+    /// The view the scene is drawn into
+    pub fn hdr_view(&self) -> &wgpu::TextureView {
+        &self.targets.hdr
+    }
+    /// Records the passes from the drawn HDR target to `surface_view`
+    pub fn encode(
+        &self,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        surface_view: &wgpu::TextureView,
+    ) {
+        let params = &self.params;
+        let uniform = PostUniform {
+            exposure: params.exposure,
+            bloom_strength: params.bloom_strength,
+            tone_mapping: params.tone_mapping.uniform_value(),
+            encode_srgb: self.encode_srgb as u32,
+            exposure_key: params.exposure_key,
+            adaptation: params.adaptation,
+            auto_exposure: params.auto_exposure as u32,
+            render_scale: self.render_scale,
+        };
+        queue.write_buffer(&self.uniform, 0, bytes_of(&uniform));
+
+        if params.auto_exposure {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Auto Exposure"),
+                timestamp_writes: None,
+            });
+            pass.set_bind_group(0, &self.targets.exposure_group, &[]);
+            pass.set_pipeline(&self.luminance_pipeline);
+            let groups = EXPOSURE_SAMPLE_GRID.div_ceil(8);
+            pass.dispatch_workgroups(groups, groups, 1);
+            pass.set_pipeline(&self.adapt_pipeline);
+            pass.dispatch_workgroups(1, 1, 1);
+        } else {
+            queue.write_buffer(&self.exposure, 0, bytes_of(&params.exposure));
+        }
+
+        let fullscreen_pass = |encoder: &mut wgpu::CommandEncoder,
+                               view: &wgpu::TextureView,
+                               load: wgpu::LoadOp<wgpu::Color>,
+                               pipeline: &wgpu::RenderPipeline,
+                               group: &wgpu::BindGroup| {
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: None,
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load,
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                ..Default::default()
+            });
+            pass.set_pipeline(pipeline);
+            pass.set_bind_group(0, group, &[]);
+            pass.draw(0..3, 0..1);
+        };
+
+        let targets = &self.targets;
+        if params.bloom_strength > 0. {
+            for (view, group) in targets.bloom.iter().zip(&targets.downsample_groups) {
+                fullscreen_pass(
+                    encoder,
+                    view,
+                    wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    &self.downsample_pipeline,
+                    group,
+                );
+            }
+            // Back up the chain, each level adding the blurred one below
+            for (view, group) in targets.bloom.iter().zip(&targets.upsample_groups).rev() {
+                fullscreen_pass(
+                    encoder,
+                    view,
+                    wgpu::LoadOp::Load,
+                    &self.upsample_pipeline,
+                    group,
+                );
+            }
+        }
+        fullscreen_pass(
+            encoder,
+            surface_view,
+            wgpu::LoadOp::Clear(crate::CLEAR_COLOR),
+            &self.composite_pipeline,
+            &targets.composite_group,
+        );
+    }
+}
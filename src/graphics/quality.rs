@@ -0,0 +1,121 @@
+use super::post_processing::HDR_FORMAT;
+use super::DEPTH_FORMAT;
+use crate::prelude::*;
+
+/// How finished frames are handed to the display
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum PresentMode {
+    /// Waits for the display's refresh, never tears
+    #[default]
+    Vsync,
+    /// Shows the newest finished frame at each refresh, without tearing or
+    /// waiting. Not every platform has it
+    Mailbox,
+    /// Shows each frame as soon as it's done, may tear
+    Immediate,
+}
+
+impl PresentMode {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Vsync => Self::Mailbox,
+            Self::Mailbox => Self::Immediate,
+            Self::Immediate => Self::Vsync,
+        }
+    }
+    pub fn wgpu_present_mode(self) -> wgpu::PresentMode {
+        match self {
+            Self::Vsync => wgpu::PresentMode::Fifo,
+            Self::Mailbox => wgpu::PresentMode::Mailbox,
+            Self::Immediate => wgpu::PresentMode::Immediate,
+        }
+    }
+}
+
+#[derive(Builder, Debug, Clone, Copy, PartialEq)]
+#[builder(default)]
+pub struct RenderQuality {
+    /// Samples per pixel of the scene, one turns MSAA off
+    pub msaa_samples: u32,
+    /// Size the scene is drawn at relative to the surface, above one
+    /// supersamples and below one trades sharpness for speed
+    pub render_scale: f32,
+    pub present_mode: PresentMode,
+}
+
+impl Default for RenderQuality {
+    fn default() -> Self {
+        Self {
+            msaa_samples: 4,
+            render_scale: 1.,
+            present_mode: PresentMode::default(),
+        }
+    }
+}
+
+impl RenderQuality {
+    const RENDER_SCALE_RANGE: (f32, f32) = (0.25, 4.);
+
+    /// Sample counts the scene's targets can have on `device`
+    pub fn supported_msaa_samples(adapter: &wgpu::Adapter, device: &wgpu::Device) -> Vec<u32> {
+        let flags = |format: wgpu::TextureFormat| {
+            // Beyond the guaranteed counts needs the adapter specific feature
+            if device
+                .features()
+                .contains(wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES)
+            {
+                adapter.get_texture_format_features(format).flags
+            } else {
+                format.guaranteed_format_features(device.features()).flags
+            }
+        };
+        let (hdr, depth) = (flags(HDR_FORMAT), flags(DEPTH_FORMAT));
+        [1, 2, 4, 8, 16]
+            .into_iter()
+            .filter(|&count| {
+                count == 1
+                    || (hdr.sample_count_supported(count)
+                        && depth.sample_count_supported(count)
+                        && hdr.contains(wgpu::TextureFormatFeatureFlags::MULTISAMPLE_RESOLVE))
+            })
+            .collect()
+    }
+    /// The closest settings that can be used: the most samples supported up
+    /// to those asked for, a scale that keeps the scene within the texture
+    /// size limit, and vsync in place of an unsupported present mode
+    pub fn supported(
+        self,
+        adapter: &wgpu::Adapter,
+        device: &wgpu::Device,
+        present_modes: &[wgpu::PresentMode],
+        (width, height): (u32, u32),
+    ) -> Self {
+        let msaa_samples = Self::supported_msaa_samples(adapter, device)
+            .into_iter()
+            .filter(|&count| count <= self.msaa_samples)
+            .max()
+            .unwrap_or(1);
+        let max_scale =
+            device.limits().max_texture_dimension_2d as f32 / width.max(height).max(1) as f32;
+        let (low, high) = Self::RENDER_SCALE_RANGE;
+        let render_scale = self.render_scale.clamp(low, high.min(max_scale));
+        let present_mode = match present_modes.contains(&self.present_mode.wgpu_present_mode()) {
+            true => self.present_mode,
+            false => PresentMode::Vsync,
+        };
+        let supported = Self {
+            msaa_samples,
+            render_scale,
+            present_mode,
+        };
+        if supported != self {
+            warn!("Render quality {self:?} isn't supported, using {supported:?}");
+        }
+        supported
+    }
+    /// Size the scene is drawn at for a surface of `width` by `height`
+    pub fn render_size(&self, width: u32, height: u32) -> (u32, u32) {
+        let scale = |size| ((size as f32 * self.render_scale).round() as u32).max(1);
+        (scale(width), scale(height))
+    }
+}
@@ -0,0 +1,178 @@
+use std::{
+    io::Write,
+    path::PathBuf,
+    process::{Child, Command, Stdio},
+};
+
+use super::capture::{self, Image};
+use crate::prelude::*;
+
+/// A process the frames are piped to as raw 8 bit RGBA, rows from the top
+#[derive(Debug, Clone, PartialEq)]
+pub enum FrameEncoder {
+    /// ffmpeg on the PATH, writing an H.264 video to `output`
+    Ffmpeg { output: PathBuf, frame_rate: u32 },
+    /// Any program reading the frames on its stdin
+    Command { program: String, args: Vec<String> },
+}
+
+impl FrameEncoder {
+    fn command(&self, width: u32, height: u32) -> Command {
+        match self {
+            Self::Ffmpeg { output, frame_rate } => {
+                let mut command = Command::new("ffmpeg");
+                command
+                    .args(["-y", "-loglevel", "error", "-f", "rawvideo"])
+                    .args(["-pixel_format", "rgba"])
+                    .args(["-video_size", &format!("{width}x{height}")])
+                    .args(["-framerate", &frame_rate.to_string(), "-i", "-"])
+                    // yuv420p needs even sizes
+                    .args(["-vf", "pad=ceil(iw/2)*2:ceil(ih/2)*2"])
+                    .args(["-c:v", "libx264", "-pix_fmt", "yuv420p"])
+                    .arg(output);
+                command
+            }
+            Self::Command { program, args } => {
+                let mut command = Command::new(program);
+                command.args(args);
+                command
+            }
+        }
+    }
+}
+
+#[derive(Builder, Debug, Clone, PartialEq)]
+#[builder(default)]
+pub struct RecordingParams {
+    /// Size of the frames, independent of the window's
+    pub width: u32,
+    pub height: u32,
+    /// Simulation time step for the whole recording
+    pub time_step: f32,
+    /// Steps between recorded frames
+    pub steps_per_frame: u32,
+    /// Stops after this many frames, None records until stopped
+    pub frame_count: Option<u32>,
+    /// Some to write the frames as numbered PNGs in this directory
+    pub directory: Option<PathBuf>,
+    /// Some to pipe the frames to an encoder
+    pub encoder: Option<FrameEncoder>,
+}
+
+impl Default for RecordingParams {
+    fn default() -> Self {
+        Self {
+            width: 1920,
+            height: 1080,
+            time_step: 0.005,
+            steps_per_frame: 1,
+            frame_count: None,
+            directory: Some(PathBuf::from("recording")),
+            encoder: None,
+        }
+    }
+}
+
+/// Frames rendered offscreen at a fixed time step per frame, so the result
+/// only depends on the starting state and not on the real frame rate. See
+/// `Graphics::start_recording`
+#[derive(Debug)]
+pub struct Recording {
+    params: RecordingParams,
+    texture: wgpu::Texture,
+    view: wgpu::TextureView,
+    encoder: Option<Child>,
+    frame: u32,
+    /// Restored when the recording stops
+    pub previous_time_step: f32,
+    /// Output size to restore, kept up to date with window resizes
+    pub previous_size: (u32, u32),
+}
+
+impl Recording {
+    /// Creates the frame directory and starts the encoder
+    pub fn new(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        params: RecordingParams,
+        previous_time_step: f32,
+        previous_size: (u32, u32),
+    ) -> Result<Self> {
+        if params.directory.is_none() && params.encoder.is_none() {
+            bail!("A recording needs a directory or an encoder to write its frames to");
+        }
+        if let Some(directory) = &params.directory {
+            std::fs::create_dir_all(directory)
+                .with_context(|| format!("Failed to create {directory:?}"))?;
+        }
+        let encoder = params
+            .encoder
+            .as_ref()
+            .map(|encoder| {
+                encoder
+                    .command(params.width, params.height)
+                    .stdin(Stdio::piped())
+                    .stdout(Stdio::null())
+                    .spawn()
+                    .with_context(|| format!("Failed to start {encoder:?}"))
+            })
+            .transpose()?;
+        let texture = capture::generate_output_texture(device, format, params.width, params.height);
+        Ok(Self {
+            view: texture.create_view(&Default::default()),
+            texture,
+            params: RecordingParams {
+                steps_per_frame: params.steps_per_frame.max(1),
+                ..params
+            },
+            encoder,
+            frame: 0,
+            previous_time_step,
+            previous_size,
+        })
+    }
+    pub fn params(&self) -> &RecordingParams {
+        &self.params
+    }
+    /// Frames written so far
+    pub fn frame(&self) -> u32 {
+        self.frame
+    }
+    pub fn texture(&self) -> &wgpu::Texture {
+        &self.texture
+    }
+    pub fn view(&self) -> &wgpu::TextureView {
+        &self.view
+    }
+    pub fn finished(&self) -> bool {
+        self.params
+            .frame_count
+            .is_some_and(|count| self.frame >= count)
+    }
+    pub fn write_frame(&mut self, image: &Image) -> Result<()> {
+        if let Some(directory) = &self.params.directory {
+            image.write_png(directory.join(format!("frame_{:06}.png", self.frame)))?;
+        }
+        if let Some(stdin) = self.encoder.as_mut().and_then(|child| child.stdin.as_mut()) {
+            stdin
+                .write_all(&image.pixels)
+                .with_context(|| "Failed to pipe frame to the encoder")?;
+        }
+        self.frame += 1;
+        Ok(())
+    }
+    /// Closes the encoder's input and waits for it to finish the file
+    pub fn finish(self) -> Result<()> {
+        let Some(mut child) = self.encoder else {
+            return Ok(());
+        };
+        drop(child.stdin.take());
+        let status = child
+            .wait()
+            .with_context(|| "Failed to wait for the encoder")?;
+        if !status.success() {
+            bail!("The encoder exited with {status}");
+        }
+        Ok(())
+    }
+}
@@ -92,7 +92,7 @@ impl ViewMode for ViewModeLookAt {
     /// to the ViewMode trait signature.
     fn rotate_orientation(&mut self, rotation: Quat, position: &mut Vec3) {
         // d = p - f
-        let mut dif = position.clone() - self.focus;
+        let mut dif = *position - self.focus;
         // mutate d
         dif = rotation * dif;
         // p = d + f
@@ -118,7 +118,7 @@ impl ViewMode for ViewModeLookAt {
     }
     fn zoom(&mut self, zoom: f32, camera_position: &mut Vec3, fov: &mut f32) {
         info!("received zoom: {:?}", zoom);
-        let mut dif = camera_position.clone() - self.focus;
+        let mut dif = *camera_position - self.focus;
         dif = zoom * dif;
         *camera_position = self.focus + dif;
         
@@ -132,7 +132,12 @@ impl<M: ViewMode + Default> Camera<M> {
     fn generate_perspective_matrix(&self) -> Mat4 {
         // the fov multiplcation occurs because self.fov is for x dimension,
         // so fov/aspect_ratio
-        Mat4::perspective_infinite_rh(self.fov / self.aspect_ratio, self.aspect_ratio, self.z_near)
+        // Reversed, see DepthMode
+        Mat4::perspective_infinite_reverse_rh(
+            self.fov / self.aspect_ratio,
+            self.aspect_ratio,
+            self.z_near,
+        )
     }
     fn generate_world_matix(&self) -> Mat4 {
         self.generate_perspective_matrix().mul_mat4( &self.generate_view_matrix())
@@ -140,6 +145,11 @@ impl<M: ViewMode + Default> Camera<M> {
     pub fn generate_world_matrix_columns(&self) -> [[f32; 4]; 4] {
         self.generate_world_matix().to_cols_array_2d()
     }
+    /// x and y scale of the perspective projection, for sizing sprites
+    pub fn projection_scale(&self) -> [f32; 2] {
+        let perspective = self.generate_perspective_matrix();
+        [perspective.x_axis.x, perspective.y_axis.y]
+    }
     /// This is created as it has slightly different behaviour based on the view
     /// mode type
     ///
@@ -208,8 +218,61 @@ pub struct Uniform {
     pub world_mat: [[f32; 4]; 4],
     pub width: u32,
     pub height: u32,
+    /// See SpriteParams
+    pub sprite_size: f32,
+    pub min_sprite_pixels: f32,
+    pub projection_scale: [f32; 2],
+    /// See coloring::Colormap::uniform_value
+    pub colormap: u32,
+    /// See DepthMode::uniform_values
+    pub depth_mode: u32,
+    pub log_depth_far: f32,
     #[builder(setter(skip))]
-    padding: [f32; 2],
+    padding: [u32; 3],
+}
+
+/// How the depth buffer spends its precision. Both keep it spread over
+/// distances from planetary systems to whole galaxies, where a standard
+/// depth buffer would collapse into z-fighting. Either way one is at the
+/// camera and the depth test keeps the greater value
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum DepthMode {
+    /// An infinite projection with the depth reversed, so the float depth
+    /// buffer's precision near zero goes to the far distances
+    #[default]
+    ReversedZ,
+    /// Depth from the logarithm of the distance, nothing beyond `far` is drawn
+    Logarithmic { far: f32 },
+}
+
+impl DepthMode {
+    /// The `depth_mode` and `log_depth_far` of `Uniform`
+    pub fn uniform_values(self) -> (u32, f32) {
+        match self {
+            Self::ReversedZ => (0, 0.),
+            Self::Logarithmic { far } => (1, far),
+        }
+    }
+}
+
+/// How the bodies are drawn, as camera facing discs sized by mass
+#[derive(Builder, Debug, Clone, Copy, PartialEq)]
+#[builder(default)]
+pub struct SpriteParams {
+    /// World radius of a body of unit mass, heavier bodies grow with the cube
+    /// root of their mass
+    pub size: f32,
+    /// Smallest radius in pixels, so distant and massless bodies stay visible
+    pub min_pixels: f32,
+}
+
+impl Default for SpriteParams {
+    fn default() -> Self {
+        Self {
+            size: 0.003,
+            min_pixels: 1.5,
+        }
+    }
 }
 
 impl Uniform {
@@ -217,7 +280,7 @@ impl Uniform {
         device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Uniform Buffer"),
             contents: bytes_of(self),
-            usage: wgpu::BufferUsages::UNIFORM,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         })
     }
     pub fn generate_bind_group_layout_entry(
@@ -0,0 +1,388 @@
+use bytemuck::bytes_of;
+
+use super::post_processing::HDR_FORMAT;
+use super::rendering;
+use super::vertices::read_buffer;
+use crate::prelude::*;
+
+/// Which bodies are drawn with trails
+#[derive(Debug, Default, Clone, PartialEq)]
+pub enum TrailSelection {
+    #[default]
+    All,
+    /// Body indices
+    Bodies(Vec<u32>),
+    /// Bodies whose tag is one of these. The initial conditions tag their
+    /// bodies, see the `TAG_*` constants in vertices, or set them with
+    /// `Graphics::set_body_tags`
+    Groups(Vec<u32>),
+}
+
+impl TrailSelection {
+    /// Indices of the selected bodies, out of range ones dropped. `tags` has
+    /// one entry per body, or is empty if none were set
+    pub fn resolve(&self, body_count: usize, tags: &[u32]) -> Vec<u32> {
+        match self {
+            Self::All => (0..body_count as u32).collect(),
+            Self::Bodies(bodies) => bodies
+                .iter()
+                .copied()
+                .filter(|&body| (body as usize) < body_count)
+                .collect(),
+            Self::Groups(groups) => (0..body_count as u32)
+                .filter(|&body| {
+                    tags.get(body as usize)
+                        .is_some_and(|tag| groups.contains(tag))
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Builder, Debug, Clone, PartialEq)]
+#[builder(default)]
+pub struct TrailParams {
+    /// Positions kept per body
+    pub length: u32,
+    /// Frames between samples, larger spreads the same length further back
+    pub interval: u32,
+    /// Exponent of the fade with age, larger fades out sooner
+    pub fade: f32,
+    pub color: [f32; 3],
+    pub selection: TrailSelection,
+}
+
+impl Default for TrailParams {
+    fn default() -> Self {
+        Self {
+            length: 128,
+            interval: 2,
+            fade: 1.,
+            color: [0.4, 0.6, 1.],
+            selection: TrailSelection::default(),
+        }
+    }
+}
+
+/// Mirrors the `Trail` struct in trails.wgsl and trail_record.wgsl
+#[derive(Debug, Pod, Zeroable, Copy, Clone)]
+#[repr(C)]
+struct TrailUniform {
+    length: u32,
+    head: u32,
+    filled: u32,
+    fade: f32,
+    color: [f32; 3],
+    selected: u32,
+    box_size: f32,
+    periodic: u32,
+    padding: [u32; 2],
+}
+
+/// Recent paths of the selected bodies, kept on the gpu as a ring of past
+/// positions and drawn as fading lines. The record pass reads the bodies
+/// through group 0 like the other compute passes, the trail state is at group
+/// 1
+#[derive(Debug)]
+pub struct Trails {
+    params: TrailParams,
+    uniform: wgpu::Buffer,
+    /// `length` slots of one position per selected body
+    history: wgpu::Buffer,
+    /// Indices of the bodies with trails, read by the record pass
+    bodies: wgpu::Buffer,
+    selected: Vec<u32>,
+    record_group: wgpu::BindGroup,
+    draw_layout: wgpu::BindGroupLayout,
+    draw_group: wgpu::BindGroup,
+    record_pipeline: wgpu::ComputePipeline,
+    draw_pipeline: wgpu::RenderPipeline,
+    head: u32,
+    filled: u32,
+    frame: u32,
+}
+
+impl Trails {
+    fn generate_bind_group_layout(
+        device: &wgpu::Device,
+        visibility: wgpu::ShaderStages,
+        read_only: bool,
+    ) -> wgpu::BindGroupLayout {
+        let entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility,
+            ty: wgpu::BindingType::Buffer {
+                ty: match binding {
+                    0 => wgpu::BufferBindingType::Uniform,
+                    1 => wgpu::BufferBindingType::Storage { read_only },
+                    _ => wgpu::BufferBindingType::Storage { read_only: true },
+                },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        // The history is already in selection order when drawn
+        let count = if read_only { 2 } else { 3 };
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Trails"),
+            entries: &(0..count).map(entry).collect::<Vec<_>>(),
+        })
+    }
+    fn generate_draw_pipeline(
+        device: &wgpu::Device,
+        trail_layout: &wgpu::BindGroupLayout,
+        depth_stencil: wgpu::DepthStencilState,
+        multisample: wgpu::MultisampleState,
+    ) -> wgpu::RenderPipeline {
+        let uniform_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: None,
+            entries: &[rendering::Uniform::generate_bind_group_layout_entry(
+                device, 0,
+            )],
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Trails"),
+            bind_group_layouts: &[&uniform_layout, trail_layout],
+            push_constant_ranges: &[],
+        });
+        let module = device.create_shader_module(include_wgsl!("../../shaders/trails.wgsl"));
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Trail Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &module,
+                entry_point: Some("vs_trail"),
+                compilation_options: Default::default(),
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &module,
+                entry_point: Some("fs_trail"),
+                compilation_options: Default::default(),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: HDR_FORMAT,
+                    // Added like the sprites, so crossing trails brighten
+                    blend: Some(wgpu::BlendState {
+                        color: wgpu::BlendComponent {
+                            src_factor: wgpu::BlendFactor::SrcAlpha,
+                            dst_factor: wgpu::BlendFactor::One,
+                            operation: wgpu::BlendOperation::Add,
+                        },
+                        alpha: wgpu::BlendComponent::OVER,
+                    }),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::LineList,
+                ..Default::default()
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                depth_write_enabled: false,
+                ..depth_stencil
+            }),
+            multisample,
+            multiview: None,
+            cache: None,
+        })
+    }
+    /// `body_layout` is the layout of group 0 of the compute pipelines,
+    /// `depth_stencil` and `multisample` those of the scene's render pass and
+    /// `selected` the bodies to draw trails for, see `TrailSelection::resolve`
+    pub fn new(
+        device: &wgpu::Device,
+        body_layout: &wgpu::BindGroupLayout,
+        depth_stencil: wgpu::DepthStencilState,
+        multisample: wgpu::MultisampleState,
+        params: TrailParams,
+        selected: &[u32],
+    ) -> Self {
+        let params = TrailParams {
+            length: params.length.max(2),
+            interval: params.interval.max(1),
+            ..params
+        };
+        let buffer = |label, size, usage| {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size: size as u64,
+                usage,
+                mapped_at_creation: false,
+            })
+        };
+        use wgpu::BufferUsages as BU;
+        let uniform = buffer(
+            "Trail Uniform",
+            size_of::<TrailUniform>(),
+            BU::UNIFORM | BU::COPY_DST,
+        );
+        let history = buffer(
+            "Trail History",
+            size_of::<[f32; 4]>() * selected.len().max(1) * params.length as usize,
+            BU::STORAGE | BU::COPY_SRC,
+        );
+        // A single unused entry when nothing is selected, so the bind group
+        // stays valid
+        let bodies = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Trail Bodies"),
+            contents: bytemuck::cast_slice(if selected.is_empty() { &[0] } else { selected }),
+            usage: BU::STORAGE,
+        });
+
+        let record_layout =
+            Self::generate_bind_group_layout(device, wgpu::ShaderStages::COMPUTE, false);
+        let draw_layout =
+            Self::generate_bind_group_layout(device, wgpu::ShaderStages::VERTEX_FRAGMENT, true);
+        let bind_group = |layout, buffers: &[&wgpu::Buffer]| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("Trails"),
+                layout,
+                entries: &buffers
+                    .iter()
+                    .enumerate()
+                    .map(|(binding, buffer)| wgpu::BindGroupEntry {
+                        binding: binding as u32,
+                        resource: buffer.as_entire_binding(),
+                    })
+                    .collect::<Vec<_>>(),
+            })
+        };
+        let record_group = bind_group(&record_layout, &[&uniform, &history, &bodies]);
+        let draw_group = bind_group(&draw_layout, &[&uniform, &history]);
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Trail Recording"),
+            bind_group_layouts: &[body_layout, &record_layout],
+            push_constant_ranges: &[],
+        });
+        let module = device.create_shader_module(include_wgsl!("../../shaders/trail_record.wgsl"));
+        let record_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("record_positions"),
+            layout: Some(&layout),
+            module: &module,
+            entry_point: Some("record_positions"),
+            compilation_options: Default::default(),
+            cache: None,
+        });
+
+        Self {
+            draw_pipeline: Self::generate_draw_pipeline(
+                device,
+                &draw_layout,
+                depth_stencil,
+                multisample,
+            ),
+            draw_layout,
+            record_pipeline,
+            params,
+            uniform,
+            history,
+            bodies,
+            selected: selected.to_vec(),
+            record_group,
+            draw_group,
+            head: 0,
+            filled: 0,
+            frame: 0,
+        }
+    }
+    pub fn params(&self) -> &TrailParams {
+        &self.params
+    }
+    /// Indices of the bodies with trails
+    pub fn selected(&self) -> &[u32] {
+        &self.selected
+    }
+    /// Changes the look of the trails, keeping their paths. A new length or
+    /// selection needs a new `Trails`
+    pub fn set_params(&mut self, params: TrailParams) {
+        self.params = TrailParams {
+            length: self.params.length,
+            interval: params.interval.max(1),
+            ..params
+        };
+    }
+    /// Rebuilds the draw pipeline for a scene pass with a new sample count,
+    /// keeping the paths
+    pub fn set_multisample(
+        &mut self,
+        device: &wgpu::Device,
+        depth_stencil: wgpu::DepthStencilState,
+        multisample: wgpu::MultisampleState,
+    ) {
+        self.draw_pipeline =
+            Self::generate_draw_pipeline(device, &self.draw_layout, depth_stencil, multisample);
+    }
+    /// Newest sample of each selected body, in selection order, stalling like
+    /// `BodyData::read_back`
+    pub fn read_newest(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> Result<Vec<[f32; 4]>> {
+        let size = (size_of::<[f32; 4]>() * self.selected.len().max(1)) as u64;
+        let readable = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let mut encoder = device.create_command_encoder(&Default::default());
+        encoder.copy_buffer_to_buffer(&self.history, self.head as u64 * size, &readable, 0, size);
+        queue.submit(Some(encoder.finish()));
+        let mut newest = read_buffer(&readable, device)?;
+        newest.truncate(self.selected.len());
+        Ok(newest)
+    }
+    /// Forgets the recorded paths, e.g. after bodies were moved by hand
+    pub fn clear(&mut self) {
+        self.filled = 0;
+    }
+    /// Records the selected bodies' positions every `interval` frames. `boundary` is
+    /// the simulation's periodic flag and box size, so wrapped bodies don't
+    /// draw a line across the box
+    pub fn encode(
+        &mut self,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        body_bind_group: &wgpu::BindGroup,
+        (periodic, box_size): (u32, f32),
+    ) {
+        let frame = self.frame;
+        self.frame = self.frame.wrapping_add(1);
+        if !frame.is_multiple_of(self.params.interval) {
+            return;
+        }
+        self.head = (self.head + 1) % self.params.length;
+        self.filled = (self.filled + 1).min(self.params.length);
+        let uniform = TrailUniform {
+            length: self.params.length,
+            head: self.head,
+            filled: self.filled,
+            fade: self.params.fade,
+            color: self.params.color,
+            selected: self.selected.len() as u32,
+            box_size,
+            periodic,
+            padding: [0; 2],
+        };
+        queue.write_buffer(&self.uniform, 0, bytes_of(&uniform));
+
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Trails"),
+            timestamp_writes: None,
+        });
+        pass.set_pipeline(&self.record_pipeline);
+        pass.set_bind_group(0, body_bind_group, &[]);
+        pass.set_bind_group(1, &self.record_group, &[]);
+        pass.dispatch_workgroups((self.selected.len() as u32).div_ceil(64), 1, 1);
+    }
+    /// Draws the trails into a pass of the scene, `uniform_bind_group` being
+    /// the one the sprites are drawn with
+    pub fn draw(&self, rpass: &mut wgpu::RenderPass, uniform_bind_group: &wgpu::BindGroup) {
+        rpass.set_pipeline(&self.draw_pipeline);
+        rpass.set_bind_group(0, uniform_bind_group, &[]);
+        rpass.set_bind_group(1, &self.draw_group, &[]);
+        rpass.draw(
+            0..2 * (self.params.length - 1),
+            0..self.selected.len() as u32,
+        );
+    }
+}
@@ -1,16 +1,128 @@
 use core::f32;
 
-use wgpu::{core::device, Maintain};
+use wgpu::Maintain;
 
+use crate::physics::{
+    imf::InitialMassFunction, restricted_three_body::RestrictedThreeBody,
+    zeldovich::ZeldovichParams,
+};
 use crate::prelude::*;
 
+/// The bodies a simulation starts with
+#[derive(Debug, Clone)]
+pub enum InitialConditions {
+    Galaxy(GalaxyParams),
+    /// A perturbed lattice for large scale structure runs, meant for a
+    /// periodic boundary of the same box size and an expanding cosmology
+    Zeldovich(ZeldovichParams),
+    /// Massless test particles around two primaries, in the frame rotating
+    /// with them. The primaries themselves are external potentials, see
+    /// `RestrictedThreeBody::potentials`
+    RestrictedThreeBody(RestrictedThreeBody),
+}
+
+impl Default for InitialConditions {
+    fn default() -> Self {
+        Self::Galaxy(Default::default())
+    }
+}
+
+#[derive(Builder, Debug, Clone)]
+#[builder(default)]
+pub struct GalaxyParams {
+    pub max_radius: f32,
+    pub max_phi: f32,
+    pub star_count: usize,
+    pub up: Vec3,
+    /// Fraction of the gravity the initial orbital speeds balance,
+    /// v² = f G M(<r) / r with M(<r) the central body and the stars inside r
+    pub rotation_support: f32,
+    /// Fraction of the bodies that are gas rather than stars
+    pub gas_fraction: f32,
+    /// Whether the central heavy body is a sink
+    pub central_sink: bool,
+    /// None gives every star a mass of one
+    pub mass_function: Option<InitialMassFunction>,
+}
+
+impl Default for GalaxyParams {
+    fn default() -> Self {
+        Self {
+            max_radius: 1.,
+            max_phi: f32::consts::PI / 8.,
+            star_count: 7000,
+            up: Vec3::Z,
+            rotation_support: 0.2,
+            gas_fraction: 0.,
+            central_sink: false,
+            mass_function: None,
+        }
+    }
+}
+
+impl InitialConditions {
+    /// `gravitation_constant` is the simulation's, which the orbits and
+    /// zeldovich masses are derived from. Also gives each body's tag, see the
+    /// `TAG_*` constants, or none for a lattice
+    pub fn generate(
+        &self,
+        gravitation_constant: f32,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+    ) -> Result<(BodyData<Compute>, Vec<u32>)> {
+        match self {
+            Self::Galaxy(params) => {
+                BodyData::<Compute>::generate_galaxy(params, gravitation_constant, device, encoder)
+            }
+            Self::Zeldovich(params) => Ok((
+                BodyData::<Compute>::generate_zeldovich(
+                    params,
+                    gravitation_constant,
+                    device,
+                    encoder,
+                )?,
+                Vec::new(),
+            )),
+            Self::RestrictedThreeBody(params) => {
+                BodyData::<Compute>::generate_restricted_three_body(
+                    params,
+                    gravitation_constant,
+                    device,
+                    encoder,
+                )
+            }
+        }
+    }
+}
+
+/// Collisionless bodies, stars or dark matter, that only feel gravity
+pub const SPECIES_COLLISIONLESS: u32 = 0;
+/// Gas bodies, which also take part in the hydrodynamics
+pub const SPECIES_GAS: u32 = 1;
+/// Sinks absorb the bodies that come within their accretion radius, see
+/// physics::sinks
+pub const SPECIES_SINK: u32 = 2;
+
+/// Tags of the generated bodies, see `Graphics::set_body_tags`. A galaxy's
+/// heavy central body
+pub const TAG_GALAXY_CENTRE: u32 = 0;
+pub const TAG_GALAXY_STAR: u32 = 1;
+pub const TAG_GALAXY_GAS: u32 = 2;
+/// A restricted three body test particle circling the barycentre
+pub const TAG_DISC: u32 = 0;
+/// A restricted three body test particle about L4 or L5
+pub const TAG_TROJAN: u32 = 1;
+
 #[derive(Debug)]
 pub struct BodyData<B: BufferType> {
-    /// The actual positions of the points
+    /// The actual positions of the points, w is 1 or 0 once the body has been
+    /// removed from the simulation
     pub positions: Arc<wgpu::Buffer>,
     /// The velocities of the points
     pub velocities: Arc<wgpu::Buffer>,
     pub mass: Arc<wgpu::Buffer>,
+    /// Which kind of body each is, see the `SPECIES_*` constants
+    pub species: Arc<wgpu::Buffer>,
     pub len: usize,
     buffer_type: B,
 }
@@ -20,6 +132,7 @@ pub struct UnbufferedBodyData {
     pub positions: Arc<Vec<[f32; 4]>>,
     pub velocities: Arc<Vec<[f32; 4]>>,
     pub mass: Arc<Vec<f32>>,
+    pub species: Arc<Vec<u32>>,
 }
 
 impl BodyData<Compute> {
@@ -54,6 +167,14 @@ impl BodyData<Compute> {
             0_u64,
             size,
         );
+        let size = mappable.species.size();
+        encoder.copy_buffer_to_buffer(
+            mappable.species.as_ref(),
+            0_u64,
+            self.species.as_ref(),
+            0_u64,
+            size,
+        );
     }
     pub fn map_to(
         &self,
@@ -69,12 +190,100 @@ impl BodyData<Compute> {
         self.copy_from_mappable(&mapping_buffer, device, encoder);
         Ok(())
     }
+    pub fn copy_to_readable(&self, readable: &BodyData<Readable>, encoder: &mut wgpu::CommandEncoder) {
+        encoder.copy_buffer_to_buffer(
+            self.positions.as_ref(),
+            0_u64,
+            readable.positions.as_ref(),
+            0_u64,
+            self.positions.size(),
+        );
+        encoder.copy_buffer_to_buffer(
+            self.velocities.as_ref(),
+            0_u64,
+            readable.velocities.as_ref(),
+            0_u64,
+            self.velocities.size(),
+        );
+        encoder.copy_buffer_to_buffer(
+            self.mass.as_ref(),
+            0_u64,
+            readable.mass.as_ref(),
+            0_u64,
+            self.mass.size(),
+        );
+        encoder.copy_buffer_to_buffer(
+            self.species.as_ref(),
+            0_u64,
+            readable.species.as_ref(),
+            0_u64,
+            self.species.size(),
+        );
+    }
+    /// Copies the gpu bound data back to the cpu. This stalls until the
+    /// queue has finished all submitted work, so it shouldn't be used per frame
+    pub fn read_back(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> Result<UnbufferedBodyData> {
+        let readable = BodyData::<Readable>::with_length(device, self.len);
+        let mut encoder = device.create_command_encoder(&Default::default());
+        self.copy_to_readable(&readable, &mut encoder);
+        queue.submit(Some(encoder.finish()));
+        readable.read(device)
+    }
+}
+
+/// Maps a `Readable` buffer and copies it out. Stalls until the queue has
+/// finished all submitted work
+pub fn read_buffer<T: Pod>(buffer: &wgpu::Buffer, device: &wgpu::Device) -> Result<Vec<T>> {
+    let (sender, receiver) = std::sync::mpsc::channel();
+    buffer
+        .slice(..)
+        .map_async(wgpu::MapMode::Read, move |map_result| {
+            let _ = sender.send(map_result);
+        });
+    device.poll(Maintain::Wait);
+    receiver
+        .recv()
+        .with_context(|| "Mapping callback was never called")?
+        .with_context(|| "Failed to map buffer for reading")?;
+    let data = bytemuck::cast_slice(&buffer.slice(..).get_mapped_range()).to_vec();
+    buffer.unmap();
+    Ok(data)
+}
+
+/// Swaps elements `a` and `b` of `buffer`, each `size` bytes, by way of
+/// `scratch` as a buffer can't be copied onto itself. `scratch` needs room
+/// for two elements
+pub fn encode_swap(
+    encoder: &mut wgpu::CommandEncoder,
+    buffer: &wgpu::Buffer,
+    scratch: &wgpu::Buffer,
+    size: u64,
+    a: u32,
+    b: u32,
+) {
+    let (a, b) = (a as u64 * size, b as u64 * size);
+    encoder.copy_buffer_to_buffer(buffer, a, scratch, 0, size);
+    encoder.copy_buffer_to_buffer(buffer, b, scratch, size, size);
+    encoder.copy_buffer_to_buffer(scratch, size, buffer, a, size);
+    encoder.copy_buffer_to_buffer(scratch, 0, buffer, b, size);
+}
+
+impl BodyData<Readable> {
+    pub fn read(&self, device: &wgpu::Device) -> Result<UnbufferedBodyData> {
+        Ok(UnbufferedBodyData {
+            positions: Arc::new(read_buffer(&self.positions, device)?),
+            velocities: Arc::new(read_buffer(&self.velocities, device)?),
+            mass: Arc::new(read_buffer(&self.mass, device)?),
+            species: Arc::new(read_buffer(&self.species, device)?),
+        })
+    }
 }
 
 impl BodyData<Mappable> {
     pub fn map(&mut self, data: &UnbufferedBodyData) -> Result<()> {
         if !(data.positions.len() == data.velocities.len()
-            && data.velocities.len() == data.mass.len())
+            && data.velocities.len() == data.mass.len()
+            && data.mass.len() == data.species.len())
         {
             bail!("The lengths of the data fields do not equal eachother")
         }
@@ -82,10 +291,11 @@ impl BodyData<Mappable> {
             bail!("The length of the data fields does not equal the buffer length")
         }
 
-        let (c_position, c_velocities, c_mass) = (
+        let (c_position, c_velocities, c_mass, c_species) = (
             self.positions.clone(),
             self.velocities.clone(),
             self.mass.clone(),
+            self.species.clone(),
         );
 
         self.buffer_type
@@ -143,10 +353,27 @@ impl BodyData<Mappable> {
                 }
             });
 
+        let species = data.species.clone();
+        let s_atomic = self.buffer_type.mapped_buffer_count.clone();
+        self.species
+            .slice(..)
+            .map_async(wgpu::MapMode::Write, move |map_result| {
+                if map_result.is_ok() {
+                    c_species
+                        .slice(..)
+                        .get_mapped_range_mut()
+                        .copy_from_slice(bytemuck::cast_slice(&species[..]));
+                    let prev_value = s_atomic.load(Ordering::Relaxed);
+                    s_atomic.store(prev_value + 1, Ordering::Relaxed);
+                } else {
+                    panic!("Failed to map species buffer")
+                }
+            });
+
         Ok(())
     }
     fn ensure_mapping_complete(&self, device: &wgpu::Device) {
-        if self.buffer_type.mapped_buffer_count.load(Ordering::Relaxed) < 3 {
+        if self.buffer_type.mapped_buffer_count.load(Ordering::Relaxed) < 4 {
             device.poll(Maintain::Wait);
         }
     }
@@ -170,11 +397,13 @@ impl<B: BufferType> BodyData<B> {
         let vel_buffer_desc = Self::create_buffer_desc(4, len, B::get_usages());
 
         let mass_buffer_desc = Self::create_buffer_desc(1, len, B::get_usages());
+        let species_buffer_desc = Self::create_buffer_desc(1, len, B::get_usages());
 
         BodyData::<B> {
             positions: Arc::new(device.create_buffer(&pos_buffer_desc)),
             velocities: Arc::new(device.create_buffer(&vel_buffer_desc)),
             mass: Arc::new(device.create_buffer(&mass_buffer_desc)),
+            species: Arc::new(device.create_buffer(&species_buffer_desc)),
             len,
             buffer_type: B::new(),
         }
@@ -186,6 +415,27 @@ impl<B: BufferType> BodyData<B> {
             attributes: &wgpu::vertex_attr_array![0 => Float32x4],
         }
     }
+    /// Positions, masses and colour values stepped per instance, one sprite
+    /// per body
+    pub fn get_sprite_buffer_layouts() -> [wgpu::VertexBufferLayout<'static>; 3] {
+        [
+            wgpu::VertexBufferLayout {
+                array_stride: size_of::<[f32; 4]>() as u64,
+                step_mode: wgpu::VertexStepMode::Instance,
+                attributes: &wgpu::vertex_attr_array![0 => Float32x4],
+            },
+            wgpu::VertexBufferLayout {
+                array_stride: size_of::<f32>() as u64,
+                step_mode: wgpu::VertexStepMode::Instance,
+                attributes: &wgpu::vertex_attr_array![1 => Float32],
+            },
+            wgpu::VertexBufferLayout {
+                array_stride: size_of::<f32>() as u64,
+                step_mode: wgpu::VertexStepMode::Instance,
+                attributes: &wgpu::vertex_attr_array![2 => Float32],
+            },
+        ]
+    }
     pub fn generate_unit_points(
         device: &wgpu::Device,
         encoder: &mut wgpu::CommandEncoder,
@@ -201,6 +451,7 @@ impl<B: BufferType> BodyData<B> {
         ]);
         let velocities = Arc::new(vec![[0.0; 4]; points.len()]);
         let masses = Arc::new(vec![1.0; points.len()]);
+        let species = Arc::new(vec![SPECIES_COLLISIONLESS; points.len()]);
 
         let compute = BodyData::<Compute>::with_length(device, points.len());
         compute
@@ -211,26 +462,39 @@ impl<B: BufferType> BodyData<B> {
                     positions: points,
                     velocities,
                     mass: masses,
+                    species,
                 },
             )
             .with_context(|| "Failed to map unit points buffers to gpu bound buffers")?;
         Ok(compute)
     }
     pub fn generate_galaxy(
-        max_radius: f32,
-        max_phi: f32,
-        star_count: usize,
-        up: Vec3,
+        params: &GalaxyParams,
         gravitation_constant: f32,
         device: &wgpu::Device,
         encoder: &mut wgpu::CommandEncoder,
-    ) -> Result<BodyData<Compute>> {
+    ) -> Result<(BodyData<Compute>, Vec<u32>)> {
+        let GalaxyParams {
+            max_radius,
+            max_phi,
+            star_count,
+            up,
+            rotation_support,
+            gas_fraction,
+            central_sink,
+            mass_function,
+        } = *params;
         let mut rng = rng();
-        let (mut positions, mut velocities, mut mass): (Vec<[f32; 4]>, Vec<[f32; 4]>, Vec<f32>) = (
+        let (mut positions, mut velocities): (Vec<[f32; 4]>, Vec<[f32; 4]>) = (
             vec![[0., 0., 0., 1.]; star_count],
             vec![[0.; 4]; star_count],
-            vec![1.; star_count],
         );
+        let mut mass = match mass_function {
+            Some(mass_function) => mass_function
+                .sample(star_count, &mut rng)
+                .with_context(|| "Failed to sample star masses")?,
+            None => vec![1.; star_count],
+        };
 
         // up DOT (x,y) = 0, up.x * x = -up.y * y
         // p = (x, (Up.x * x) / (-up.y))
@@ -242,9 +506,12 @@ impl<B: BufferType> BodyData<B> {
         }
         .normalize_or_zero();
         let phi_axis = up.cross(r_axis).normalize_or_zero();
-        let galactic_mass = star_count as f32;
+        // Star 0's sample is replaced by the central body
+        mass[0] = mass[1..].iter().sum::<f32>() / 8.;
+        let mut radii = vec![0.; star_count];
+        let mut directions = vec![Vec3::ZERO; star_count];
 
-        for i in 0..star_count {
+        for i in 1..star_count {
             let mut r: f32 = rng.random_range(0.0..=1.0);
             r = r.powf(1. / 3.) * max_radius; // 1/3 should be an even radial distribution, 1/2
                                               // biases towards center
@@ -263,17 +530,48 @@ impl<B: BufferType> BodyData<B> {
             positions[i][1] = position.y;
             positions[i][2] = position.z;
 
-            let mut vel = theta_rot.mul_vec3(phi_axis);
-            vel *= (gravitation_constant * galactic_mass / r).sqrt() * r / max_radius * 1.;
+            radii[i] = r;
+            directions[i] = theta_rot.mul_vec3(phi_axis);
+        }
 
-            velocities[i][0] = vel.x;
-            velocities[i][1] = vel.y;
-            velocities[i][2] = vel.z;
+        // Each star orbits the mass inside it, the central body's and that of
+        // the stars nearer in
+        let mut order: Vec<usize> = (1..star_count).collect();
+        order.sort_by(|&a, &b| radii[a].total_cmp(&radii[b]));
+        let mut enclosed = mass[0];
+        for i in order {
+            if radii[i] > 0. {
+                let speed = (rotation_support * gravitation_constant * enclosed / radii[i]).sqrt();
+                velocities[i] = (directions[i] * speed).extend(0.).to_array();
+            }
+            enclosed += mass[i];
         }
 
-        positions[0] = [0.0, 0.0, 0.0, 1.0];
-        velocities[0] = [0.0; 4];
-        mass[0] = star_count as f32 / 8.;
+        // The central body is never gas
+        let species: Vec<u32> = (0..star_count)
+            .map(|i| {
+                if i == 0 {
+                    if central_sink {
+                        SPECIES_SINK
+                    } else {
+                        SPECIES_COLLISIONLESS
+                    }
+                } else if rng.random_bool(gas_fraction.clamp(0., 1.) as f64) {
+                    SPECIES_GAS
+                } else {
+                    SPECIES_COLLISIONLESS
+                }
+            })
+            .collect();
+        let tags = species
+            .iter()
+            .enumerate()
+            .map(|(i, &species)| match (i, species) {
+                (0, _) => TAG_GALAXY_CENTRE,
+                (_, SPECIES_GAS) => TAG_GALAXY_GAS,
+                _ => TAG_GALAXY_STAR,
+            })
+            .collect();
 
         let body_data = BodyData::<Compute>::with_length(device, positions.len());
 
@@ -285,10 +583,147 @@ impl<B: BufferType> BodyData<B> {
                     positions: Arc::new(positions),
                     velocities: Arc::new(velocities),
                     mass: Arc::new(mass),
+                    species: Arc::new(species),
                 },
             )
             .with_context(|| "Failed to map galaxy to bodydata buffers")?;
 
+        Ok((body_data, tags))
+    }
+}
+
+impl BodyData<Compute> {
+    /// Takes a body out of the simulation. It keeps its slot, but stops moving,
+    /// loses its mass and is no longer drawn
+    pub fn remove(&self, queue: &wgpu::Queue, index: u32) {
+        let index = index as u64;
+        let vec4_size = size_of::<[f32; 4]>() as u64;
+        queue.write_buffer(
+            &self.positions,
+            index * vec4_size + 3 * size_of::<f32>() as u64,
+            bytemuck::bytes_of(&0_f32),
+        );
+        queue.write_buffer(
+            &self.velocities,
+            index * vec4_size,
+            bytemuck::bytes_of(&[0_f32; 4]),
+        );
+        queue.write_buffer(
+            &self.mass,
+            index * size_of::<f32>() as u64,
+            bytemuck::bytes_of(&0_f32),
+        );
+    }
+    /// Swaps the slots of two bodies, see `encode_swap`
+    pub fn encode_swap(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        scratch: &wgpu::Buffer,
+        a: u32,
+        b: u32,
+    ) {
+        let vec4_size = size_of::<[f32; 4]>() as u64;
+        let scalar_size = size_of::<f32>() as u64;
+        encode_swap(encoder, &self.positions, scratch, vec4_size, a, b);
+        encode_swap(encoder, &self.velocities, scratch, vec4_size, a, b);
+        encode_swap(encoder, &self.mass, scratch, scalar_size, a, b);
+        encode_swap(encoder, &self.species, scratch, scalar_size, a, b);
+    }
+    /// Overwrites a body's motion and mass, also restoring it if it was
+    /// removed
+    pub fn write_body(
+        &self,
+        queue: &wgpu::Queue,
+        index: u32,
+        position: Vec3,
+        velocity: Vec3,
+        mass: f32,
+    ) {
+        let index = index as u64;
+        let vec4_size = size_of::<[f32; 4]>() as u64;
+        queue.write_buffer(
+            &self.positions,
+            index * vec4_size,
+            bytemuck::bytes_of(&position.extend(1.).to_array()),
+        );
+        queue.write_buffer(
+            &self.velocities,
+            index * vec4_size,
+            bytemuck::bytes_of(&velocity.extend(0.).to_array()),
+        );
+        queue.write_buffer(
+            &self.mass,
+            index * size_of::<f32>() as u64,
+            bytemuck::bytes_of(&mass),
+        );
+    }
+    pub fn generate_restricted_three_body(
+        params: &RestrictedThreeBody,
+        gravitation_constant: f32,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+    ) -> Result<(BodyData<Compute>, Vec<u32>)> {
+        if !params.separation.is_finite() || params.separation <= 0. {
+            bail!("The primaries' separation must be positive");
+        }
+        if params.secondary_mass <= 0. || params.secondary_mass > params.primary_mass {
+            bail!(
+                "The secondary's mass must be positive and at most the primary's {}, got {}",
+                params.primary_mass,
+                params.secondary_mass
+            );
+        }
+        let (positions, velocities) = params.generate_particles(gravitation_constant);
+        if positions.is_empty() {
+            bail!("The restricted three body problem needs at least one particle");
+        }
+        let mass = vec![0.; positions.len()];
+        let species = vec![SPECIES_COLLISIONLESS; positions.len()];
+        // The disc comes first, see `RestrictedThreeBody::generate_particles`
+        let mut tags = vec![TAG_DISC; params.disc_count];
+        tags.resize(positions.len(), TAG_TROJAN);
+
+        let body_data = BodyData::<Compute>::with_length(device, positions.len());
+        body_data
+            .map_to(
+                device,
+                encoder,
+                &UnbufferedBodyData {
+                    positions: Arc::new(positions),
+                    velocities: Arc::new(velocities),
+                    mass: Arc::new(mass),
+                    species: Arc::new(species),
+                },
+            )
+            .with_context(|| "Failed to map restricted three body particles to bodydata buffers")?;
+
+        Ok((body_data, tags))
+    }
+    pub fn generate_zeldovich(
+        params: &ZeldovichParams,
+        gravitation_constant: f32,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+    ) -> Result<BodyData<Compute>> {
+        let field = params
+            .generate(gravitation_constant)
+            .with_context(|| "Failed to generate zeldovich field")?;
+        let species = vec![SPECIES_COLLISIONLESS; field.positions.len()];
+
+        let body_data = BodyData::<Compute>::with_length(device, field.positions.len());
+        body_data
+            .map_to(
+                device,
+                encoder,
+                &UnbufferedBodyData {
+                    positions: Arc::new(field.positions),
+                    velocities: Arc::new(field.velocities),
+                    mass: Arc::new(field.masses),
+                    species: Arc::new(species),
+                },
+            )
+            .with_context(|| "Failed to map zeldovich field to bodydata buffers")?;
+
         Ok(body_data)
     }
 }
@@ -306,6 +741,9 @@ pub struct Mappable {
 #[derive(Debug, Default)]
 pub struct Compute;
 
+#[derive(Debug, Default)]
+pub struct Readable;
+
 impl BufferType for Mappable {
     fn get_usages() -> wgpu::BufferUsages {
         use wgpu::BufferUsages as BU;
@@ -313,7 +751,7 @@ impl BufferType for Mappable {
     }
     fn new() -> Self {
         Self {
-            mapped_buffer_count: Arc::new(AtomicUsize::new(3)),
+            mapped_buffer_count: Arc::new(AtomicUsize::new(4)),
         }
     }
 }
@@ -321,7 +759,17 @@ impl BufferType for Mappable {
 impl BufferType for Compute {
     fn get_usages() -> wgpu::BufferUsages {
         use wgpu::BufferUsages as BU;
-        BU::INDIRECT | BU::VERTEX | BU::STORAGE | BU::COPY_DST
+        BU::INDIRECT | BU::VERTEX | BU::STORAGE | BU::COPY_DST | BU::COPY_SRC
+    }
+    fn new() -> Self {
+        Self
+    }
+}
+
+impl BufferType for Readable {
+    fn get_usages() -> wgpu::BufferUsages {
+        use wgpu::BufferUsages as BU;
+        BU::MAP_READ | BU::COPY_DST
     }
     fn new() -> Self {
         Self
@@ -4,6 +4,7 @@ const CLEAR_COLOR: wgpu::Color = wgpu::Color::BLACK;
 
 mod application;
 mod graphics;
+mod physics;
 
 pub mod prelude {
     pub use anyhow::{anyhow, bail, Context, Result};
@@ -39,6 +40,13 @@ fn main() {
         .unwrap();
 
     let mut app: application::App = Default::default();
+    // An optional scenario file of physical options, see
+    // `UserOptions::load_scenario`
+    if let Some(path) = std::env::args().nth(1) {
+        app.load_scenario(path)
+            .with_context(|| "Failed to load scenario")
+            .unwrap();
+    }
 
     event_loop.set_control_flow(winit::event_loop::ControlFlow::Poll);
 
@@ -0,0 +1,117 @@
+use crate::prelude::*;
+
+/// A Friedmann-Lemaître cosmology, curvature is whatever is left over from
+/// the matter and dark energy densities
+#[derive(Builder, Debug, Clone, Copy, PartialEq)]
+#[builder(default)]
+pub struct Cosmology {
+    pub omega_matter: f64,
+    pub omega_lambda: f64,
+    /// Hubble constant today, in inverse simulation time units
+    pub hubble_const: f64,
+    /// Redshift the simulation starts at
+    pub initial_redshift: f64,
+}
+
+impl Default for Cosmology {
+    fn default() -> Self {
+        Self {
+            omega_matter: 0.3,
+            omega_lambda: 0.7,
+            hubble_const: 0.1,
+            initial_redshift: 50.,
+        }
+    }
+}
+
+impl Cosmology {
+    pub fn omega_curvature(&self) -> f64 {
+        1. - self.omega_matter - self.omega_lambda
+    }
+    /// Hubble parameter H(a) = H0 sqrt(Ωm a⁻³ + Ωk a⁻² + ΩΛ)
+    pub fn hubble(&self, scale_factor: f64) -> f64 {
+        let a = scale_factor;
+        self.hubble_const
+            * (self.omega_matter / (a * a * a)
+                + self.omega_curvature() / (a * a)
+                + self.omega_lambda)
+                .sqrt()
+    }
+    pub fn initial_scale_factor(&self) -> f64 {
+        1. / (1. + self.initial_redshift)
+    }
+}
+
+/// The expansion history of a run, advanced alongside the bodies
+#[derive(Debug, Clone, Copy)]
+pub struct Expansion {
+    pub cosmology: Cosmology,
+    pub scale_factor: f64,
+}
+
+impl Expansion {
+    pub fn new(cosmology: Cosmology) -> Self {
+        Self {
+            scale_factor: cosmology.initial_scale_factor(),
+            cosmology,
+        }
+    }
+    pub fn redshift(&self) -> f64 {
+        1. / self.scale_factor - 1.
+    }
+    pub fn hubble(&self) -> f64 {
+        self.cosmology.hubble(self.scale_factor)
+    }
+    /// Advances a(t) by `dt` with a single RK4 step of da/dt = a H(a)
+    pub fn advance(&mut self, dt: f64) {
+        let f = |a: f64| a * self.cosmology.hubble(a);
+        let a = self.scale_factor;
+        let k1 = f(a);
+        let k2 = f(a + 0.5 * dt * k1);
+        let k3 = f(a + 0.5 * dt * k2);
+        let k4 = f(a + dt * k3);
+        self.scale_factor = a + dt / 6. * (k1 + 2. * k2 + 2. * k3 + k4);
+    }
+    /// Scale applied to the comoving gravitational acceleration, which falls
+    /// off as a⁻³ with comoving positions and velocities dx/dt
+    pub fn force_scale(&self) -> f32 {
+        self.scale_factor.powi(-3) as f32
+    }
+    /// Factor the velocities are multiplied by each step to account for the
+    /// Hubble drag term, -2H dx/dt
+    pub fn velocity_damping(&self, dt: f64) -> f32 {
+        (-2. * self.hubble() * dt).exp() as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Time since the big bang in a flat ΛCDM universe
+    fn age(cosmology: &Cosmology, scale_factor: f64) -> f64 {
+        let (m, l) = (cosmology.omega_matter, cosmology.omega_lambda);
+        2. / (3. * cosmology.hubble_const * l.sqrt())
+            * ((l / m).sqrt() * scale_factor.powf(1.5)).asinh()
+    }
+
+    #[test]
+    fn reaches_today_on_time() {
+        let cosmology = Cosmology::default();
+        let mut expansion = Expansion::new(cosmology);
+        assert!((expansion.redshift() - cosmology.initial_redshift).abs() < 1e-9);
+
+        let duration = age(&cosmology, 1.) - age(&cosmology, expansion.scale_factor);
+        let steps = 10_000;
+        for _ in 0..steps {
+            expansion.advance(duration / steps as f64);
+        }
+        assert!(
+            (expansion.scale_factor - 1.).abs() < 1e-6,
+            "a = {}",
+            expansion.scale_factor
+        );
+        assert!(expansion.redshift().abs() < 1e-6);
+        assert!((expansion.hubble() - cosmology.hubble_const).abs() < 1e-6);
+    }
+}
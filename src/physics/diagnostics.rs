@@ -0,0 +1,53 @@
+use crate::graphics::vertices::UnbufferedBodyData;
+use crate::prelude::*;
+
+use super::potentials::ExternalPotential;
+
+/// A snapshot of the energy budget of the system, all values are totals over
+/// every body
+#[derive(Debug, Default, Clone, Copy)]
+pub struct EnergyReport {
+    pub kinetic: f32,
+    /// Potential energy of the pairwise gravity, each pair counted once
+    pub pairwise_potential: f32,
+    /// Potential energy of the bodies in the external potentials
+    pub external_potential: f32,
+}
+
+impl EnergyReport {
+    /// This is O(n²), so it should only be run on demand rather than each
+    /// frame
+    pub fn measure(
+        data: &UnbufferedBodyData,
+        gravitation_const: f32,
+        potentials: &[ExternalPotential],
+    ) -> Self {
+        let positions: Vec<Vec3> = data
+            .positions
+            .iter()
+            .map(|p| Vec4::from_array(*p).truncate())
+            .collect();
+        let mut report = Self::default();
+
+        for (i, (p1, m1)) in positions.iter().zip(data.mass.iter()).enumerate() {
+            let v = Vec4::from_array(data.velocities[i]).truncate();
+            report.kinetic += 0.5 * m1 * v.length_squared();
+
+            for (p2, m2) in positions.iter().zip(data.mass.iter()).skip(i + 1) {
+                let r = p1.distance(*p2);
+                if r > 0. {
+                    report.pairwise_potential -= gravitation_const * m1 * m2 / r;
+                }
+            }
+
+            report.external_potential += potentials
+                .iter()
+                .map(|potential| m1 * potential.potential(*p1, gravitation_const))
+                .sum::<f32>();
+        }
+        report
+    }
+    pub fn total(&self) -> f32 {
+        self.kinetic + self.pairwise_potential + self.external_potential
+    }
+}
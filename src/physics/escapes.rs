@@ -0,0 +1,180 @@
+use std::io::Write;
+
+use crate::graphics::vertices::UnbufferedBodyData;
+use crate::prelude::*;
+
+use super::force_models::ForceModel;
+use super::potentials::ExternalPotential;
+
+/// A body counts as escaped once it is further than `radius` from the centre
+/// of mass of the rest and its energy relative to them is positive
+#[derive(Builder, Debug, Clone, Copy, PartialEq)]
+#[builder(default)]
+pub struct EscapeCriterion {
+    pub radius: f32,
+    /// Frames between checks, each check reads the bodies back from the gpu
+    /// and stalls that frame until it has
+    pub check_interval: u32,
+    /// Whether escaped bodies are taken out of the simulation, otherwise they
+    /// are only recorded. Removed bodies swap slots with the last live body
+    /// and the passes stop at the live ones, so removals make the steps
+    /// cheaper but move bodies to other indices
+    pub remove: bool,
+}
+
+impl Default for EscapeCriterion {
+    fn default() -> Self {
+        Self {
+            radius: 10.,
+            check_interval: 100,
+            remove: true,
+        }
+    }
+}
+
+/// A body leaving the system, positions and velocities are relative to the
+/// centre of mass of the bodies left behind
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EscapeRecord {
+    /// Index the body had when the watch started
+    pub body: u32,
+    /// Simulation time of the check that caught it
+    pub time: f64,
+    pub position: Vec3,
+    pub velocity: Vec3,
+    /// Specific energy, kinetic plus potential per unit mass
+    pub energy: f32,
+}
+
+#[derive(Debug, Clone)]
+pub struct EscapeTracker {
+    pub criterion: EscapeCriterion,
+    /// Per body, set once a body has escaped
+    pub escaped: Vec<bool>,
+    pub records: Vec<EscapeRecord>,
+    /// Index each body had when the watch started, by current slot
+    ids: Vec<u32>,
+    frames_since_check: u32,
+}
+
+impl EscapeTracker {
+    pub fn new(criterion: EscapeCriterion, body_count: usize) -> Self {
+        Self {
+            criterion,
+            escaped: vec![false; body_count],
+            records: Vec::new(),
+            ids: (0..body_count as u32).collect(),
+            frames_since_check: 0,
+        }
+    }
+    /// Follows the bodies to new slots, `slots[i]` being the slot the body
+    /// now at `i` came from
+    pub fn reorder(&mut self, slots: &[u32]) {
+        self.escaped = slots.iter().map(|&i| self.escaped[i as usize]).collect();
+        self.ids = slots.iter().map(|&i| self.ids[i as usize]).collect();
+    }
+    /// Counts a frame, true once enough have passed for another check
+    pub fn frame_due(&mut self) -> bool {
+        self.frames_since_check += 1;
+        if self.frames_since_check < self.criterion.check_interval.max(1) {
+            return false;
+        }
+        self.frames_since_check = 0;
+        true
+    }
+    /// Flags and records every body that newly meets the criterion, returning
+    /// their current slots. The potential is only summed for bodies outside the
+    /// radius, so this is O(n) per candidate. It assumes open boundaries and
+    /// a static background, and counts no pairwise potential for force models
+    /// without one
+    pub fn check(
+        &mut self,
+        data: &UnbufferedBodyData,
+        gravitation_const: f32,
+        force_model: &ForceModel,
+        potentials: &[ExternalPotential],
+        time: f64,
+    ) -> Vec<u32> {
+        let live = |i: usize| data.positions[i][3] != 0. && !self.escaped[i];
+        let position = |i: usize| Vec4::from_array(data.positions[i]).truncate();
+        let velocity = |i: usize| Vec4::from_array(data.velocities[i]).truncate();
+
+        let (mut mass, mut moment, mut momentum) = (0., Vec3::ZERO, Vec3::ZERO);
+        for i in (0..data.mass.len()).filter(|&i| live(i)) {
+            mass += data.mass[i];
+            moment += position(i) * data.mass[i];
+            momentum += velocity(i) * data.mass[i];
+        }
+        if mass <= 0. {
+            return Vec::new();
+        }
+        let (centre, drift) = (moment / mass, momentum / mass);
+
+        let mut escaped = Vec::new();
+        for i in (0..data.mass.len()).filter(|&i| live(i)) {
+            let r = position(i) - centre;
+            if r.length() < self.criterion.radius {
+                continue;
+            }
+            let v = velocity(i) - drift;
+            let potential = (0..data.mass.len())
+                .filter(|&j| j != i && live(j))
+                .map(|j| {
+                    let d = position(i).distance(position(j));
+                    if d > 0. {
+                        force_model
+                            .pair_potential(data.mass[j], d, gravitation_const)
+                            .unwrap_or(0.)
+                    } else {
+                        0.
+                    }
+                })
+                .sum::<f32>()
+                + potentials
+                    .iter()
+                    .map(|p| p.potential(position(i), gravitation_const))
+                    .sum::<f32>();
+            let energy = 0.5 * v.length_squared() + potential;
+            if energy > 0. {
+                escaped.push(i as u32);
+                self.records.push(EscapeRecord {
+                    body: self.ids[i],
+                    time,
+                    position: r,
+                    velocity: v,
+                    energy,
+                });
+            }
+        }
+        for &i in &escaped {
+            self.escaped[i as usize] = true;
+        }
+        escaped
+    }
+    /// Writes one `body time x y z vx vy vz energy` line per escape
+    pub fn write(&self, path: impl AsRef<std::path::Path>) -> Result<()> {
+        let path = path.as_ref();
+        let file = std::fs::File::create(path)
+            .with_context(|| format!("Failed to create escape log {:?}", path))?;
+        let mut out = std::io::BufWriter::new(file);
+        writeln!(out, "# body time x y z vx vy vz energy")?;
+        for e in &self.records {
+            writeln!(
+                out,
+                "{} {} {} {} {} {} {} {} {}",
+                e.body,
+                e.time,
+                e.position.x,
+                e.position.y,
+                e.position.z,
+                e.velocity.x,
+                e.velocity.y,
+                e.velocity.z,
+                e.energy
+            )?;
+        }
+        out.flush()
+            .with_context(|| format!("Failed to write escape log {:?}", path))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,156 @@
+use std::ops::{Add, Mul, Sub};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Complex {
+    pub re: f64,
+    pub im: f64,
+}
+
+impl Complex {
+    pub const ZERO: Self = Self { re: 0., im: 0. };
+    pub fn new(re: f64, im: f64) -> Self {
+        Self { re, im }
+    }
+    pub fn from_angle(angle: f64) -> Self {
+        Self::new(angle.cos(), angle.sin())
+    }
+    pub fn scale(self, s: f64) -> Self {
+        Self::new(self.re * s, self.im * s)
+    }
+    /// Multiplication by i
+    pub fn rotate(self) -> Self {
+        Self::new(-self.im, self.re)
+    }
+}
+
+impl Add for Complex {
+    type Output = Self;
+    fn add(self, rhs: Self) -> Self {
+        Self::new(self.re + rhs.re, self.im + rhs.im)
+    }
+}
+
+impl Sub for Complex {
+    type Output = Self;
+    fn sub(self, rhs: Self) -> Self {
+        Self::new(self.re - rhs.re, self.im - rhs.im)
+    }
+}
+
+impl Mul for Complex {
+    type Output = Self;
+    fn mul(self, rhs: Self) -> Self {
+        Self::new(
+            self.re * rhs.re - self.im * rhs.im,
+            self.re * rhs.im + self.im * rhs.re,
+        )
+    }
+}
+
+/// In place radix-2 FFT, `line.len()` must be a power of two. The inverse is
+/// left unnormalised, the same as the mesh solver's
+pub fn fft(line: &mut [Complex], inverse: bool) {
+    let n = line.len();
+    debug_assert!(n.is_power_of_two());
+    let bits = n.trailing_zeros();
+    if bits == 0 {
+        return;
+    }
+    for i in 0..n {
+        let j = i.reverse_bits() >> (usize::BITS - bits);
+        if i < j {
+            line.swap(i, j);
+        }
+    }
+    let direction = if inverse { 1. } else { -1. };
+    let mut size = 2;
+    while size <= n {
+        let half = size / 2;
+        for k in 0..half {
+            let twiddle =
+                Complex::from_angle(direction * 2. * std::f64::consts::PI * k as f64 / size as f64);
+            for j in (k..n).step_by(size) {
+                let even = line[j];
+                let odd = line[j + half] * twiddle;
+                line[j] = even + odd;
+                line[j + half] = even - odd;
+            }
+        }
+        size *= 2;
+    }
+}
+
+/// FFT of an n³ grid stored with x varying fastest
+pub fn fft_3d(grid: &mut [Complex], n: usize, inverse: bool) {
+    let mut line = vec![Complex::ZERO; n];
+    for axis in 0..3 {
+        let stride = n.pow(axis);
+        for a in 0..n {
+            for b in 0..n {
+                // a and b index the two other axes
+                let start = match axis {
+                    0 => n * (a + n * b),
+                    1 => a + n * n * b,
+                    _ => a + n * b,
+                };
+                for (i, value) in line.iter_mut().enumerate() {
+                    *value = grid[start + i * stride];
+                }
+                fft(&mut line, inverse);
+                for (i, value) in line.iter().enumerate() {
+                    grid[start + i * stride] = *value;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signal(len: usize) -> Vec<Complex> {
+        (0..len)
+            .map(|i| Complex::new((i as f64 * 0.7).sin(), (i as f64 * 1.3).cos()))
+            .collect()
+    }
+
+    fn assert_close(a: &[Complex], b: &[Complex]) {
+        for (a, b) in a.iter().zip(b) {
+            assert!((a.re - b.re).abs() < 1e-12 && (a.im - b.im).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn inverse_undoes_forward() {
+        let original = signal(64);
+        let mut line = original.clone();
+        fft(&mut line, false);
+        fft(&mut line, true);
+        let line: Vec<_> = line.into_iter().map(|c| c.scale(1. / 64.)).collect();
+        assert_close(&line, &original);
+
+        let n = 8;
+        let original = signal(n * n * n);
+        let mut grid = original.clone();
+        fft_3d(&mut grid, n, false);
+        fft_3d(&mut grid, n, true);
+        let grid: Vec<_> = grid
+            .into_iter()
+            .map(|c| c.scale(1. / (n * n * n) as f64))
+            .collect();
+        assert_close(&grid, &original);
+    }
+
+    #[test]
+    fn single_frequency_lands_in_its_bin() {
+        let (n, k) = (16, 3);
+        let mut line: Vec<_> = (0..n)
+            .map(|i| Complex::from_angle(2. * std::f64::consts::PI * (k * i) as f64 / n as f64))
+            .collect();
+        fft(&mut line, false);
+        let mut expected = vec![Complex::ZERO; n];
+        expected[k] = Complex::new(n as f64, 0.);
+        assert_close(&line, &expected);
+    }
+}
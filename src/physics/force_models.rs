@@ -0,0 +1,96 @@
+use super::precision::generate_kicking_shader;
+use crate::prelude::*;
+
+/// The law the pairwise force follows. Each model is its own variant of the
+/// pairwise kernel, built by appending the model's functions to compute.wgsl.
+///
+/// The Ewald correction and the particle mesh are always newtonian, so the
+/// other models are only exact with an open boundary and direct summation.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub enum ForceModel {
+    #[default]
+    Newtonian,
+    /// Newtonian gravity softened over `softening`
+    Plummer { softening: f32 },
+    /// Gravity screened with a Yukawa potential, Φ = -Gm exp(-r/λ) / r
+    Yukawa { screening_length: f32 },
+    /// MOND with the simple interpolating function μ(x) = x / (1 + x)
+    Mond { acceleration_scale: f32 },
+    /// A signed inverse square law, like charges repel. There must be one
+    /// charge per body, the gravitational constant is used as the coupling
+    Coulomb { charges: Vec<f32> },
+}
+
+impl ForceModel {
+    fn kernel_source(&self) -> &'static str {
+        match self {
+            Self::Newtonian => include_str!("../../shaders/force_models/newtonian.wgsl"),
+            Self::Plummer { .. } => include_str!("../../shaders/force_models/plummer.wgsl"),
+            Self::Yukawa { .. } => include_str!("../../shaders/force_models/yukawa.wgsl"),
+            Self::Mond { .. } => include_str!("../../shaders/force_models/mond.wgsl"),
+            Self::Coulomb { .. } => include_str!("../../shaders/force_models/coulomb.wgsl"),
+        }
+    }
+    /// The value of `force_param` in `SimulationUniform`
+    pub fn uniform_value(&self) -> f32 {
+        match self {
+            Self::Newtonian | Self::Coulomb { .. } => 0.,
+            Self::Plummer { softening } => *softening,
+            Self::Yukawa { screening_length } => *screening_length,
+            Self::Mond { acceleration_scale } => *acceleration_scale,
+        }
+    }
+    /// Potential at `distance` from a body of `mass`, matching the kernel.
+    /// None for the models without a pairwise potential
+    pub fn pair_potential(&self, mass: f32, distance: f32, gravitation_const: f32) -> Option<f32> {
+        let gm = gravitation_const * mass;
+        match self {
+            Self::Newtonian => Some(-gm / distance),
+            Self::Plummer { softening } => {
+                Some(-gm / (distance * distance + softening * softening).sqrt())
+            }
+            Self::Yukawa { screening_length } => {
+                Some(-gm * (-distance / screening_length).exp() / distance)
+            }
+            Self::Mond { .. } | Self::Coulomb { .. } => None,
+        }
+    }
+    pub fn charges(&self) -> Option<&[f32]> {
+        match self {
+            Self::Coulomb { charges } => Some(charges),
+            _ => None,
+        }
+    }
+    /// compute.wgsl with this model's pair_acceleration and
+    /// finish_acceleration appended, and the shared kick
+    pub fn generate_shader_module(&self, device: &wgpu::Device) -> wgpu::ShaderModule {
+        let source = format!(
+            "{}\n{}",
+            include_str!("../../shaders/compute.wgsl"),
+            self.kernel_source()
+        );
+        generate_kicking_shader(device, "compute.wgsl", &source)
+    }
+    /// The charge buffer bound for the coulomb model, a single zeroed entry
+    /// for the others so the bind group stays valid
+    pub fn generate_charge_buffer(&self, device: &wgpu::Device) -> wgpu::Buffer {
+        let placeholder = [0_f32];
+        device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Charge Buffer"),
+            contents: bytemuck::cast_slice(self.charges().unwrap_or(&placeholder)),
+            usage: wgpu::BufferUsages::STORAGE,
+        })
+    }
+    pub fn generate_bind_group_layout_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+        wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        }
+    }
+}
@@ -0,0 +1,139 @@
+use crate::prelude::*;
+
+/// Points the cumulative distribution is tabulated at when sampling
+const TABLE_SIZE: usize = 1024;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum ImfKind {
+    /// dN/dm ∝ m^-2.35
+    Salpeter,
+    /// Broken power law with slopes 0.3, 1.3 and 2.3, breaking at 0.08 and 0.5
+    #[default]
+    Kroupa,
+    /// Lognormal below one mass unit, centred on 0.079 with σ = 0.69 dex, and
+    /// Salpeter-like above
+    Chabrier,
+}
+
+/// Distribution the star masses are drawn from. The break points are in
+/// solar masses, so the bounds should be too
+#[derive(Builder, Debug, Clone, Copy, PartialEq)]
+#[builder(default)]
+pub struct InitialMassFunction {
+    pub kind: ImfKind,
+    pub min_mass: f32,
+    pub max_mass: f32,
+}
+
+impl Default for InitialMassFunction {
+    fn default() -> Self {
+        Self {
+            kind: ImfKind::default(),
+            min_mass: 0.08,
+            max_mass: 100.,
+        }
+    }
+}
+
+impl InitialMassFunction {
+    /// dN/dlog m, unnormalised but continuous across the breaks
+    pub fn density(&self, mass: f64) -> f64 {
+        match self.kind {
+            ImfKind::Salpeter => mass.powf(-1.35),
+            ImfKind::Kroupa => {
+                if mass < 0.08 {
+                    mass.powf(0.7)
+                } else if mass < 0.5 {
+                    0.08 * mass.powf(-0.3)
+                } else {
+                    0.04 * mass.powf(-1.3)
+                }
+            }
+            ImfKind::Chabrier => {
+                let lognormal = |m: f64| {
+                    let x = m.log10() - 0.079_f64.log10();
+                    (-x * x / (2. * 0.69 * 0.69)).exp()
+                };
+                if mass <= 1. {
+                    lognormal(mass)
+                } else {
+                    lognormal(1.) * mass.powf(-1.3)
+                }
+            }
+        }
+    }
+    /// Draws `count` masses by inverting the cumulative distribution,
+    /// tabulated in log mass
+    pub fn sample(&self, count: usize, rng: &mut impl Rng) -> Result<Vec<f32>> {
+        if !(self.min_mass > 0. && self.max_mass > self.min_mass) {
+            bail!(
+                "Invalid mass bounds [{}, {}], they must be positive and increasing",
+                self.min_mass,
+                self.max_mass
+            );
+        }
+        let (low, high) = ((self.min_mass as f64).ln(), (self.max_mass as f64).ln());
+        let log_mass = |i: usize| low + (high - low) * i as f64 / (TABLE_SIZE - 1) as f64;
+        let mut cumulative = vec![0.; TABLE_SIZE];
+        for i in 1..TABLE_SIZE {
+            let (a, b) = (log_mass(i - 1), log_mass(i));
+            cumulative[i] =
+                cumulative[i - 1] + 0.5 * (self.density(a.exp()) + self.density(b.exp())) * (b - a);
+        }
+        let total = cumulative[TABLE_SIZE - 1];
+
+        Ok((0..count)
+            .map(|_| {
+                let target = rng.random_range(0. ..total);
+                let i = cumulative
+                    .partition_point(|&c| c <= target)
+                    .clamp(1, TABLE_SIZE - 1);
+                let t = (target - cumulative[i - 1]) / (cumulative[i] - cumulative[i - 1]);
+                (log_mass(i - 1) + t * (log_mass(i) - log_mass(i - 1))).exp() as f32
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    fn sample(kind: ImfKind, count: usize) -> Vec<f32> {
+        let imf = InitialMassFunction {
+            kind,
+            min_mass: 1.,
+            max_mass: 100.,
+        };
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        imf.sample(count, &mut rng).unwrap()
+    }
+
+    #[test]
+    fn samples_stay_in_bounds() {
+        for kind in [ImfKind::Salpeter, ImfKind::Kroupa, ImfKind::Chabrier] {
+            let masses = sample(kind, 10_000);
+            assert!(
+                masses.iter().all(|m| (1. ..=100.).contains(m)),
+                "{kind:?} drew outside [1, 100]"
+            );
+        }
+        let reversed = InitialMassFunction {
+            min_mass: 10.,
+            max_mass: 1.,
+            ..Default::default()
+        };
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        assert!(reversed.sample(1, &mut rng).is_err());
+    }
+
+    #[test]
+    fn salpeter_slope() {
+        let masses = sample(ImfKind::Salpeter, 200_000);
+        let count = |low: f32| masses.iter().filter(|&&m| m >= low && m < 2. * low).count();
+        // Both bins are a factor of two wide, so their counts go as m^-1.35
+        let slope = (count(1.) as f64 / count(10.) as f64).log10();
+        assert!((slope - 1.35).abs() < 0.05, "slope {slope}");
+    }
+}
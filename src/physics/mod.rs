@@ -0,0 +1,117 @@
+#![allow(dead_code, unused_variables)]
+
+use bytemuck::bytes_of;
+
+use crate::prelude::*;
+
+pub mod cosmology;
+pub mod diagnostics;
+pub mod escapes;
+pub mod fft;
+pub mod force_models;
+pub mod imf;
+pub mod particle_mesh;
+pub mod periodic;
+pub mod post_newtonian;
+pub mod potentials;
+pub mod precision;
+pub mod regularization;
+pub mod restricted_three_body;
+pub mod sinks;
+pub mod snapshot;
+pub mod sph;
+pub mod units;
+pub mod zeldovich;
+
+/// Values shared by every compute pass, mirrored by the `Simulation` struct
+/// in the compute shaders
+#[derive(Builder, Debug, Pod, Zeroable, Copy, Clone)]
+#[builder(default)]
+#[repr(C)]
+pub struct SimulationUniform {
+    /// Derived from the unit system, see units::UnitSystem
+    pub gravitation_const: f32,
+    /// The time step used by each compute pass
+    pub time_step: f32,
+    /// Live bodies, the slots past them hold bodies removed by the escape
+    /// checks
+    pub body_count: u32,
+    /// Number of valid entries in the external potential buffer
+    pub potential_count: u32,
+    /// Side length of the periodic box, unused unless `periodic` is set
+    pub box_size: f32,
+    /// Non zero when the boundary is periodic, see periodic::Boundary
+    pub periodic: u32,
+    /// See particle_mesh::GravitySolver
+    pub solver: u32,
+    /// Scale of the force split between the mesh and the direct sum
+    pub split_scale: f32,
+    /// Multiplies every gravitational acceleration, a⁻³ in an expanding run
+    pub force_scale: f32,
+    /// Multiplies the velocities each step, for the Hubble drag
+    pub velocity_damping: f32,
+    /// The force model's parameter, see force_models::ForceModel
+    pub force_param: f32,
+    /// Zero disables the post newtonian corrections
+    pub speed_of_light: f32,
+    pub post_newtonian_mass_threshold: f32,
+    /// Non zero when the mass threshold picks out pairs, otherwise only the
+    /// listed pairs get the corrections
+    pub post_newtonian_enabled: u32,
+    pub post_newtonian_pair_count: u32,
+    /// Angular velocity of the frame about the z axis, zero for an inertial
+    /// frame
+    pub frame_rotation: f32,
+    /// Non zero for Kahan summed kicks and double-single positions, see
+    /// precision::Compensation
+    pub compensated: u32,
+    #[builder(setter(skip))]
+    padding: [u32; 3],
+}
+
+impl Default for SimulationUniform {
+    fn default() -> Self {
+        Self {
+            gravitation_const: 6e-3,
+            time_step: 0.005,
+            body_count: 0,
+            potential_count: 0,
+            box_size: 0.,
+            periodic: 0,
+            solver: 0,
+            split_scale: 0.,
+            force_scale: 1.,
+            velocity_damping: 1.,
+            force_param: 0.,
+            speed_of_light: 0.,
+            post_newtonian_mass_threshold: f32::INFINITY,
+            post_newtonian_enabled: 0,
+            post_newtonian_pair_count: 0,
+            frame_rotation: 0.,
+            compensated: 0,
+            padding: [0; 3],
+        }
+    }
+}
+
+impl SimulationUniform {
+    pub fn generate_buffer(&self, device: &wgpu::Device) -> wgpu::Buffer {
+        device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Simulation Uniform Buffer"),
+            contents: bytes_of(self),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        })
+    }
+    pub fn generate_bind_group_layout_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+        wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        }
+    }
+}
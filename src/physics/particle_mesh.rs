@@ -0,0 +1,251 @@
+use bytemuck::bytes_of;
+
+use super::precision::generate_kicking_shader;
+use crate::graphics::vertices::read_buffer;
+use crate::prelude::*;
+
+/// Cells along each axis of the mesh, must be a power of two and match
+/// `PM_GRID` in particle_mesh.wgsl
+pub const PM_GRID: u32 = 64;
+
+/// How the pairwise gravity is computed
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum GravitySolver {
+    /// Every pair is summed directly, O(n²)
+    #[default]
+    DirectSum,
+    /// Mass is assigned to a mesh and the poisson equation is solved with an
+    /// FFT, O(n + m log m). Forces are smoothed below the cell size
+    ParticleMesh,
+    /// The mesh takes the long range force and pairs closer than a few
+    /// `split_scale`s are summed directly (P³M). `split_scale` should be
+    /// around a cell width (box size / PM_GRID)
+    P3M { split_scale: f32 },
+}
+
+impl GravitySolver {
+    /// Solver tag and split scale as laid out in `SimulationUniform`, the tags
+    /// must match the `SOLVER_*` constants in the shaders
+    pub fn uniform_values(&self) -> (u32, f32) {
+        match self {
+            Self::DirectSum => (0, 0.),
+            Self::ParticleMesh => (1, 0.),
+            Self::P3M { split_scale } => (2, *split_scale),
+        }
+    }
+    pub fn uses_mesh(&self) -> bool {
+        !matches!(self, Self::DirectSum)
+    }
+    pub fn uses_direct_sum(&self) -> bool {
+        !matches!(self, Self::ParticleMesh)
+    }
+}
+
+/// Mirrors the `Mesh` struct in particle_mesh.wgsl
+#[derive(Debug, Pod, Zeroable, Copy, Clone)]
+#[repr(C)]
+struct MeshUniform {
+    mass_scale: f32,
+    padding: [u32; 3],
+}
+
+/// GPU resources for the particle mesh solver. Every pass reads the body
+/// buffers and simulation uniform through the same bind groups as the other
+/// compute passes, the mesh itself is bound at group 2
+#[derive(Debug)]
+pub struct ParticleMesh {
+    density: wgpu::Buffer,
+    grid: wgpu::Buffer,
+    mass_scale: f32,
+    bind_group: wgpu::BindGroup,
+    assign_pipeline: wgpu::ComputePipeline,
+    load_pipeline: wgpu::ComputePipeline,
+    forward_pipelines: [wgpu::ComputePipeline; 3],
+    solve_pipeline: wgpu::ComputePipeline,
+    inverse_pipelines: [wgpu::ComputePipeline; 3],
+    interpolate_pipeline: wgpu::ComputePipeline,
+}
+
+impl ParticleMesh {
+    fn cell_count() -> u64 {
+        (PM_GRID * PM_GRID * PM_GRID) as u64
+    }
+    /// Fixed point units per unit of mass for the density. Masses only ever
+    /// merge or get removed, so a cell can't hold more than `total_mass` and
+    /// half the range is left for the rounding of each deposit
+    pub fn mass_scale(total_mass: f64) -> f32 {
+        if total_mass > 0. {
+            ((1 << 30) as f64 / total_mass) as f32
+        } else {
+            1.
+        }
+    }
+    /// Entries of group 2, the density, the complex grid and the uniform
+    pub fn generate_bind_group_layout_entries() -> Vec<wgpu::BindGroupLayoutEntry> {
+        let entry = |binding, ty| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let storage = wgpu::BufferBindingType::Storage { read_only: false };
+        vec![
+            entry(0, storage),
+            entry(1, storage),
+            entry(2, wgpu::BufferBindingType::Uniform),
+        ]
+    }
+    fn generate_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Particle Mesh"),
+            entries: &Self::generate_bind_group_layout_entries(),
+        })
+    }
+    /// `body_layout` and `simulation_layout` are the layouts of groups 0 and 1
+    /// of the other compute pipelines, `total_mass` that of every body
+    pub fn new(
+        device: &wgpu::Device,
+        body_layout: &wgpu::BindGroupLayout,
+        simulation_layout: &wgpu::BindGroupLayout,
+        total_mass: f64,
+    ) -> Self {
+        let density = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Particle Mesh Density"),
+            size: Self::cell_count() * size_of::<i32>() as u64,
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let grid = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Particle Mesh Grid"),
+            size: Self::cell_count() * size_of::<[f32; 2]>() as u64,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+        let mass_scale = Self::mass_scale(total_mass);
+        let uniform = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Particle Mesh Uniform"),
+            contents: bytes_of(&MeshUniform {
+                mass_scale,
+                padding: [0; 3],
+            }),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+
+        let mesh_layout = Self::generate_bind_group_layout(device);
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Particle Mesh"),
+            layout: &mesh_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: density.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: grid.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: uniform.as_entire_binding(),
+                },
+            ],
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Particle Mesh"),
+            bind_group_layouts: &[body_layout, simulation_layout, &mesh_layout],
+            push_constant_ranges: &[],
+        });
+        let module = generate_kicking_shader(
+            device,
+            "particle_mesh.wgsl",
+            include_str!("../../shaders/particle_mesh.wgsl"),
+        );
+        let pipeline = |entry_point: &str| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(entry_point),
+                layout: Some(&layout),
+                module: &module,
+                entry_point: Some(entry_point),
+                compilation_options: Default::default(),
+                cache: None,
+            })
+        };
+
+        Self {
+            assign_pipeline: pipeline("assign_mass"),
+            load_pipeline: pipeline("load_density"),
+            forward_pipelines: ["fft_forward_x", "fft_forward_y", "fft_forward_z"].map(pipeline),
+            solve_pipeline: pipeline("solve_potential"),
+            inverse_pipelines: ["fft_inverse_z", "fft_inverse_y", "fft_inverse_x"].map(pipeline),
+            interpolate_pipeline: pipeline("interpolate_forces"),
+            density,
+            grid,
+            mass_scale,
+            bind_group,
+        }
+    }
+    /// Records the passes that add the mesh force to every body's velocity
+    pub fn encode(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        body_bind_group: &wgpu::BindGroup,
+        simulation_bind_group: &wgpu::BindGroup,
+        body_count: u32,
+    ) {
+        encoder.clear_buffer(&self.density, 0, None);
+
+        let body_workgroups = body_count.div_ceil(64);
+        let cell_workgroups = (Self::cell_count() as u32).div_ceil(64);
+        let line_workgroups = PM_GRID.div_ceil(8);
+
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Particle Mesh"),
+            timestamp_writes: None,
+        });
+        pass.set_bind_group(0, body_bind_group, &[]);
+        pass.set_bind_group(1, simulation_bind_group, &[]);
+        pass.set_bind_group(2, &self.bind_group, &[]);
+
+        pass.set_pipeline(&self.assign_pipeline);
+        pass.dispatch_workgroups(body_workgroups, 1, 1);
+        pass.set_pipeline(&self.load_pipeline);
+        pass.dispatch_workgroups(cell_workgroups, 1, 1);
+        for fft in &self.forward_pipelines {
+            pass.set_pipeline(fft);
+            pass.dispatch_workgroups(line_workgroups, line_workgroups, 1);
+        }
+        pass.set_pipeline(&self.solve_pipeline);
+        pass.dispatch_workgroups(cell_workgroups, 1, 1);
+        for fft in &self.inverse_pipelines {
+            pass.set_pipeline(fft);
+            pass.dispatch_workgroups(line_workgroups, line_workgroups, 1);
+        }
+        pass.set_pipeline(&self.interpolate_pipeline);
+        pass.dispatch_workgroups(body_workgroups, 1, 1);
+    }
+    /// Mass in each cell from the last step, stalling like
+    /// `BodyData::read_back`
+    pub fn read_density(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> Result<Vec<f32>> {
+        let readable = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: self.density.size(),
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let mut encoder = device.create_command_encoder(&Default::default());
+        encoder.copy_buffer_to_buffer(&self.density, 0, &readable, 0, readable.size());
+        queue.submit(Some(encoder.finish()));
+        let density = read_buffer::<i32>(&readable, device)?;
+        Ok(density
+            .into_iter()
+            .map(|m| m as f32 / self.mass_scale)
+            .collect())
+    }
+}
@@ -0,0 +1,216 @@
+use crate::prelude::*;
+
+/// Number of cells along each axis of the Ewald correction table, the table
+/// has one more sample than this per axis so the edges at 0 and L/2 are both
+/// included. Must match `EWALD_RESOLUTION` in compute.wgsl
+pub const EWALD_RESOLUTION: usize = 24;
+
+/// Splitting parameter between the real and fourier space sums, in units of
+/// the box size
+const EWALD_ALPHA: f64 = 2.;
+/// How many images/wave vectors along each axis are summed over
+const EWALD_IMAGES: i32 = 4;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum Boundary {
+    /// Plain all-pairs gravity in an infinite space
+    #[default]
+    Open,
+    /// Positions wrap into the cube [-L/2, L/2)³ centred on the origin, and
+    /// forces include every periodic image through an Ewald correction
+    Periodic { box_size: f32 },
+}
+
+impl Boundary {
+    /// Flag and box size as laid out in `SimulationUniform`
+    pub fn uniform_values(&self) -> (u32, f32) {
+        match self {
+            Self::Open => (0, 0.),
+            Self::Periodic { box_size } => (1, *box_size),
+        }
+    }
+    /// The line list for the outline of the box, in the vertex format the
+    /// render pipeline takes
+    pub fn outline_vertices(&self) -> Vec<[f32; 4]> {
+        let Self::Periodic { box_size } = self else {
+            return Vec::new();
+        };
+        let h = box_size / 2.;
+        let corner = |i: u32| {
+            [
+                if i & 1 == 0 { -h } else { h },
+                if i & 2 == 0 { -h } else { h },
+                if i & 4 == 0 { -h } else { h },
+                1.,
+            ]
+        };
+        // Each edge connects two corners that differ by one bit
+        (0..8_u32)
+            .flat_map(|i| [1, 2, 4].map(|bit| (i, i | bit)))
+            .filter(|(a, b)| a != b)
+            .flat_map(|(a, b)| [corner(a), corner(b)])
+            .collect()
+    }
+}
+
+/// Complementary error function, from Numerical Recipes (fractional error
+/// below 1.2e-7)
+fn erfc(x: f64) -> f64 {
+    let z = x.abs();
+    let t = 1. / (1. + 0.5 * z);
+    let poly = -z * z - 1.26551223
+        + t * (1.00002368
+            + t * (0.37409196
+                + t * (0.09678418
+                    + t * (-0.18628806
+                        + t * (0.27886807
+                            + t * (-1.13520398
+                                + t * (1.48851587 + t * (-0.82215223 + t * 0.17087277))))))));
+    let ans = t * poly.exp();
+    if x >= 0. {
+        ans
+    } else {
+        2. - ans
+    }
+}
+
+/// The acceleration at `x` due to a unit mass at the origin and all of its
+/// periodic images in a unit box (with G = 1), minus the contribution of
+/// the nearest image, which the kernel computes directly
+fn ewald_correction(x: [f64; 3]) -> [f64; 3] {
+    use std::f64::consts::PI;
+
+    let r2 = x[0] * x[0] + x[1] * x[1] + x[2] * x[2];
+    if r2 == 0. {
+        return [0.; 3];
+    }
+    let r = r2.sqrt();
+    // Start with the newtonian term removed
+    let mut force = [x[0] / (r2 * r), x[1] / (r2 * r), x[2] / (r2 * r)];
+
+    for i in -EWALD_IMAGES..=EWALD_IMAGES {
+        for j in -EWALD_IMAGES..=EWALD_IMAGES {
+            for k in -EWALD_IMAGES..=EWALD_IMAGES {
+                let n = [i as f64, j as f64, k as f64];
+
+                let d = [x[0] - n[0], x[1] - n[1], x[2] - n[2]];
+                let dr = (d[0] * d[0] + d[1] * d[1] + d[2] * d[2]).sqrt();
+                let val = erfc(EWALD_ALPHA * dr)
+                    + 2. * EWALD_ALPHA * dr / PI.sqrt()
+                        * (-EWALD_ALPHA * EWALD_ALPHA * dr * dr).exp();
+                for c in 0..3 {
+                    force[c] -= d[c] / (dr * dr * dr) * val;
+                }
+
+                let h2 = n[0] * n[0] + n[1] * n[1] + n[2] * n[2];
+                if h2 > 0. {
+                    let hdotx = n[0] * x[0] + n[1] * x[1] + n[2] * x[2];
+                    let val = 2. / h2
+                        * (-PI * PI * h2 / (EWALD_ALPHA * EWALD_ALPHA)).exp()
+                        * (2. * PI * hdotx).sin();
+                    for c in 0..3 {
+                        force[c] -= n[c] * val;
+                    }
+                }
+            }
+        }
+    }
+    force
+}
+
+/// Precomputed Ewald corrections over the positive octant [0, 1/2]³ of a unit
+/// box. The correction is odd in each coordinate, so the kernel mirrors the
+/// lookup into this octant and flips the signs back afterwards.
+#[derive(Debug)]
+pub struct EwaldTable {
+    pub data: Vec<[f32; 4]>,
+}
+
+impl EwaldTable {
+    pub fn generate() -> Self {
+        let start = std::time::Instant::now();
+        let n = EWALD_RESOLUTION + 1;
+        let mut data = Vec::with_capacity(n * n * n);
+        // Index order must match ewald_index in compute.wgsl, x varies fastest
+        for k in 0..n {
+            for j in 0..n {
+                for i in 0..n {
+                    let x = [i, j, k].map(|c| 0.5 * c as f64 / EWALD_RESOLUTION as f64);
+                    let f = ewald_correction(x);
+                    data.push([f[0] as f32, f[1] as f32, f[2] as f32, 0.]);
+                }
+            }
+        }
+        info!("Ewald table generation: {:?}", start.elapsed());
+        Self { data }
+    }
+    pub fn generate_buffer(&self, device: &wgpu::Device) -> wgpu::Buffer {
+        device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Ewald Table Buffer"),
+            contents: bytemuck::cast_slice(&self.data),
+            usage: wgpu::BufferUsages::STORAGE,
+        })
+    }
+    /// A single zeroed entry, bound while the boundary is open so the bind
+    /// group stays valid
+    pub fn generate_placeholder_buffer(device: &wgpu::Device) -> wgpu::Buffer {
+        device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Ewald Table Placeholder"),
+            contents: bytemuck::cast_slice(&[[0_f32; 4]]),
+            usage: wgpu::BufferUsages::STORAGE,
+        })
+    }
+    pub fn generate_bind_group_layout_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+        wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The correction plus the nearest image, the whole periodic force
+    fn periodic_force(x: [f64; 3]) -> [f64; 3] {
+        let r3 = (x[0] * x[0] + x[1] * x[1] + x[2] * x[2]).powf(1.5);
+        let correction = ewald_correction(x);
+        std::array::from_fn(|c| correction[c] - x[c] / r3)
+    }
+
+    #[test]
+    fn correction_is_odd() {
+        for x in [[0.1, 0.2, 0.3], [0.45, -0.05, 0.2], [-0.3, 0.3, 0.01]] {
+            let forward = ewald_correction(x);
+            let mirrored = ewald_correction(x.map(|c| -c));
+            for c in 0..3 {
+                assert!((forward[c] + mirrored[c]).abs() < 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn correction_vanishes_at_the_centre() {
+        assert_eq!(ewald_correction([0.; 3]), [0.; 3]);
+        let near = ewald_correction([1e-3, 2e-3, -1e-3]);
+        assert!(near.iter().all(|c| c.abs() < 1e-2), "{near:?}");
+        let table = EwaldTable::generate();
+        assert_eq!(table.data[0], [0.; 4]);
+    }
+
+    #[test]
+    fn images_balance_half_a_box_away() {
+        // Every image has a mirror across the faces and corner of the cell
+        let corner = periodic_force([0.5; 3]);
+        assert!(corner.iter().all(|c| c.abs() < 1e-6), "{corner:?}");
+        let face = periodic_force([0.5, 0.1, 0.2]);
+        assert!(face[0].abs() < 1e-6, "{face:?}");
+    }
+}
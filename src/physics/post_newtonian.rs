@@ -0,0 +1,130 @@
+use crate::prelude::*;
+
+/// First post newtonian corrections to the pairwise gravity, for perihelion
+/// precession and compact binaries. A pair is corrected if either body is at
+/// least `mass_threshold` heavy, or if it's one of the listed `pairs`
+#[derive(Builder, Debug, Clone, PartialEq)]
+pub struct PostNewtonian {
+    /// Speed of light in simulation units
+    pub speed_of_light: f32,
+    #[builder(default = "f32::INFINITY")]
+    pub mass_threshold: f32,
+    /// Body indices
+    #[builder(default)]
+    pub pairs: Vec<[u32; 2]>,
+}
+
+impl PostNewtonian {
+    /// The pair buffer, a single unused entry when there are no pairs so the
+    /// bind group stays valid
+    pub fn generate_pair_buffer(pairs: &[[u32; 2]], device: &wgpu::Device) -> wgpu::Buffer {
+        let placeholder = [[0_u32; 2]];
+        device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Post Newtonian Pairs"),
+            contents: bytemuck::cast_slice(if pairs.is_empty() {
+                &placeholder
+            } else {
+                pairs
+            }),
+            usage: wgpu::BufferUsages::STORAGE,
+        })
+    }
+    pub fn generate_bind_group_layout_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+        wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        }
+    }
+}
+
+/// The pass that applies the corrections, after the step's other kicks. The
+/// velocities are copied first and every body reads the copy, so none sees
+/// another's velocity half updated. Bodies and simulation are bound at groups
+/// 0 and 1 like the force kernel, the copy at group 2
+#[derive(Debug)]
+pub struct PostNewtonianKick {
+    step_velocities: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    pipeline: wgpu::ComputePipeline,
+}
+
+impl PostNewtonianKick {
+    /// `module` is the force kernel's, see `ForceModel::generate_shader_module`
+    pub fn new(
+        device: &wgpu::Device,
+        module: &wgpu::ShaderModule,
+        body_layout: &wgpu::BindGroupLayout,
+        simulation_layout: &wgpu::BindGroupLayout,
+        body_count: usize,
+    ) -> Self {
+        let step_velocities = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Step Velocities"),
+            size: (body_count.max(1) * size_of::<[f32; 4]>()) as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let step_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Step Velocities"),
+            entries: &[PostNewtonian::generate_bind_group_layout_entry(0)],
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Step Velocities"),
+            layout: &step_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: step_velocities.as_entire_binding(),
+            }],
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Post Newtonian"),
+            bind_group_layouts: &[body_layout, simulation_layout, &step_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("cs_post_newtonian"),
+            layout: Some(&layout),
+            module,
+            entry_point: Some("cs_post_newtonian"),
+            compilation_options: Default::default(),
+            cache: None,
+        });
+        Self {
+            step_velocities,
+            bind_group,
+            pipeline,
+        }
+    }
+    /// Records the copy, before any of the step's kicks
+    pub fn encode_copy(&self, encoder: &mut wgpu::CommandEncoder, velocities: &wgpu::Buffer) {
+        encoder.copy_buffer_to_buffer(
+            velocities,
+            0,
+            &self.step_velocities,
+            0,
+            self.step_velocities.size(),
+        );
+    }
+    pub fn encode(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        body_bind_group: &wgpu::BindGroup,
+        simulation_bind_group: &wgpu::BindGroup,
+        body_count: u32,
+    ) {
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Post Newtonian"),
+            timestamp_writes: None,
+        });
+        pass.set_bind_group(0, body_bind_group, &[]);
+        pass.set_bind_group(1, simulation_bind_group, &[]);
+        pass.set_bind_group(2, &self.bind_group, &[]);
+        pass.set_pipeline(&self.pipeline);
+        pass.dispatch_workgroups(body_count.div_ceil(64), 1, 1);
+    }
+}
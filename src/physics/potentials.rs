@@ -0,0 +1,380 @@
+use crate::prelude::*;
+
+/// Kind tags, these must match the `POTENTIAL_*` constants in compute.wgsl
+const KIND_POINT_MASS: u32 = 0;
+const KIND_NFW: u32 = 1;
+const KIND_HERNQUIST: u32 = 2;
+const KIND_LOGARITHMIC: u32 = 3;
+const KIND_MIYAMOTO_NAGAI: u32 = 4;
+
+/// A static, analytic potential that every body feels on top of the pairwise
+/// gravity. Any number of these can be stacked, the accelerations are simply
+/// summed.
+///
+/// `axis` is the symmetry axis for the flattened potentials (the disk normal
+/// for Miyamoto-Nagai, the short axis for the logarithmic halo), and does not
+/// need to be normalized.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExternalPotential {
+    /// A fixed point mass, softened by `softening` to avoid the singularity
+    PointMass {
+        position: Vec3,
+        mass: f32,
+        softening: f32,
+    },
+    /// Navarro-Frenk-White halo, `mass` is the characteristic mass
+    /// 4π ρ0 rs³ rather than a virial mass
+    Nfw {
+        centre: Vec3,
+        mass: f32,
+        scale_radius: f32,
+    },
+    Hernquist {
+        centre: Vec3,
+        mass: f32,
+        scale_radius: f32,
+    },
+    /// Φ = ½ v0² ln(Rc² + R² + z²/q²), doesn't depend on G
+    Logarithmic {
+        centre: Vec3,
+        axis: Vec3,
+        circular_velocity: f32,
+        core_radius: f32,
+        flattening: f32,
+    },
+    MiyamotoNagai {
+        centre: Vec3,
+        axis: Vec3,
+        mass: f32,
+        scale_length: f32,
+        scale_height: f32,
+    },
+}
+
+/// The layout of a potential in the compute shaders' storage buffer
+#[derive(Debug, Default, Pod, Zeroable, Copy, Clone)]
+#[repr(C)]
+pub struct GpuPotential {
+    pub centre: [f32; 4],
+    pub axis: [f32; 4],
+    pub params: [f32; 4],
+    pub kind: u32,
+    padding: [u32; 3],
+}
+
+/// Splits `d` into the components perpendicular to and along `axis`
+fn cylindrical(d: Vec3, axis: Vec3) -> (Vec3, Vec3) {
+    let axis = axis.normalize_or(Vec3::Z);
+    let z = axis * d.dot(axis);
+    (d - z, z)
+}
+
+impl ExternalPotential {
+    /// The acceleration felt by a body at `position`
+    pub fn acceleration(&self, position: Vec3, gravitation_const: f32) -> Vec3 {
+        let g = gravitation_const;
+        match *self {
+            Self::PointMass {
+                position: p,
+                mass,
+                softening,
+            } => {
+                let d = p - position;
+                let l = (d.length_squared() + softening * softening).sqrt();
+                d * g * mass / (l * l * l)
+            }
+            Self::Nfw {
+                centre,
+                mass,
+                scale_radius,
+            } => {
+                let d = centre - position;
+                let r = d.length();
+                if r == 0. {
+                    return Vec3::ZERO;
+                }
+                let enclosed = (1. + r / scale_radius).ln() - r / (r + scale_radius);
+                d * g * mass * enclosed / (r * r * r)
+            }
+            Self::Hernquist {
+                centre,
+                mass,
+                scale_radius,
+            } => {
+                let d = centre - position;
+                let r = d.length();
+                if r == 0. {
+                    return Vec3::ZERO;
+                }
+                d * g * mass / (r * (r + scale_radius).powi(2))
+            }
+            Self::Logarithmic {
+                centre,
+                axis,
+                circular_velocity,
+                core_radius,
+                flattening,
+            } => {
+                let (big_r, z) = cylindrical(position - centre, axis);
+                let q2 = flattening * flattening;
+                let denom =
+                    core_radius * core_radius + big_r.length_squared() + z.length_squared() / q2;
+                -(big_r + z / q2) * circular_velocity * circular_velocity / denom
+            }
+            Self::MiyamotoNagai {
+                centre,
+                axis,
+                mass,
+                scale_length,
+                scale_height,
+            } => {
+                let (big_r, z) = cylindrical(position - centre, axis);
+                let zb = (z.length_squared() + scale_height * scale_height).sqrt();
+                let s = scale_length + zb;
+                let d = (big_r.length_squared() + s * s).sqrt();
+                -(big_r + z * s / zb) * g * mass / (d * d * d)
+            }
+        }
+    }
+    /// The potential per unit mass at `position`, used by the energy
+    /// diagnostics
+    pub fn potential(&self, position: Vec3, gravitation_const: f32) -> f32 {
+        let g = gravitation_const;
+        match *self {
+            Self::PointMass {
+                position: p,
+                mass,
+                softening,
+            } => -g * mass / (p.distance_squared(position) + softening * softening).sqrt(),
+            Self::Nfw {
+                centre,
+                mass,
+                scale_radius,
+            } => {
+                let r = centre.distance(position);
+                if r == 0. {
+                    return -g * mass / scale_radius;
+                }
+                -g * mass * (1. + r / scale_radius).ln() / r
+            }
+            Self::Hernquist {
+                centre,
+                mass,
+                scale_radius,
+            } => -g * mass / (centre.distance(position) + scale_radius),
+            Self::Logarithmic {
+                centre,
+                axis,
+                circular_velocity,
+                core_radius,
+                flattening,
+            } => {
+                let (big_r, z) = cylindrical(position - centre, axis);
+                let inner = core_radius * core_radius
+                    + big_r.length_squared()
+                    + z.length_squared() / (flattening * flattening);
+                0.5 * circular_velocity * circular_velocity * inner.ln()
+            }
+            Self::MiyamotoNagai {
+                centre,
+                axis,
+                mass,
+                scale_length,
+                scale_height,
+            } => {
+                let (big_r, z) = cylindrical(position - centre, axis);
+                let s = scale_length + (z.length_squared() + scale_height * scale_height).sqrt();
+                -g * mass / (big_r.length_squared() + s * s).sqrt()
+            }
+        }
+    }
+    pub fn to_gpu(self) -> GpuPotential {
+        let (kind, centre, axis, params) = match self {
+            Self::PointMass {
+                position,
+                mass,
+                softening,
+            } => (
+                KIND_POINT_MASS,
+                position,
+                Vec3::Z,
+                [mass, softening, 0., 0.],
+            ),
+            Self::Nfw {
+                centre,
+                mass,
+                scale_radius,
+            } => (KIND_NFW, centre, Vec3::Z, [mass, scale_radius, 0., 0.]),
+            Self::Hernquist {
+                centre,
+                mass,
+                scale_radius,
+            } => (
+                KIND_HERNQUIST,
+                centre,
+                Vec3::Z,
+                [mass, scale_radius, 0., 0.],
+            ),
+            Self::Logarithmic {
+                centre,
+                axis,
+                circular_velocity,
+                core_radius,
+                flattening,
+            } => (
+                KIND_LOGARITHMIC,
+                centre,
+                axis,
+                [circular_velocity, core_radius, flattening, 0.],
+            ),
+            Self::MiyamotoNagai {
+                centre,
+                axis,
+                mass,
+                scale_length,
+                scale_height,
+            } => (
+                KIND_MIYAMOTO_NAGAI,
+                centre,
+                axis,
+                [mass, scale_length, scale_height, 0.],
+            ),
+        };
+        GpuPotential {
+            centre: centre.extend(0.).to_array(),
+            axis: axis.normalize_or(Vec3::Z).extend(0.).to_array(),
+            params,
+            kind,
+            padding: [0; 3],
+        }
+    }
+}
+
+/// Holds the storage buffer the compute shaders read the potentials from.
+/// The buffer always has room for at least one potential, as empty storage
+/// bindings aren't allowed.
+#[derive(Debug)]
+pub struct PotentialBuffer {
+    pub buffer: wgpu::Buffer,
+    capacity: usize,
+}
+
+impl PotentialBuffer {
+    fn create_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("External Potential Buffer"),
+            size: (size_of::<GpuPotential>() * capacity.max(1)) as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+    pub fn new(device: &wgpu::Device) -> Self {
+        Self {
+            buffer: Self::create_buffer(device, 1),
+            capacity: 1,
+        }
+    }
+    /// Uploads the potentials, returns true if the buffer had to be
+    /// reallocated (meaning any bind groups using it are stale)
+    pub fn write(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        potentials: &[ExternalPotential],
+    ) -> bool {
+        let reallocated = potentials.len() > self.capacity;
+        if reallocated {
+            self.capacity = potentials.len();
+            self.buffer = Self::create_buffer(device, self.capacity);
+        }
+        if !potentials.is_empty() {
+            let gpu: Vec<GpuPotential> = potentials.iter().map(|p| p.to_gpu()).collect();
+            queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&gpu));
+        }
+        reallocated
+    }
+    pub fn generate_bind_group_layout_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+        wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// -∇Φ by central differences
+    fn numerical_acceleration(potential: &ExternalPotential, position: Vec3) -> Vec3 {
+        let h = 1e-3;
+        let component = |axis: Vec3| {
+            let forward = potential.potential(position + axis * h, 1.);
+            let back = potential.potential(position - axis * h, 1.);
+            -(forward - back) / (2. * h)
+        };
+        Vec3::new(component(Vec3::X), component(Vec3::Y), component(Vec3::Z))
+    }
+
+    #[test]
+    fn acceleration_is_the_potential_gradient() {
+        let centre = Vec3::new(0.5, -0.25, 0.125);
+        let axis = Vec3::new(0.2, 0.3, 1.);
+        let potentials = [
+            ExternalPotential::PointMass {
+                position: centre,
+                mass: 2.,
+                softening: 0.1,
+            },
+            ExternalPotential::Nfw {
+                centre,
+                mass: 2.,
+                scale_radius: 1.5,
+            },
+            ExternalPotential::Hernquist {
+                centre,
+                mass: 2.,
+                scale_radius: 0.5,
+            },
+            ExternalPotential::Logarithmic {
+                centre,
+                axis,
+                circular_velocity: 1.2,
+                core_radius: 0.3,
+                flattening: 0.8,
+            },
+            ExternalPotential::MiyamotoNagai {
+                centre,
+                axis,
+                mass: 2.,
+                scale_length: 1.,
+                scale_height: 0.2,
+            },
+        ];
+        let positions = [
+            Vec3::new(1.5, 0.5, -0.5),
+            Vec3::new(-1., 2., 0.75),
+            Vec3::new(0.6, -0.3, 1.2),
+        ];
+        for potential in &potentials {
+            for &position in &positions {
+                let analytic = potential.acceleration(position, 1.);
+                let numerical = numerical_acceleration(potential, position);
+                assert!(
+                    analytic.distance(numerical) < 1e-3 * analytic.length().max(1.),
+                    "{:?} at {}: {} vs {}",
+                    potential,
+                    position,
+                    analytic,
+                    numerical
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,77 @@
+use std::borrow::Cow;
+
+use crate::graphics::vertices::encode_swap;
+
+/// Low order parts of the body state, used while compensated summation is
+/// enabled. Bound with the body buffers at group 0, bindings 4 and 5
+#[derive(Debug)]
+pub struct Compensation {
+    /// Low halves of the double-single positions. The positions buffer holds
+    /// the high halves, so the renderers and read backs still see plain f32
+    pub position_lo: wgpu::Buffer,
+    /// Running Kahan compensation of each velocity
+    pub velocity: wgpu::Buffer,
+    len: usize,
+}
+
+impl Compensation {
+    pub fn new(device: &wgpu::Device, body_count: usize) -> Self {
+        let buffer = |label| {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size: (size_of::<[f32; 4]>() * body_count.max(1)) as u64,
+                usage: wgpu::BufferUsages::STORAGE
+                    | wgpu::BufferUsages::COPY_DST
+                    | wgpu::BufferUsages::COPY_SRC,
+                mapped_at_creation: false,
+            })
+        };
+        Self {
+            position_lo: buffer("Position Low Parts"),
+            velocity: buffer("Velocity Compensation"),
+            len: body_count,
+        }
+    }
+    /// Drops the low order parts of one body, which must be done whenever the
+    /// cpu overwrites its position or velocity
+    pub fn reset(&self, queue: &wgpu::Queue, index: u32) {
+        let offset = index as u64 * size_of::<[f32; 4]>() as u64;
+        for buffer in [&self.position_lo, &self.velocity] {
+            queue.write_buffer(buffer, offset, bytemuck::bytes_of(&[0_f32; 4]));
+        }
+    }
+    /// Swaps the low order parts of two bodies along with their slots
+    pub fn encode_swap(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        scratch: &wgpu::Buffer,
+        a: u32,
+        b: u32,
+    ) {
+        for buffer in [&self.position_lo, &self.velocity] {
+            encode_swap(encoder, buffer, scratch, size_of::<[f32; 4]>() as u64, a, b);
+        }
+    }
+    /// Drops the low order parts of every body
+    pub fn clear(&self, queue: &wgpu::Queue) {
+        let zeros = vec![0_u8; size_of::<[f32; 4]>() * self.len];
+        for buffer in [&self.position_lo, &self.velocity] {
+            queue.write_buffer(buffer, 0, &zeros);
+        }
+    }
+}
+
+/// Compiles `source` with the `kick` and `reset_compensation` functions of
+/// kick.wgsl appended, so every pass changing the velocities honours
+/// `Simulation::compensated`
+pub fn generate_kicking_shader(
+    device: &wgpu::Device,
+    label: &str,
+    source: &str,
+) -> wgpu::ShaderModule {
+    let source = format!("{}\n{}", source, include_str!("../../shaders/kick.wgsl"));
+    device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some(label),
+        source: wgpu::ShaderSource::Wgsl(Cow::Owned(source)),
+    })
+}
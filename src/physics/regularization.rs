@@ -0,0 +1,371 @@
+use glam::{DMat3, DVec3};
+
+use crate::graphics::vertices::{UnbufferedBodyData, SPECIES_COLLISIONLESS};
+use crate::prelude::*;
+
+/// Settings for handing tight binaries to the Kustaanheimo-Stiefel
+/// sub-integrator
+#[derive(Builder, Debug, Clone, Copy, PartialEq)]
+#[builder(default)]
+pub struct RegularizationParams {
+    /// Bound pairs closer than this are taken off the gpu
+    pub binding_radius: f32,
+    /// Pairs are handed back once they separate past this, must be larger
+    /// than `binding_radius` or released pairs are taken straight back
+    pub release_radius: f32,
+    /// Frames between searches for new pairs, each search reads the bodies
+    /// back from the gpu and refreshes the tidal field on existing pairs. The
+    /// field is held fixed in between, so a pair passing close to another
+    /// body feels it late at long intervals
+    pub check_interval: u32,
+    /// Fraction of the local dynamical time per KS step, must be positive
+    pub accuracy: f64,
+}
+
+impl Default for RegularizationParams {
+    fn default() -> Self {
+        Self {
+            binding_radius: 0.01,
+            release_radius: 0.03,
+            check_interval: 50,
+            accuracy: 0.02,
+        }
+    }
+}
+
+/// Spatial part of L(u) w for the KS matrix L, the position is L(u) u
+fn ks_apply(u: [f64; 4], w: [f64; 4]) -> DVec3 {
+    DVec3::new(
+        u[0] * w[0] - u[1] * w[1] - u[2] * w[2] + u[3] * w[3],
+        u[1] * w[0] + u[0] * w[1] - u[3] * w[2] - u[2] * w[3],
+        u[2] * w[0] + u[3] * w[1] + u[0] * w[2] + u[1] * w[3],
+    )
+}
+
+/// Lᵀ(u) (p, 0)
+fn ks_transpose(u: [f64; 4], p: DVec3) -> [f64; 4] {
+    [
+        u[0] * p.x + u[1] * p.y + u[2] * p.z,
+        -u[1] * p.x + u[0] * p.y + u[3] * p.z,
+        -u[2] * p.x - u[3] * p.y + u[0] * p.z,
+        u[3] * p.x - u[2] * p.y + u[1] * p.z,
+    ]
+}
+
+fn dot4(a: [f64; 4], b: [f64; 4]) -> f64 {
+    a.iter().zip(b).map(|(a, b)| a * b).sum()
+}
+
+/// Relative motion in KS coordinates, integrated in the fictitious time s
+/// where dt = r ds. The two body problem is a harmonic oscillator here, so
+/// close approaches need no smaller steps than the rest of the orbit
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct KsState {
+    u: [f64; 4],
+    /// du/ds
+    w: [f64; 4],
+    /// Specific binding energy, ½v² - GM/r
+    energy: f64,
+}
+
+impl KsState {
+    fn new(x: DVec3, v: DVec3, gravitational_mass: f64) -> Self {
+        let r = x.length();
+        let u = if x.x >= 0. {
+            let u1 = (0.5 * (r + x.x)).sqrt();
+            [u1, x.y / (2. * u1), x.z / (2. * u1), 0.]
+        } else {
+            let u2 = (0.5 * (r - x.x)).sqrt();
+            [x.y / (2. * u2), u2, 0., x.z / (2. * u2)]
+        };
+        let w = ks_transpose(u, v).map(|c| 0.5 * c);
+        Self {
+            u,
+            w,
+            energy: 0.5 * v.length_squared() - gravitational_mass / r,
+        }
+    }
+    fn separation(&self) -> f64 {
+        dot4(self.u, self.u)
+    }
+    fn position(&self) -> DVec3 {
+        ks_apply(self.u, self.u)
+    }
+    fn velocity(&self) -> DVec3 {
+        ks_apply(self.u, self.w) * (2. / self.separation())
+    }
+    /// d/ds of (u, w, energy) and the physical time, with the tidal
+    /// acceleration `tidal * x` perturbing the pair
+    fn derivative(&self, tidal: &DMat3) -> (KsState, f64) {
+        let r = self.separation();
+        let q = ks_transpose(self.u, *tidal * self.position());
+        let mut w = [0.; 4];
+        for i in 0..4 {
+            w[i] = 0.5 * self.energy * self.u[i] + 0.5 * r * q[i];
+        }
+        (
+            KsState {
+                u: self.w,
+                w,
+                energy: 2. * dot4(self.w, q),
+            },
+            r,
+        )
+    }
+    fn add_scaled(&self, d: &KsState, ds: f64) -> KsState {
+        KsState {
+            u: std::array::from_fn(|i| self.u[i] + d.u[i] * ds),
+            w: std::array::from_fn(|i| self.w[i] + d.w[i] * ds),
+            energy: self.energy + d.energy * ds,
+        }
+    }
+    /// One RK4 step in s, returns the physical time it covered
+    fn step(&mut self, tidal: &DMat3, ds: f64) -> f64 {
+        let (k1, t1) = self.derivative(tidal);
+        let (k2, t2) = self.add_scaled(&k1, 0.5 * ds).derivative(tidal);
+        let (k3, t3) = self.add_scaled(&k2, 0.5 * ds).derivative(tidal);
+        let (k4, t4) = self.add_scaled(&k3, ds).derivative(tidal);
+        let combined = KsState {
+            u: std::array::from_fn(|i| (k1.u[i] + 2. * k2.u[i] + 2. * k3.u[i] + k4.u[i]) / 6.),
+            w: std::array::from_fn(|i| (k1.w[i] + 2. * k2.w[i] + 2. * k3.w[i] + k4.w[i]) / 6.),
+            energy: (k1.energy + 2. * k2.energy + 2. * k3.energy + k4.energy) / 6.,
+        };
+        *self = self.add_scaled(&combined, ds);
+        (t1 + 2. * t2 + 2. * t3 + t4) / 6. * ds
+    }
+}
+
+/// A bound pair integrated on the cpu. On the gpu the primary carries the
+/// pair's combined mass at its centre of mass and the secondary is removed
+#[derive(Debug, Clone, PartialEq)]
+pub struct RegularizedPair {
+    pub primary: u32,
+    pub secondary: u32,
+    pub masses: [f32; 2],
+    ks: KsState,
+    /// Tidal tensor of the rest of the system at the centre of mass, from
+    /// the last search
+    tidal: DMat3,
+}
+
+impl RegularizedPair {
+    /// Position and velocity of the secondary relative to the primary
+    pub fn relative_motion(&self) -> (Vec3, Vec3) {
+        (self.ks.position().as_vec3(), self.ks.velocity().as_vec3())
+    }
+    pub fn separation(&self) -> f32 {
+        self.ks.separation() as f32
+    }
+    pub fn total_mass(&self) -> f32 {
+        self.masses[0] + self.masses[1]
+    }
+    /// Positions and velocities of both bodies given the centre of mass
+    pub fn split(&self, centre: Vec3, drift: Vec3) -> [(Vec3, Vec3); 2] {
+        let (x, v) = self.relative_motion();
+        let [m1, m2] = self.masses;
+        let m = m1 + m2;
+        [
+            (centre - x * m2 / m, drift - v * m2 / m),
+            (centre + x * m1 / m, drift + v * m1 / m),
+        ]
+    }
+    /// Integrates the relative motion forward by `dt` of physical time
+    fn advance(&mut self, dt: f64, gravitation_const: f64, accuracy: f64) {
+        let gravitational_mass = gravitation_const * self.total_mass() as f64;
+        let mut elapsed = 0.;
+        // Stop short of dt by a rounding error rather than take a vanishing step
+        while dt - elapsed > dt * 1e-9 {
+            let r = self.ks.separation();
+            let mut ds = accuracy * (r / gravitational_mass).sqrt();
+            // Trim the last step so it lands on the frame, dt/ds ≈ r
+            if elapsed + r * ds > dt {
+                ds = (dt - elapsed) / r;
+            }
+            elapsed += self.ks.step(&self.tidal, ds);
+        }
+    }
+}
+
+/// Tidal tensor at `centre` from every live body in `data` except `skip`,
+/// so the relative acceleration of a pair separated by x is T x
+fn tidal_tensor(
+    data: &UnbufferedBodyData,
+    centre: Vec3,
+    skip: [u32; 2],
+    gravitation_const: f64,
+) -> DMat3 {
+    let mut tidal = DMat3::ZERO;
+    for (i, (p, m)) in data.positions.iter().zip(data.mass.iter()).enumerate() {
+        if skip.contains(&(i as u32)) || p[3] == 0. || *m == 0. {
+            continue;
+        }
+        let d = (Vec4::from_array(*p).truncate() - centre).as_dvec3();
+        let r = d.length();
+        if r == 0. {
+            continue;
+        }
+        let scale = gravitation_const * *m as f64 / r.powi(3);
+        let outer = DMat3::from_cols(d * d.x, d * d.y, d * d.z) * (3. / (r * r));
+        tidal += (outer - DMat3::IDENTITY) * scale;
+    }
+    tidal
+}
+
+/// Every pair currently off the gpu
+#[derive(Debug, Clone)]
+pub struct Regularization {
+    pub params: RegularizationParams,
+    pub pairs: Vec<RegularizedPair>,
+    frames_since_check: u32,
+}
+
+impl Regularization {
+    pub fn new(params: RegularizationParams) -> Self {
+        Self {
+            params,
+            pairs: Vec::new(),
+            frames_since_check: 0,
+        }
+    }
+    /// Counts a frame, true once enough have passed for another search
+    pub fn frame_due(&mut self) -> bool {
+        self.frames_since_check += 1;
+        if self.frames_since_check < self.params.check_interval.max(1) {
+            return false;
+        }
+        self.frames_since_check = 0;
+        true
+    }
+    fn in_pair(&self, body: u32) -> bool {
+        self.pairs
+            .iter()
+            .any(|p| p.primary == body || p.secondary == body)
+    }
+    /// Refreshes the tidal field of the existing pairs and takes over every
+    /// new bound pair inside the binding radius. Only collisionless bodies are
+    /// paired, and each at most once. Returns the new pairs
+    pub fn search(
+        &mut self,
+        data: &UnbufferedBodyData,
+        gravitation_const: f32,
+    ) -> Vec<RegularizedPair> {
+        let g = gravitation_const as f64;
+        let position = |i: usize| Vec4::from_array(data.positions[i]).truncate();
+        let velocity = |i: usize| Vec4::from_array(data.velocities[i]).truncate();
+
+        for pair in &mut self.pairs {
+            let centre = position(pair.primary as usize);
+            pair.tidal = tidal_tensor(data, centre, [pair.primary, pair.secondary], g);
+        }
+
+        let candidate = |i: usize| {
+            data.positions[i][3] != 0.
+                && data.mass[i] > 0.
+                && data.species[i] == SPECIES_COLLISIONLESS
+        };
+        let mut taken: Vec<bool> = (0..data.mass.len())
+            .map(|i| !candidate(i) || self.in_pair(i as u32))
+            .collect();
+        let mut new_pairs = Vec::new();
+        for i in 0..data.mass.len() {
+            if taken[i] {
+                continue;
+            }
+            // The nearest bound partner inside the binding radius
+            let partner = (i + 1..data.mass.len())
+                .filter(|&j| !taken[j])
+                .map(|j| (j, position(i).distance(position(j))))
+                .filter(|&(j, r)| {
+                    let v = velocity(j) - velocity(i);
+                    let m = (data.mass[i] + data.mass[j]) as f64;
+                    r > 0.
+                        && r < self.params.binding_radius
+                        && 0.5 * v.length_squared() as f64 - g * m / (r as f64) < 0.
+                })
+                .min_by(|a, b| a.1.total_cmp(&b.1));
+            let Some((j, _)) = partner else {
+                continue;
+            };
+            taken[i] = true;
+            taken[j] = true;
+
+            let (mi, mj) = (data.mass[i], data.mass[j]);
+            let x = (position(j) - position(i)).as_dvec3();
+            let v = (velocity(j) - velocity(i)).as_dvec3();
+            let centre = (position(i) * mi + position(j) * mj) / (mi + mj);
+            new_pairs.push(RegularizedPair {
+                primary: i as u32,
+                secondary: j as u32,
+                masses: [mi, mj],
+                ks: KsState::new(x, v, g * (mi + mj) as f64),
+                tidal: tidal_tensor(data, centre, [i as u32, j as u32], g),
+            });
+        }
+        self.pairs.extend(new_pairs.iter().cloned());
+        new_pairs
+    }
+    /// Advances every pair by `dt` and hands back the ones that have
+    /// separated past the release radius
+    pub fn advance(&mut self, dt: f64, gravitation_const: f32) -> Vec<RegularizedPair> {
+        let (g, accuracy) = (gravitation_const as f64, self.params.accuracy);
+        for pair in &mut self.pairs {
+            pair.advance(dt, g, accuracy);
+        }
+        let release_radius = self.params.release_radius;
+        let (released, kept) = std::mem::take(&mut self.pairs)
+            .into_iter()
+            .partition(|pair| pair.separation() > release_radius);
+        self.pairs = kept;
+        released
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn energy(pair: &RegularizedPair, gravitational_mass: f64) -> f64 {
+        let (x, v) = pair.relative_motion();
+        0.5 * v.as_dvec3().length_squared() - gravitational_mass / x.as_dvec3().length()
+    }
+
+    #[test]
+    fn isolated_binary_keeps_its_orbit() {
+        let (g, masses) = (1., [1., 1.]);
+        let gravitational_mass = g * 2.;
+        let (x, v) = (DVec3::X, DVec3::new(0., 1.2, 0.3));
+        let mut pair = RegularizedPair {
+            primary: 0,
+            secondary: 1,
+            masses,
+            ks: KsState::new(x, v, gravitational_mass),
+            tidal: DMat3::ZERO,
+        };
+        let initial_energy = energy(&pair, gravitational_mass);
+        let semi_major_axis = -gravitational_mass / (2. * initial_energy);
+        let period =
+            2. * std::f64::consts::PI * (semi_major_axis.powi(3) / gravitational_mass).sqrt();
+
+        let frames = 100;
+        for _ in 0..100 * frames {
+            pair.advance(
+                period / frames as f64,
+                g,
+                RegularizationParams::default().accuracy,
+            );
+        }
+        let relative_error = (energy(&pair, gravitational_mass) - initial_energy) / initial_energy;
+        assert!(
+            relative_error.abs() < 1e-6,
+            "energy drifted by {relative_error}"
+        );
+        // Started at apocentre, where the separation is least sensitive to
+        // the phase drift
+        let separation = pair.separation() as f64;
+        assert!(
+            (separation - x.length()).abs() < 1e-3,
+            "ended {separation} apart rather than {}",
+            x.length()
+        );
+    }
+}
@@ -0,0 +1,224 @@
+use rand::SeedableRng;
+
+use crate::prelude::*;
+
+use super::potentials::ExternalPotential;
+
+/// Circular restricted three body problem. The primaries circle their
+/// barycentre at the origin in the xy plane, and the simulation runs in the
+/// frame rotating with them, where they sit still on the x axis. Every other
+/// body is massless, so there is no initial mass function to sample
+#[derive(Builder, Debug, Clone, PartialEq)]
+#[builder(default)]
+pub struct RestrictedThreeBody {
+    pub primary_mass: f32,
+    /// Must be positive and at most the primary's mass
+    pub secondary_mass: f32,
+    pub separation: f32,
+    /// Of the primaries' potentials
+    pub softening: f32,
+    /// Test particles on circular orbits about the barycentre
+    pub disc_count: usize,
+    /// Test particles scattered about L4 and L5
+    pub trojan_count: usize,
+    /// Whether the camera turns with the primaries, otherwise the view is of
+    /// the inertial frame
+    pub co_rotating_camera: bool,
+    /// The same seed always generates the same particles
+    pub seed: u64,
+}
+
+impl Default for RestrictedThreeBody {
+    fn default() -> Self {
+        Self {
+            primary_mass: 1000.,
+            // Below the Routh limit, so L4 and L5 are stable
+            secondary_mass: 10.,
+            separation: 1.,
+            softening: 0.01,
+            disc_count: 4000,
+            trojan_count: 1000,
+            co_rotating_camera: true,
+            seed: 0,
+        }
+    }
+}
+
+/// Resolution of the grid the zero velocity curves are traced on
+const CURVE_GRID: usize = 256;
+
+impl RestrictedThreeBody {
+    /// μ = m2 / (m1 + m2)
+    pub fn mass_ratio(&self) -> f32 {
+        self.secondary_mass / (self.primary_mass + self.secondary_mass)
+    }
+    /// Ω of the primaries' orbit, and so of the frame
+    pub fn angular_velocity(&self, gravitation_const: f32) -> f32 {
+        (gravitation_const * (self.primary_mass + self.secondary_mass) / self.separation.powi(3))
+            .sqrt()
+    }
+    pub fn primary_positions(&self) -> [Vec3; 2] {
+        let mu = self.mass_ratio();
+        [
+            Vec3::X * -mu * self.separation,
+            Vec3::X * (1. - mu) * self.separation,
+        ]
+    }
+    /// The primaries as static potentials in the rotating frame
+    pub fn potentials(&self) -> Vec<ExternalPotential> {
+        self.primary_positions()
+            .into_iter()
+            .zip([self.primary_mass, self.secondary_mass])
+            .map(|(position, mass)| ExternalPotential::PointMass {
+                position,
+                mass,
+                softening: self.softening,
+            })
+            .collect()
+    }
+    /// Gravitational plus centrifugal potential in the rotating frame,
+    /// unsoftened
+    pub fn effective_potential(&self, position: Vec3, gravitation_const: f32) -> f32 {
+        let omega = self.angular_velocity(gravitation_const);
+        let [p1, p2] = self.primary_positions();
+        -gravitation_const * self.primary_mass / position.distance(p1)
+            - gravitation_const * self.secondary_mass / position.distance(p2)
+            - 0.5 * omega * omega * position.truncate().length_squared()
+    }
+    /// C = -2Φ - v², conserved along each massless orbit
+    pub fn jacobi_constant(&self, position: Vec3, velocity: Vec3, gravitation_const: f32) -> f32 {
+        -2. * self.effective_potential(position, gravitation_const) - velocity.length_squared()
+    }
+    /// L1 to L5, L1 between the primaries, L2 beyond the secondary and L3
+    /// beyond the primary
+    pub fn lagrange_points(&self, gravitation_const: f32) -> [Vec3; 5] {
+        let omega = self.angular_velocity(gravitation_const);
+        let [x1, x2] = self.primary_positions().map(|p| p.x as f64);
+        let a = self.separation as f64;
+        let (g, m1, m2) = (
+            gravitation_const as f64,
+            self.primary_mass as f64,
+            self.secondary_mass as f64,
+        );
+        // Net acceleration along the x axis, zero at the collinear points
+        let force = |x: f64| {
+            (omega as f64).powi(2) * x
+                - g * m1 * (x - x1) / (x - x1).abs().powi(3)
+                - g * m2 * (x - x2) / (x - x2).abs().powi(3)
+        };
+        let bisect = |mut low: f64, mut high: f64| {
+            let low_sign = force(low).signum();
+            for _ in 0..60 {
+                let mid = 0.5 * (low + high);
+                if force(mid).signum() == low_sign {
+                    low = mid;
+                } else {
+                    high = mid;
+                }
+            }
+            Vec3::X * (0.5 * (low + high)) as f32
+        };
+        let margin = a * 1e-6;
+        let triangle = |side: f32| {
+            vec3(
+                (0.5 - self.mass_ratio()) * self.separation,
+                side * 3_f32.sqrt() / 2. * self.separation,
+                0.,
+            )
+        };
+        [
+            bisect(x1 + margin, x2 - margin),
+            bisect(x2 + margin, x2 + 2. * a),
+            bisect(x1 - 2. * a, x1 - margin),
+            triangle(1.),
+            triangle(-1.),
+        ]
+    }
+    /// Line list of the zero velocity curves through L1, L2 and L3 in the
+    /// orbital plane, traced with marching squares
+    pub fn zero_velocity_curves(&self, gravitation_const: f32) -> Vec<[f32; 4]> {
+        let extent = 1.6 * self.separation;
+        let cell = 2. * extent / CURVE_GRID as f32;
+        let corner =
+            |i: usize, j: usize| vec3(-extent + i as f32 * cell, -extent + j as f32 * cell, 0.);
+        let jacobi: Vec<f32> = (0..=CURVE_GRID)
+            .flat_map(|j| (0..=CURVE_GRID).map(move |i| (i, j)))
+            .map(|(i, j)| -2. * self.effective_potential(corner(i, j), gravitation_const))
+            .collect();
+        let value = |i: usize, j: usize| jacobi[i + j * (CURVE_GRID + 1)];
+
+        let mut lines = Vec::new();
+        for level in self.lagrange_points(gravitation_const)[..3]
+            .iter()
+            .map(|&l| self.jacobi_constant(l, Vec3::ZERO, gravitation_const))
+        {
+            for j in 0..CURVE_GRID {
+                for i in 0..CURVE_GRID {
+                    let corners = [(i, j), (i + 1, j), (i + 1, j + 1), (i, j + 1)];
+                    // Where the level crosses each edge of the cell
+                    let crossings: Vec<Vec3> = (0..4)
+                        .filter_map(|edge| {
+                            let (a, b) = (corners[edge], corners[(edge + 1) % 4]);
+                            let (va, vb) = (value(a.0, a.1) - level, value(b.0, b.1) - level);
+                            if (va < 0.) == (vb < 0.) || !(va - vb).is_finite() {
+                                return None;
+                            }
+                            let t = va / (va - vb);
+                            Some(corner(a.0, a.1).lerp(corner(b.0, b.1), t))
+                        })
+                        .collect();
+                    for segment in crossings.chunks_exact(2) {
+                        lines.push(segment[0].extend(1.).to_array());
+                        lines.push(segment[1].extend(1.).to_array());
+                    }
+                }
+            }
+        }
+        lines
+    }
+    /// Line list of a small cross at each primary, they stay put in the
+    /// rotating frame
+    pub fn primary_markers(&self) -> Vec<[f32; 4]> {
+        let size = 0.03 * self.separation;
+        self.primary_positions()
+            .into_iter()
+            .flat_map(|p| {
+                [Vec3::X, Vec3::Y, Vec3::Z]
+                    .into_iter()
+                    .flat_map(move |axis| [p - axis * size, p + axis * size])
+            })
+            .map(|p| p.extend(1.).to_array())
+            .collect()
+    }
+    /// Positions and rotating frame velocities of the test particles
+    pub fn generate_particles(&self, gravitation_const: f32) -> (Vec<[f32; 4]>, Vec<[f32; 4]>) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(self.seed);
+        let omega = self.angular_velocity(gravitation_const);
+        let total_mass = self.primary_mass + self.secondary_mass;
+        let a = self.separation;
+
+        let mut positions = Vec::with_capacity(self.disc_count + self.trojan_count);
+        let mut velocities = Vec::with_capacity(self.disc_count + self.trojan_count);
+        for _ in 0..self.disc_count {
+            let r = rng.random_range(0.3 * a..1.8 * a);
+            let theta = rng.random_range(0.0..std::f32::consts::TAU);
+            let radial = vec3(theta.cos(), theta.sin(), 0.);
+            // Circular in the inertial frame, less the frame's rotation
+            let speed = (gravitation_const * total_mass / r).sqrt() - omega * r;
+            positions.push((radial * r).extend(1.).to_array());
+            velocities.push((Vec3::Z.cross(radial) * speed).extend(0.).to_array());
+        }
+        let [.., l4, l5] = self.lagrange_points(gravitation_const);
+        for i in 0..self.trojan_count {
+            let centre = if i % 2 == 0 { l4 } else { l5 };
+            let offset = vec3(
+                rng.random_range(-0.05..0.05),
+                rng.random_range(-0.05..0.05),
+                0.,
+            ) * a;
+            positions.push((centre + offset).extend(1.).to_array());
+            velocities.push([0.; 4]);
+        }
+        (positions, velocities)
+    }
+}
@@ -0,0 +1,294 @@
+use std::io::Write;
+
+use bytemuck::bytes_of;
+
+use super::precision::generate_kicking_shader;
+use crate::graphics::vertices::read_buffer;
+use crate::prelude::*;
+
+/// Settings for the sink bodies, see `vertices::SPECIES_SINK`
+#[derive(Builder, Debug, Clone, Copy, PartialEq)]
+#[builder(default)]
+pub struct SinkParams {
+    /// Bodies closer than this to a sink are absorbed into it
+    pub accretion_radius: f32,
+}
+
+impl Default for SinkParams {
+    fn default() -> Self {
+        Self {
+            accretion_radius: 0.02,
+        }
+    }
+}
+
+/// Mirrors the `Sinks` struct in sinks.wgsl
+#[derive(Debug, Pod, Zeroable, Copy, Clone)]
+#[repr(C)]
+struct SinkUniform {
+    accretion_radius: f32,
+    time: f32,
+    padding: [u32; 2],
+}
+
+/// A body absorbed by a sink, mirrors `AccretionEvent` in sinks.wgsl
+#[derive(Debug, Pod, Zeroable, Copy, Clone, PartialEq)]
+#[repr(C)]
+pub struct AccretionEvent {
+    /// Simulation time of the step it was absorbed in
+    pub time: f32,
+    pub sink: u32,
+    pub body: u32,
+    pub mass: f32,
+}
+
+/// Every accretion so far, in the order they happened
+#[derive(Debug, Default, Clone)]
+pub struct AccretionLog {
+    pub events: Vec<AccretionEvent>,
+}
+
+impl AccretionLog {
+    pub fn total_mass(&self) -> f64 {
+        self.events.iter().map(|e| e.mass as f64).sum()
+    }
+    /// Accreted mass per unit time, summed over every sink, in bins of
+    /// `bin_width` from time zero to `end_time`. Each entry is the bin's
+    /// start time and rate
+    pub fn rate_series(&self, bin_width: f64, end_time: f64) -> Vec<(f64, f64)> {
+        let bins = (end_time / bin_width).ceil().max(1.) as usize;
+        let mut accreted = vec![0.; bins];
+        for event in &self.events {
+            let bin = ((event.time as f64 / bin_width) as usize).min(bins - 1);
+            accreted[bin] += event.mass as f64;
+        }
+        accreted
+            .into_iter()
+            .enumerate()
+            .map(|(i, mass)| (i as f64 * bin_width, mass / bin_width))
+            .collect()
+    }
+    /// Writes one `time sink body mass` line per event
+    pub fn write(&self, path: impl AsRef<std::path::Path>) -> Result<()> {
+        let path = path.as_ref();
+        let file = std::fs::File::create(path)
+            .with_context(|| format!("Failed to create accretion log {:?}", path))?;
+        let mut out = std::io::BufWriter::new(file);
+        writeln!(out, "# time sink body mass")?;
+        for e in &self.events {
+            writeln!(out, "{} {} {} {}", e.time, e.sink, e.body, e.mass)?;
+        }
+        out.flush()
+            .with_context(|| format!("Failed to write accretion log {:?}", path))?;
+        Ok(())
+    }
+    /// Writes one `time rate` line per bin of `rate_series`
+    pub fn write_rate_series(
+        &self,
+        path: impl AsRef<std::path::Path>,
+        bin_width: f64,
+        end_time: f64,
+    ) -> Result<()> {
+        let path = path.as_ref();
+        let file = std::fs::File::create(path)
+            .with_context(|| format!("Failed to create accretion rate file {:?}", path))?;
+        let mut out = std::io::BufWriter::new(file);
+        writeln!(out, "# time rate")?;
+        for (time, rate) in self.rate_series(bin_width, end_time) {
+            writeln!(out, "{} {}", time, rate)?;
+        }
+        out.flush()
+            .with_context(|| format!("Failed to write accretion rate file {:?}", path))?;
+        Ok(())
+    }
+}
+
+/// GPU resources for the sinks. Like the particle mesh, every pass reads the
+/// body buffers and simulation uniform through groups 0 and 1, and the
+/// accretion state is bound at group 2
+#[derive(Debug)]
+pub struct Sinks {
+    params: SinkParams,
+    uniform: wgpu::Buffer,
+    event_count: wgpu::Buffer,
+    events: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    claim_pipeline: wgpu::ComputePipeline,
+    merge_pipeline: wgpu::ComputePipeline,
+    remove_pipeline: wgpu::ComputePipeline,
+}
+
+impl Sinks {
+    /// Entries of group 2, the uniform and the accretion events
+    pub fn generate_bind_group_layout_entries() -> Vec<wgpu::BindGroupLayoutEntry> {
+        let entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: if binding == 0 {
+                    wgpu::BufferBindingType::Uniform
+                } else {
+                    wgpu::BufferBindingType::Storage { read_only: false }
+                },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        (0..4).map(entry).collect()
+    }
+    fn generate_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Sinks"),
+            entries: &Self::generate_bind_group_layout_entries(),
+        })
+    }
+    fn uniform(&self, time: f64) -> SinkUniform {
+        SinkUniform {
+            accretion_radius: self.params.accretion_radius,
+            time: time as f32,
+            padding: [0; 2],
+        }
+    }
+    /// `body_layout` and `simulation_layout` are the layouts of groups 0 and 1
+    /// of the other compute pipelines
+    pub fn new(
+        device: &wgpu::Device,
+        body_layout: &wgpu::BindGroupLayout,
+        simulation_layout: &wgpu::BindGroupLayout,
+        params: SinkParams,
+        body_count: usize,
+    ) -> Self {
+        let uniform = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Sink Uniform"),
+            size: size_of::<SinkUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        // Every body starts unclaimed
+        let targets = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Accretion Targets"),
+            contents: bytemuck::cast_slice(&vec![u32::MAX; body_count]),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        let event_count = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Accretion Event Count"),
+            size: size_of::<u32>() as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let events = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Accretion Events"),
+            size: (body_count.max(1) * size_of::<AccretionEvent>()) as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+
+        let sink_layout = Self::generate_bind_group_layout(device);
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Sinks"),
+            layout: &sink_layout,
+            entries: &[&uniform, &targets, &event_count, &events]
+                .iter()
+                .enumerate()
+                .map(|(binding, buffer)| wgpu::BindGroupEntry {
+                    binding: binding as u32,
+                    resource: buffer.as_entire_binding(),
+                })
+                .collect::<Vec<_>>(),
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Sinks"),
+            bind_group_layouts: &[body_layout, simulation_layout, &sink_layout],
+            push_constant_ranges: &[],
+        });
+        let module = generate_kicking_shader(
+            device,
+            "sinks.wgsl",
+            include_str!("../../shaders/sinks.wgsl"),
+        );
+        let pipeline = |entry_point: &str| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(entry_point),
+                layout: Some(&layout),
+                module: &module,
+                entry_point: Some(entry_point),
+                compilation_options: Default::default(),
+                cache: None,
+            })
+        };
+
+        Self {
+            claim_pipeline: pipeline("claim_bodies"),
+            merge_pipeline: pipeline("merge_bodies"),
+            remove_pipeline: pipeline("remove_bodies"),
+            params,
+            uniform,
+            event_count,
+            events,
+            bind_group,
+        }
+    }
+    pub fn params(&self) -> &SinkParams {
+        &self.params
+    }
+    /// Updates the settings in place, the accretion log is kept
+    pub fn set_params(&mut self, params: SinkParams) {
+        self.params = params;
+    }
+    /// Records the passes that absorb every body inside a sink's accretion
+    /// radius. `time` is the simulation time the events are logged with
+    pub fn encode(
+        &self,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        body_bind_group: &wgpu::BindGroup,
+        simulation_bind_group: &wgpu::BindGroup,
+        body_count: u32,
+        time: f64,
+    ) {
+        queue.write_buffer(&self.uniform, 0, bytes_of(&self.uniform(time)));
+
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Sinks"),
+            timestamp_writes: None,
+        });
+        pass.set_bind_group(0, body_bind_group, &[]);
+        pass.set_bind_group(1, simulation_bind_group, &[]);
+        pass.set_bind_group(2, &self.bind_group, &[]);
+
+        for pipeline in [
+            &self.claim_pipeline,
+            &self.merge_pipeline,
+            &self.remove_pipeline,
+        ] {
+            pass.set_pipeline(pipeline);
+            pass.dispatch_workgroups(body_count.div_ceil(64), 1, 1);
+        }
+    }
+    /// Copies the events back from the gpu, stalling like
+    /// `BodyData::read_back`
+    pub fn read_log(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> Result<AccretionLog> {
+        let readable = |buffer: &wgpu::Buffer| {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: None,
+                size: buffer.size(),
+                usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            })
+        };
+        let (count, events) = (readable(&self.event_count), readable(&self.events));
+        let mut encoder = device.create_command_encoder(&Default::default());
+        encoder.copy_buffer_to_buffer(&self.event_count, 0, &count, 0, count.size());
+        encoder.copy_buffer_to_buffer(&self.events, 0, &events, 0, events.size());
+        queue.submit(Some(encoder.finish()));
+
+        let count = read_buffer::<u32>(&count, device)?[0] as usize;
+        let mut events = read_buffer::<AccretionEvent>(&events, device)?;
+        events.truncate(count);
+        // Sinks append concurrently, so only the steps are in order
+        events.sort_by(|a, b| a.time.total_cmp(&b.time).then(a.body.cmp(&b.body)));
+        Ok(AccretionLog { events })
+    }
+}
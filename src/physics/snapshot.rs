@@ -0,0 +1,49 @@
+use std::io::Write;
+
+use crate::graphics::vertices::UnbufferedBodyData;
+use crate::prelude::*;
+
+/// The state of every body at a point in the simulation
+#[derive(Debug)]
+pub struct Snapshot {
+    pub time: f64,
+    /// None unless the run is expanding
+    pub scale_factor: Option<f64>,
+    pub data: UnbufferedBodyData,
+}
+
+impl Snapshot {
+    /// Writes the snapshot as plain text, a commented header followed by one
+    /// `x y z vx vy vz mass species` line per body
+    pub fn write(&self, path: impl AsRef<std::path::Path>) -> Result<()> {
+        let path = path.as_ref();
+        let file = std::fs::File::create(path)
+            .with_context(|| format!("Failed to create snapshot file {:?}", path))?;
+        let mut out = std::io::BufWriter::new(file);
+
+        writeln!(out, "# time {}", self.time)?;
+        if let Some(a) = self.scale_factor {
+            writeln!(out, "# scale_factor {}", a)?;
+            writeln!(out, "# redshift {}", 1. / a - 1.)?;
+        }
+        writeln!(out, "# bodies {}", self.data.mass.len())?;
+        writeln!(out, "# x y z vx vy vz mass species")?;
+        for (((p, v), m), s) in self
+            .data
+            .positions
+            .iter()
+            .zip(self.data.velocities.iter())
+            .zip(self.data.mass.iter())
+            .zip(self.data.species.iter())
+        {
+            writeln!(
+                out,
+                "{} {} {} {} {} {} {} {}",
+                p[0], p[1], p[2], v[0], v[1], v[2], m, s
+            )?;
+        }
+        out.flush()
+            .with_context(|| format!("Failed to write snapshot file {:?}", path))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,283 @@
+use bytemuck::bytes_of;
+
+use super::precision::generate_kicking_shader;
+use crate::graphics::vertices::encode_swap;
+use crate::prelude::*;
+
+/// Buckets in the neighbour search's spatial hash, must match
+/// `HASH_TABLE_SIZE` in sph.wgsl
+pub const HASH_TABLE_SIZE: u32 = 32768;
+/// Bodies each bucket can hold, must match `CELL_CAPACITY` in sph.wgsl
+pub const CELL_CAPACITY: u32 = 32;
+
+/// Relates the pressure of the gas to its density and internal energy
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EquationOfState {
+    /// P = c² ρ, the internal energy is left constant
+    Isothermal { sound_speed: f32 },
+    /// P = (γ - 1) ρ u, the internal energy evolves with the compression and
+    /// viscous heating
+    Adiabatic { adiabatic_index: f32 },
+}
+
+impl Default for EquationOfState {
+    fn default() -> Self {
+        Self::Isothermal { sound_speed: 0.05 }
+    }
+}
+
+/// Settings for the gas bodies, see `vertices::SPECIES_GAS`
+#[derive(Builder, Debug, Clone, Copy, PartialEq)]
+#[builder(default)]
+pub struct HydroParams {
+    /// Fixed for every body, the kernel reaches out to twice this
+    pub smoothing_length: f32,
+    pub equation_of_state: EquationOfState,
+    /// Monaghan viscosity coefficients
+    pub viscosity_alpha: f32,
+    pub viscosity_beta: f32,
+    /// Specific internal energy every gas body starts with, only used by the
+    /// adiabatic equation of state
+    pub initial_internal_energy: f32,
+}
+
+impl Default for HydroParams {
+    fn default() -> Self {
+        Self {
+            smoothing_length: 0.02,
+            equation_of_state: EquationOfState::default(),
+            viscosity_alpha: 1.,
+            viscosity_beta: 2.,
+            initial_internal_energy: 1e-3,
+        }
+    }
+}
+
+/// Mirrors the `Hydro` struct in sph.wgsl
+#[derive(Debug, Pod, Zeroable, Copy, Clone)]
+#[repr(C)]
+struct HydroUniform {
+    smoothing_length: f32,
+    adiabatic_index: f32,
+    isothermal_sound_speed: f32,
+    equation_of_state: u32,
+    viscosity_alpha: f32,
+    viscosity_beta: f32,
+    padding: [u32; 2],
+}
+
+impl From<&HydroParams> for HydroUniform {
+    fn from(params: &HydroParams) -> Self {
+        let (equation_of_state, adiabatic_index, isothermal_sound_speed) =
+            match params.equation_of_state {
+                EquationOfState::Isothermal { sound_speed } => (0, 1., sound_speed),
+                EquationOfState::Adiabatic { adiabatic_index } => (1, adiabatic_index, 0.),
+            };
+        Self {
+            smoothing_length: params.smoothing_length,
+            adiabatic_index,
+            isothermal_sound_speed,
+            equation_of_state,
+            viscosity_alpha: params.viscosity_alpha,
+            viscosity_beta: params.viscosity_beta,
+            padding: [0; 2],
+        }
+    }
+}
+
+/// GPU resources for the gas bodies. Like the particle mesh, every pass reads
+/// the body buffers and simulation uniform through groups 0 and 1, and the
+/// hydro state is bound at group 2
+#[derive(Debug)]
+pub struct Hydrodynamics {
+    params: HydroParams,
+    uniform: wgpu::Buffer,
+    density: wgpu::Buffer,
+    pressure: wgpu::Buffer,
+    internal_energy: wgpu::Buffer,
+    accelerations: wgpu::Buffer,
+    cell_counts: wgpu::Buffer,
+    cell_bodies: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    insert_pipeline: wgpu::ComputePipeline,
+    density_pipeline: wgpu::ComputePipeline,
+    force_pipeline: wgpu::ComputePipeline,
+    apply_pipeline: wgpu::ComputePipeline,
+}
+
+impl Hydrodynamics {
+    /// Entries of group 2, the uniform, the gas state and the neighbour grid
+    pub fn generate_bind_group_layout_entries() -> Vec<wgpu::BindGroupLayoutEntry> {
+        let entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: if binding == 0 {
+                    wgpu::BufferBindingType::Uniform
+                } else {
+                    wgpu::BufferBindingType::Storage { read_only: false }
+                },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        (0..7).map(entry).collect()
+    }
+    fn generate_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Hydrodynamics"),
+            entries: &Self::generate_bind_group_layout_entries(),
+        })
+    }
+    /// `body_layout` and `simulation_layout` are the layouts of groups 0 and 1
+    /// of the other compute pipelines
+    pub fn new(
+        device: &wgpu::Device,
+        body_layout: &wgpu::BindGroupLayout,
+        simulation_layout: &wgpu::BindGroupLayout,
+        params: HydroParams,
+        body_count: usize,
+    ) -> Self {
+        let uniform = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Hydro Uniform"),
+            contents: bytes_of(&HydroUniform::from(&params)),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let per_body = |label, size: usize| {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size: (body_count * size) as u64,
+                usage: wgpu::BufferUsages::STORAGE,
+                mapped_at_creation: false,
+            })
+        };
+        let density = per_body("Gas Density", size_of::<f32>());
+        let pressure = per_body("Gas Pressure", size_of::<f32>());
+        let accelerations = per_body("Gas Accelerations", size_of::<[f32; 4]>());
+        let internal_energy = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Gas Internal Energy"),
+            contents: bytemuck::cast_slice(&vec![params.initial_internal_energy; body_count]),
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::COPY_SRC,
+        });
+        let cell_counts = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Gas Cell Counts"),
+            size: HASH_TABLE_SIZE as u64 * size_of::<u32>() as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let cell_bodies = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Gas Cell Bodies"),
+            size: (HASH_TABLE_SIZE * CELL_CAPACITY) as u64 * size_of::<u32>() as u64,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+
+        let hydro_layout = Self::generate_bind_group_layout(device);
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Hydrodynamics"),
+            layout: &hydro_layout,
+            entries: &[
+                &uniform,
+                &density,
+                &pressure,
+                &internal_energy,
+                &accelerations,
+                &cell_counts,
+                &cell_bodies,
+            ]
+            .iter()
+            .enumerate()
+            .map(|(binding, buffer)| wgpu::BindGroupEntry {
+                binding: binding as u32,
+                resource: buffer.as_entire_binding(),
+            })
+            .collect::<Vec<_>>(),
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Hydrodynamics"),
+            bind_group_layouts: &[body_layout, simulation_layout, &hydro_layout],
+            push_constant_ranges: &[],
+        });
+        let module =
+            generate_kicking_shader(device, "sph.wgsl", include_str!("../../shaders/sph.wgsl"));
+        let pipeline = |entry_point: &str| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(entry_point),
+                layout: Some(&layout),
+                module: &module,
+                entry_point: Some(entry_point),
+                compilation_options: Default::default(),
+                cache: None,
+            })
+        };
+
+        Self {
+            insert_pipeline: pipeline("insert_bodies"),
+            density_pipeline: pipeline("compute_density"),
+            force_pipeline: pipeline("compute_forces"),
+            apply_pipeline: pipeline("apply_forces"),
+            params,
+            uniform,
+            density,
+            pressure,
+            internal_energy,
+            accelerations,
+            cell_counts,
+            cell_bodies,
+            bind_group,
+        }
+    }
+    pub fn params(&self) -> &HydroParams {
+        &self.params
+    }
+    /// Updates the settings in place, the internal energies are kept
+    pub fn set_params(&mut self, queue: &wgpu::Queue, params: HydroParams) {
+        queue.write_buffer(&self.uniform, 0, bytes_of(&HydroUniform::from(&params)));
+        self.params = params;
+    }
+    /// Swaps the internal energies of two bodies along with their slots, the
+    /// rest of the gas state is rebuilt every step
+    pub fn encode_swap(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        scratch: &wgpu::Buffer,
+        a: u32,
+        b: u32,
+    ) {
+        let size = size_of::<f32>() as u64;
+        encode_swap(encoder, &self.internal_energy, scratch, size, a, b);
+    }
+    /// Records the passes that add the pressure and viscous forces to every
+    /// gas body's velocity
+    pub fn encode(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        body_bind_group: &wgpu::BindGroup,
+        simulation_bind_group: &wgpu::BindGroup,
+        body_count: u32,
+    ) {
+        encoder.clear_buffer(&self.cell_counts, 0, None);
+
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Hydrodynamics"),
+            timestamp_writes: None,
+        });
+        pass.set_bind_group(0, body_bind_group, &[]);
+        pass.set_bind_group(1, simulation_bind_group, &[]);
+        pass.set_bind_group(2, &self.bind_group, &[]);
+
+        for pipeline in [
+            &self.insert_pipeline,
+            &self.density_pipeline,
+            &self.force_pipeline,
+            &self.apply_pipeline,
+        ] {
+            pass.set_pipeline(pipeline);
+            pass.dispatch_workgroups(body_count.div_ceil(64), 1, 1);
+        }
+    }
+}
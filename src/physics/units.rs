@@ -0,0 +1,256 @@
+use crate::prelude::*;
+
+/// Newton's constant in SI units
+pub const GRAVITATION_CONST_SI: f64 = 6.6743e-11;
+
+pub const METRE: f64 = 1.;
+pub const ASTRONOMICAL_UNIT: f64 = 1.495978707e11;
+pub const PARSEC: f64 = 3.085_677_581_491_367e16;
+pub const KILOPARSEC: f64 = 1e3 * PARSEC;
+pub const LIGHT_YEAR: f64 = 9.4607304725808e15;
+
+pub const KILOGRAM: f64 = 1.;
+pub const SOLAR_MASS: f64 = 1.98841e30;
+
+pub const SECOND: f64 = 1.;
+pub const YEAR: f64 = 3.15576e7;
+pub const MEGAYEAR: f64 = 1e6 * YEAR;
+pub const GIGAYEAR: f64 = 1e9 * YEAR;
+
+/// A named unit and its size in SI
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Unit {
+    pub name: &'static str,
+    pub si: f64,
+}
+
+impl Unit {
+    pub const fn new(name: &'static str, si: f64) -> Self {
+        Self { name, si }
+    }
+}
+
+/// Powers of length, mass and time
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Dimension {
+    pub length: i32,
+    pub mass: i32,
+    pub time: i32,
+}
+
+impl Dimension {
+    pub const NONE: Self = Self::new(0, 0, 0);
+    pub const LENGTH: Self = Self::new(1, 0, 0);
+    pub const MASS: Self = Self::new(0, 1, 0);
+    pub const TIME: Self = Self::new(0, 0, 1);
+    pub const VELOCITY: Self = Self::new(1, 0, -1);
+    pub const ACCELERATION: Self = Self::new(1, 0, -2);
+
+    pub const fn new(length: i32, mass: i32, time: i32) -> Self {
+        Self { length, mass, time }
+    }
+    fn scale(self, power: i32) -> Self {
+        Self::new(self.length * power, self.mass * power, self.time * power)
+    }
+    fn add(self, other: Self) -> Self {
+        Self::new(
+            self.length + other.length,
+            self.mass + other.mass,
+            self.time + other.time,
+        )
+    }
+}
+
+/// Units quantities can be written in, with their SI size and dimension
+const KNOWN_UNITS: &[(&str, f64, Dimension)] = &[
+    ("m", METRE, Dimension::LENGTH),
+    ("km", 1e3, Dimension::LENGTH),
+    ("AU", ASTRONOMICAL_UNIT, Dimension::LENGTH),
+    ("au", ASTRONOMICAL_UNIT, Dimension::LENGTH),
+    ("ly", LIGHT_YEAR, Dimension::LENGTH),
+    ("pc", PARSEC, Dimension::LENGTH),
+    ("kpc", KILOPARSEC, Dimension::LENGTH),
+    ("Mpc", 1e6 * PARSEC, Dimension::LENGTH),
+    ("g", 1e-3, Dimension::MASS),
+    ("kg", KILOGRAM, Dimension::MASS),
+    ("Msun", SOLAR_MASS, Dimension::MASS),
+    ("s", SECOND, Dimension::TIME),
+    ("day", 86400., Dimension::TIME),
+    ("yr", YEAR, Dimension::TIME),
+    ("Myr", MEGAYEAR, Dimension::TIME),
+    ("Gyr", GIGAYEAR, Dimension::TIME),
+];
+
+/// A value with units, e.g. parsed from "8.5 kpc" or "220 km/s"
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quantity {
+    /// The value in SI units
+    pub si: f64,
+    pub dimension: Dimension,
+}
+
+impl std::str::FromStr for Quantity {
+    type Err = anyhow::Error;
+
+    /// A number followed by units multiplied with `*` or divided with `/`,
+    /// each optionally raised to an integer power with `^`. A bare number is
+    /// dimensionless
+    fn from_str(text: &str) -> Result<Self> {
+        let text = text.trim();
+        let split = text.find(|c: char| c.is_whitespace()).unwrap_or(text.len());
+        let (number, units) = text.split_at(split);
+        let value: f64 = number
+            .parse()
+            .with_context(|| format!("Invalid number {:?} in {:?}", number, text))?;
+
+        let mut quantity = Quantity {
+            si: value,
+            dimension: Dimension::NONE,
+        };
+        let units = units.trim();
+        if units.is_empty() {
+            return Ok(quantity);
+        }
+        // Every factor after a / is divided
+        let mut sign = 1;
+        for (i, factors) in units.split('/').enumerate() {
+            if i > 0 {
+                sign = -1;
+            }
+            for factor in factors.split('*').map(str::trim) {
+                let (name, power) = match factor.split_once('^') {
+                    Some((name, power)) => (
+                        name.trim(),
+                        power
+                            .trim()
+                            .parse::<i32>()
+                            .with_context(|| format!("Invalid power in {:?}", factor))?,
+                    ),
+                    None => (factor, 1),
+                };
+                let Some(&(_, si, dimension)) = KNOWN_UNITS.iter().find(|(n, ..)| *n == name)
+                else {
+                    bail!("Unknown unit {:?} in {:?}", name, text);
+                };
+                quantity.si *= si.powi(sign * power);
+                quantity.dimension = quantity.dimension.add(dimension.scale(sign * power));
+            }
+        }
+        Ok(quantity)
+    }
+}
+
+/// The length, mass and time units the simulation's numbers are in. G is
+/// derived from them, so it is never set on its own
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UnitSystem {
+    pub length: Unit,
+    pub mass: Unit,
+    pub time: Unit,
+}
+
+impl UnitSystem {
+    /// AU, solar masses and years, G = 4π²
+    pub const ASTRONOMICAL: Self = Self {
+        length: Unit::new("AU", ASTRONOMICAL_UNIT),
+        mass: Unit::new("Msun", SOLAR_MASS),
+        time: Unit::new("yr", YEAR),
+    };
+    /// kpc, 10¹⁰ solar masses and Gyr, G ≈ 4.5e4
+    pub const GALACTIC: Self = Self {
+        length: Unit::new("kpc", KILOPARSEC),
+        mass: Unit::new("1e10 Msun", 1e10 * SOLAR_MASS),
+        time: Unit::new("Gyr", GIGAYEAR),
+    };
+    pub const SI: Self = Self {
+        length: Unit::new("m", METRE),
+        mass: Unit::new("kg", KILOGRAM),
+        time: Unit::new("s", SECOND),
+    };
+
+    /// Picks the mass unit that gives the wanted G
+    pub fn with_gravitation_const(length: Unit, time: Unit, gravitation_const: f64) -> Self {
+        let mass = gravitation_const * length.si.powi(3) / (GRAVITATION_CONST_SI * time.si.powi(2));
+        Self {
+            length,
+            mass: Unit::new("mass units", mass),
+            time,
+        }
+    }
+    pub fn gravitation_const(&self) -> f64 {
+        GRAVITATION_CONST_SI * self.mass.si * self.time.si.powi(2) / self.length.si.powi(3)
+    }
+    /// Size of one simulation unit of `dimension` in SI
+    fn si_scale(&self, dimension: Dimension) -> f64 {
+        self.length.si.powi(dimension.length)
+            * self.mass.si.powi(dimension.mass)
+            * self.time.si.powi(dimension.time)
+    }
+    /// Converts to simulation units, checking the quantity has the expected
+    /// dimension
+    pub fn convert(&self, quantity: Quantity, expected: Dimension) -> Result<f64> {
+        if quantity.dimension != expected {
+            bail!(
+                "Expected a quantity with dimension {:?}, got {:?}",
+                expected,
+                quantity.dimension
+            );
+        }
+        Ok(quantity.si / self.si_scale(expected))
+    }
+    /// Parses a quantity like "8.5 kpc" into simulation units. A bare number
+    /// is taken to already be in simulation units
+    pub fn parse(&self, text: &str, expected: Dimension) -> Result<f64> {
+        let quantity: Quantity = text.parse()?;
+        if quantity.dimension == Dimension::NONE && expected != Dimension::NONE {
+            return Ok(quantity.si);
+        }
+        self.convert(quantity, expected)
+    }
+    pub fn format_time(&self, time: f64) -> String {
+        format!("{:.3} {}", time, self.time.name)
+    }
+    pub fn format_length(&self, length: f64) -> String {
+        format!("{:.3} {}", length, self.length.name)
+    }
+}
+
+impl Default for UnitSystem {
+    /// kpc and Gyr, with the mass unit picked so G = 6e-3, which the default
+    /// initial conditions are tuned for
+    fn default() -> Self {
+        Self::with_gravitation_const(
+            Unit::new("kpc", KILOPARSEC),
+            Unit::new("Gyr", GIGAYEAR),
+            6e-3,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn derived_gravitation_consts() {
+        let astronomical = UnitSystem::ASTRONOMICAL.gravitation_const();
+        let four_pi_squared = 4. * std::f64::consts::PI.powi(2);
+        assert!((astronomical / four_pi_squared - 1.).abs() < 1e-3);
+        let galactic = UnitSystem::GALACTIC.gravitation_const();
+        assert!((galactic / 4.5e4 - 1.).abs() < 1e-2, "G = {galactic}");
+    }
+
+    #[test]
+    fn parses_velocities() {
+        let quantity: Quantity = "220 km/s".parse().unwrap();
+        assert_eq!(quantity.dimension, Dimension::VELOCITY);
+        assert!((quantity.si - 2.2e5).abs() < 1e-6);
+        // About 225 kpc/Gyr
+        let velocity = UnitSystem::GALACTIC
+            .parse("220 km/s", Dimension::VELOCITY)
+            .unwrap();
+        assert!((velocity - 225.).abs() < 1., "{velocity} kpc/Gyr");
+        assert!("220 km/s^2".parse::<Quantity>().unwrap().dimension == Dimension::ACCELERATION);
+        assert!("220 furlongs".parse::<Quantity>().is_err());
+    }
+}
@@ -0,0 +1,261 @@
+use rand::SeedableRng;
+
+use crate::prelude::*;
+
+use super::cosmology::Cosmology;
+use super::fft::{fft_3d, Complex};
+use super::imf::InitialMassFunction;
+
+/// Linear matter power spectrum P(k) at the initial redshift, with k in
+/// inverse simulation length units
+#[derive(Debug, Clone, PartialEq)]
+pub enum PowerSpectrum {
+    /// P(k) = amplitude * k^index
+    PowerLaw { amplitude: f64, index: f64 },
+    /// (k, P) pairs sorted by k, interpolated in log-log space and clamped to
+    /// the end values outside of the table
+    Tabulated(Vec<(f64, f64)>),
+}
+
+impl PowerSpectrum {
+    /// Reads a table of whitespace separated `k P` pairs, one per line. Blank
+    /// lines and lines starting with # are skipped
+    pub fn from_file(path: impl AsRef<std::path::Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read power spectrum {:?}", path))?;
+        let mut table = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let values: Vec<f64> = line
+                .split_whitespace()
+                .map(str::parse)
+                .collect::<std::result::Result<_, _>>()
+                .with_context(|| format!("Invalid number on line {}", number + 1))?;
+            let [k, p] = values[..] else {
+                bail!(
+                    "Expected 2 columns on line {}, found {}",
+                    number + 1,
+                    values.len()
+                );
+            };
+            if k <= 0. || p < 0. {
+                bail!(
+                    "k must be positive and P non negative on line {}",
+                    number + 1
+                );
+            }
+            table.push((k, p));
+        }
+        if table.is_empty() {
+            bail!("Power spectrum {:?} has no entries", path);
+        }
+        table.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Ok(Self::Tabulated(table))
+    }
+    pub fn evaluate(&self, k: f64) -> f64 {
+        match self {
+            Self::PowerLaw { amplitude, index } => amplitude * k.powf(*index),
+            Self::Tabulated(table) => {
+                let upper = table.partition_point(|(tk, _)| *tk < k);
+                if upper == 0 {
+                    return table[0].1;
+                }
+                if upper == table.len() {
+                    return table[table.len() - 1].1;
+                }
+                let (k0, p0) = table[upper - 1];
+                let (k1, p1) = table[upper];
+                if p0 <= 0. || p1 <= 0. {
+                    // Can't interpolate a zero in log space
+                    return p0 + (p1 - p0) * (k - k0) / (k1 - k0);
+                }
+                let t = (k / k0).ln() / (k1 / k0).ln();
+                (p0.ln() + t * (p1 / p0).ln()).exp()
+            }
+        }
+    }
+}
+
+#[derive(Builder, Debug, Clone)]
+pub struct ZeldovichParams {
+    /// Particles along each side of the lattice, must be a power of two
+    pub lattice_size: usize,
+    pub box_size: f32,
+    pub power_spectrum: PowerSpectrum,
+    #[builder(default)]
+    pub cosmology: Cosmology,
+    /// The same seed always generates the same field
+    #[builder(default = "0")]
+    pub seed: u64,
+    /// Spreads the particle masses out with this distribution, rescaled to
+    /// keep the mean density. None gives every particle the same mass
+    #[builder(default)]
+    pub mass_function: Option<InitialMassFunction>,
+}
+
+/// Positions and velocities displaced from a lattice filling the periodic box
+/// [-L/2, L/2)³, and the mass of every particle
+pub struct ZeldovichField {
+    pub positions: Vec<[f32; 4]>,
+    pub velocities: Vec<[f32; 4]>,
+    pub masses: Vec<f32>,
+}
+
+impl ZeldovichParams {
+    /// Linear growth rate f = dlnD/dlna, using the Ωm(a)^0.55 approximation
+    fn growth_rate(&self, scale_factor: f64) -> f64 {
+        let c = &self.cosmology;
+        let h = c.hubble(scale_factor) / c.hubble_const;
+        let omega_matter = c.omega_matter / (scale_factor.powi(3) * h * h);
+        omega_matter.powf(0.55)
+    }
+    /// Unit gaussian white noise on the lattice, from Box-Muller
+    fn white_noise(&self) -> Vec<Complex> {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(self.seed);
+        (0..self.lattice_size.pow(3))
+            .map(|_| {
+                let u1: f64 = rng.random_range(f64::EPSILON..1.);
+                let u2: f64 = rng.random_range(0. ..1.);
+                let r = (-2. * u1.ln()).sqrt();
+                Complex::new(r * (2. * std::f64::consts::PI * u2).cos(), 0.)
+            })
+            .collect()
+    }
+    /// The comoving mass per particle such that the mean density matches the
+    /// cosmology's matter density, 4πGρ = 3/2 H0² Ωm
+    fn particle_mass(&self, gravitation_constant: f64) -> f64 {
+        let c = &self.cosmology;
+        let mean_density = 3. * c.hubble_const.powi(2) * c.omega_matter
+            / (8. * std::f64::consts::PI * gravitation_constant);
+        mean_density * (self.box_size as f64).powi(3) / self.lattice_size.pow(3) as f64
+    }
+    pub fn generate(&self, gravitation_constant: f32) -> Result<ZeldovichField> {
+        let n = self.lattice_size;
+        if !n.is_power_of_two() {
+            bail!("Lattice size must be a power of two, got {}", n);
+        }
+        let box_size = self.box_size as f64;
+        let cells = n.pow(3);
+
+        // White noise has <|W_k|²> = N³, rescale so the discrete field has
+        // the variance of the continuous spectrum, <|δ_k|²> = N⁶ P(k) / L³
+        let mut delta = self.white_noise();
+        fft_3d(&mut delta, n, false);
+
+        let mut displacement = [
+            vec![Complex::ZERO; cells],
+            vec![Complex::ZERO; cells],
+            vec![Complex::ZERO; cells],
+        ];
+        let frequency = |i: usize| {
+            if i < n / 2 {
+                i as f64
+            } else {
+                i as f64 - n as f64
+            }
+        };
+        for (index, d) in delta.iter().enumerate() {
+            let i = [index % n, (index / n) % n, index / (n * n)];
+            // The nyquist planes have no partner to keep the field real
+            if i.contains(&(n / 2)) {
+                continue;
+            }
+            let k = i.map(|i| frequency(i) * 2. * std::f64::consts::PI / box_size);
+            let k2 = k[0] * k[0] + k[1] * k[1] + k[2] * k[2];
+            if k2 == 0. {
+                continue;
+            }
+            let amplitude =
+                (cells as f64 * self.power_spectrum.evaluate(k2.sqrt()) / box_size.powi(3)).sqrt();
+            // ψ_k = i k δ_k / k², so that δ = -∇·ψ
+            let d = d.scale(amplitude / k2).rotate();
+            for axis in 0..3 {
+                displacement[axis][index] = d.scale(k[axis]);
+            }
+        }
+        for component in &mut displacement {
+            fft_3d(component, n, true);
+        }
+
+        let a = self.cosmology.initial_scale_factor();
+        let velocity_scale = self.growth_rate(a) * self.cosmology.hubble(a);
+        let spacing = box_size / n as f64;
+
+        let mut positions = Vec::with_capacity(cells);
+        let mut velocities = Vec::with_capacity(cells);
+        for index in 0..cells {
+            let i = [index % n, (index / n) % n, index / (n * n)];
+            let psi = displacement.each_ref().map(|c| c[index].re / cells as f64);
+            let p = std::array::from_fn::<_, 3, _>(|axis| {
+                let q = (i[axis] as f64 + 0.5) * spacing - box_size / 2.;
+                let x = q + psi[axis];
+                // Wrap back into the box
+                (x - box_size * (x / box_size + 0.5).floor()) as f32
+            });
+            positions.push([p[0], p[1], p[2], 1.]);
+            velocities.push([
+                (psi[0] * velocity_scale) as f32,
+                (psi[1] * velocity_scale) as f32,
+                (psi[2] * velocity_scale) as f32,
+                0.,
+            ]);
+        }
+
+        let particle_mass = self.particle_mass(gravitation_constant as f64) as f32;
+        let masses = match self.mass_function {
+            Some(mass_function) => {
+                // Seeded apart from the noise so the field doesn't change
+                let mut rng = rand::rngs::StdRng::seed_from_u64(self.seed.wrapping_add(1));
+                let sampled = mass_function
+                    .sample(cells, &mut rng)
+                    .with_context(|| "Failed to sample particle masses")?;
+                let mean = sampled.iter().map(|&m| m as f64).sum::<f64>() / cells as f64;
+                sampled
+                    .into_iter()
+                    .map(|m| (m as f64 / mean) as f32 * particle_mass)
+                    .collect()
+            }
+            None => vec![particle_mass; cells],
+        };
+
+        Ok(ZeldovichField {
+            positions,
+            velocities,
+            masses,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(seed: u64) -> ZeldovichParams {
+        ZeldovichParamsBuilder::default()
+            .lattice_size(8)
+            .box_size(10.)
+            .power_spectrum(PowerSpectrum::PowerLaw {
+                amplitude: 1e-3,
+                index: -2.,
+            })
+            .seed(seed)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn seed_fixes_the_field() {
+        let first = params(7).generate(1.).unwrap();
+        let again = params(7).generate(1.).unwrap();
+        assert_eq!(first.positions, again.positions);
+        assert_eq!(first.velocities, again.velocities);
+        assert_eq!(first.masses, again.masses);
+
+        let other = params(8).generate(1.).unwrap();
+        assert_ne!(first.positions, other.positions);
+    }
+}